@@ -0,0 +1,36 @@
+//! Compares running a program whose hot loop calls small functions with and without `-O2`,
+//! which inlines those calls.
+
+use std::{cell::RefCell, io, rc::Rc};
+
+use crafting_interpreters::prelude::*;
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+
+const PROGRAM: &str = "
+fun square(x) = x * x;
+fun add(a, b) = a + b;
+fun run() {
+  var total = 0;
+  for (var i = 0; i < 2000; i = i + 1) total = add(total, square(i));
+  return total;
+}
+run();
+";
+
+fn inline(c: &mut Criterion) {
+    let mut group = c.benchmark_group("inline");
+    for (name, opt_level) in [("O1", OptLevel::Constants), ("O2", OptLevel::Inline)] {
+        group.bench_function(BenchmarkId::from_parameter(name), |b| {
+            b.iter(|| {
+                let mut interpreter =
+                    Interpreter::new(Rc::new(RefCell::new(io::sink()))).with_opt_level(opt_level);
+                let (result, _) = run_timed(PROGRAM, &mut interpreter);
+                assert!(result.is_ok());
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, inline);
+criterion_main!(benches);
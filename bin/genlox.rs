@@ -0,0 +1,170 @@
+use std::{fmt::Write, fs, path::Path};
+
+use clap::Parser as ClapParser;
+
+/// Generates large Lox programs, along with their expected outputs, to stress the
+/// interpreter. Each program is written as `<name>.lox` and `<name>.output`, the layout used
+/// by `tests/scripts`.
+#[derive(ClapParser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// Directory the programs are written to
+    out_dir: String,
+
+    /// Multiplies the size of every generated program, at least 1
+    #[arg(
+        long,
+        default_value_t = 1,
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    scale: usize,
+}
+
+struct Program {
+    name: &'static str,
+    source: String,
+    output: String,
+}
+
+fn main() {
+    let args = Args::parse();
+    let out_dir = Path::new(&args.out_dir);
+    fs::create_dir_all(out_dir).expect("Failed to create output directory");
+
+    for program in [
+        functions(1000 * args.scale),
+        nesting(50 * args.scale),
+        else_if_chain(200 * args.scale),
+        call_chain(200 * args.scale),
+        property_chain(200 * args.scale),
+        recursion(100 * args.scale),
+    ] {
+        let path = out_dir.join(program.name);
+        fs::write(path.with_extension("lox"), program.source).expect("Failed to write program");
+        fs::write(path.with_extension("output"), program.output).expect("Failed to write output");
+    }
+}
+
+/// Identifiers can't contain digits, so numbers are spelled with letters instead.
+fn name(prefix: &str, mut n: usize) -> String {
+    let mut name = String::new();
+    loop {
+        name.insert(0, (b'a' + (n % 26) as u8) as char);
+        n /= 26;
+        if n == 0 {
+            break;
+        }
+    }
+    format!("{prefix}_{name}")
+}
+
+/// Thousands of top-level functions, all called from one expression.
+fn functions(count: usize) -> Program {
+    let mut source = String::new();
+    for i in 0..count {
+        writeln!(source, "fun {}() {{ return {i}; }}", name("f", i)).unwrap();
+    }
+    writeln!(source, "var total = 0;").unwrap();
+    for i in 0..count {
+        writeln!(source, "total = total + {}();", name("f", i)).unwrap();
+    }
+    writeln!(source, "print(total);").unwrap();
+
+    Program {
+        name: "stress_functions",
+        source,
+        output: format!("{}\n", count * (count - 1) / 2),
+    }
+}
+
+/// Deeply nested blocks, each shadowing the variable of the enclosing one and reading the
+/// variable of the outermost one.
+fn nesting(depth: usize) -> Program {
+    let mut source = String::from("var outer = \"outer\";\n");
+    for level in 0..depth {
+        writeln!(source, "{{ var v = {level};").unwrap();
+        writeln!(source, "if (v == {level}) {{").unwrap();
+    }
+    writeln!(source, "print(v);").unwrap();
+    writeln!(source, "print(outer);").unwrap();
+    for _ in 0..depth {
+        writeln!(source, "}} }}").unwrap();
+    }
+
+    Program {
+        name: "stress_nesting",
+        source,
+        output: format!("{}\nouter\n", depth - 1),
+    }
+}
+
+/// A long `else if` chain where only the last branch matches.
+fn else_if_chain(length: usize) -> Program {
+    let mut source = format!("var x = {};\n", length - 1);
+    for i in 0..length {
+        if i > 0 {
+            source.push_str("else ");
+        }
+        writeln!(source, "if (x == {i}) print({i});").unwrap();
+    }
+    source.push_str("else print(\"none\");\n");
+
+    Program {
+        name: "stress_else_if",
+        source,
+        output: format!("{}\n", length - 1),
+    }
+}
+
+/// A long chain of method calls on the same instance.
+fn call_chain(length: usize) -> Program {
+    let mut source = String::from(
+        "class Counter {\n  init() { this.count = 0; }\n  add(n) {\n    this.count = this.count + n;\n    return this;\n  }\n}\n",
+    );
+    source.push_str("print(Counter()");
+    for i in 0..length {
+        write!(source, ".add({i})").unwrap();
+    }
+    source.push_str(".count);\n");
+
+    Program {
+        name: "stress_call_chain",
+        source,
+        output: format!("{}\n", length * (length - 1) / 2),
+    }
+}
+
+/// A long chain of property reads, walking a linked list from its head to its tail.
+fn property_chain(length: usize) -> Program {
+    let mut source = String::from(
+        "class Node {\n  init(value, next) {\n    this.value = value;\n    this.next = next;\n  }\n}\n",
+    );
+    source.push_str("var list = nil;\n");
+    writeln!(
+        source,
+        "for (var i = 0; i < {length}; i = i + 1) list = Node(i, list);"
+    )
+    .unwrap();
+    source.push_str("print(list");
+    source.push_str(&".next".repeat(length - 1));
+    source.push_str(".value);\n");
+
+    Program {
+        name: "stress_property_chain",
+        source,
+        output: "0\n".to_string(),
+    }
+}
+
+/// Deep recursion through a user function.
+fn recursion(depth: usize) -> Program {
+    let source = format!(
+        "fun sum(n) {{\n  if (n == 0) return 0;\n  return n + sum(n - 1);\n}}\nprint(sum({depth}));\n"
+    );
+
+    Program {
+        name: "stress_recursion",
+        source,
+        output: format!("{}\n", depth * (depth + 1) / 2),
+    }
+}
@@ -1,97 +1,592 @@
 use std::{
     cell::RefCell,
-    fs::{self},
-    io::{self, Write},
+    env,
+    fs::{self, File},
+    io::{self, BufRead, BufWriter, IsTerminal, Write},
+    mem,
+    path::{Path, PathBuf},
+    process,
     rc::Rc,
 };
 
-use clap::Parser as ClapParser;
+use clap::{Parser as ClapParser, Subcommand};
 use crafting_interpreters::{
-    error::RuntimeException, interpreter::Interpreter, parser::Parser, resolver::Resolver,
-    scanner::Scanner, token::Token,
+    ast_printer::AstPrinter,
+    grade::grade,
+    prelude::*,
+    token::{Token, TokenIdentity},
 };
+use rustyline::{DefaultEditor, error::ReadlineError};
 
 #[derive(ClapParser, Debug)]
-#[command(version, about, long_about = None)]
+#[command(version, about, long_about = None, args_conflicts_with_subcommands = true)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// The script to run, or `-` to read it from stdin. Without it, starts a REPL, unless
+    /// stdin is piped, in which case the script is read from it
     file_path: Option<String>,
+
+    /// Read variables declared without an initializer as nil instead of raising an error
+    #[arg(long)]
+    nil_uninitialized: bool,
+
+    /// Parse the grammar of the book's Lox, where `print` takes no parentheses and names
+    /// like `break` aren't keywords, to run programs written for other implementations
+    #[arg(long)]
+    compat_lox: bool,
+
+    /// Match keywords regardless of case, so that `IF` and `If` both read as `if`
+    #[arg(long)]
+    case_insensitive_keywords: bool,
+
+    /// Read a name written with a leading backslash, such as `\class`, as an identifier even
+    /// when it's a keyword
+    #[arg(long)]
+    raw_identifiers: bool,
+
+    /// Print the time spent scanning, parsing, resolving and executing the file to stderr
+    #[arg(long, requires = "file_path")]
+    timings: bool,
+
+    /// Print the environments left once the file has run, as a Graphviz DOT graph, to stderr
+    #[arg(long, requires = "file_path")]
+    env_graph: bool,
+
+    /// Scan, parse and resolve the file without running it, reporting every error found and
+    /// exiting with 1 if there's any
+    #[arg(long, requires = "file_path", conflicts_with_all = ["tokens", "dump_ast"])]
+    check: bool,
+
+    /// Print the tokens scanned from the file, with their position and text, instead of
+    /// running it
+    #[arg(long, requires = "file_path", conflicts_with = "dump_ast")]
+    tokens: bool,
+
+    /// Print the program parsed from the file as s-expressions instead of running it
+    #[arg(long, requires = "file_path")]
+    dump_ast: bool,
+
+    /// Read the file as a program parsed to JSON, in the format of the `serde` feature, rather
+    /// than as Lox source, so that other tools can use the interpreter as a backend
+    #[cfg(feature = "serde")]
+    #[arg(long, requires = "file_path", conflicts_with_all = ["check", "tokens", "dump_ast"])]
+    from_ast: bool,
+
+    /// Run this code instead of a file, printing the value of its last expression, as in
+    /// `rlox -e '1 + 2'`, and exiting with 1 on an error
+    #[arg(short, long, value_name = "CODE", conflicts_with = "file_path")]
+    eval: Option<String>,
+
+    /// Write the output of the file to this file instead of stdout
+    #[arg(long, value_name = "FILE", requires = "file_path")]
+    out: Option<String>,
+
+    /// Write errors, warnings, timings and the environment graph to this file instead of
+    /// stdout and stderr
+    #[arg(long, value_name = "FILE", requires = "file_path")]
+    err: Option<String>,
+
+    /// Optimize the file: 0 runs it as written, 1 propagates constants and 2 also inlines
+    /// small functions (experimental)
+    #[arg(
+        short = 'O',
+        value_name = "LEVEL",
+        default_value_t = 1,
+        value_parser = clap::value_parser!(u8).range(0..=2),
+        requires = "file_path"
+    )]
+    opt_level: u8,
+
+    /// Arguments passed to the script, returned by its `args()`
+    #[arg(
+        trailing_var_arg = true,
+        allow_hyphen_values = true,
+        requires = "file_path"
+    )]
+    script_args: Vec<String>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Run a script and compare its output with an expected output file. Exits with 1 and
+    /// prints a diff when they differ
+    Grade { script: String, expected: String },
 }
 
 fn main() {
     let args = Args::parse();
-    if let Some(file_path) = args.file_path {
-        run_file(&file_path);
+    if let Some(Command::Grade { script, expected }) = &args.command {
+        grade_file(script, expected);
+    }
+    let uninitialized_read = if args.nil_uninitialized {
+        UninitializedRead::Nil
     } else {
-        run_prompt();
+        UninitializedRead::Error
+    };
+    let grammar = Grammar {
+        dialect: if args.compat_lox {
+            Dialect::Lox
+        } else {
+            Dialect::Rlox
+        },
+        case_insensitive_keywords: args.case_insensitive_keywords,
+        raw_identifiers: args.raw_identifiers,
+    };
+    if let Some(code) = &args.eval {
+        return eval(code, uninitialized_read, grammar);
+    }
+    let file_path = args
+        .file_path
+        .clone()
+        .or_else(|| (!io::stdin().is_terminal()).then(|| "-".to_owned()));
+    match &file_path {
+        Some(file_path) if args.check => check_file(file_path, grammar),
+        Some(file_path) if args.tokens => dump_tokens(file_path, grammar),
+        Some(file_path) if args.dump_ast => dump_ast(file_path, grammar),
+        Some(file_path) => run_file(file_path, uninitialized_read, grammar, &args),
+        None => run_prompt(uninitialized_read, grammar),
     }
 }
 
-fn run_file(path: &str) {
-    let writer = Rc::new(RefCell::new(io::stdout()));
-    let mut interpreter = Interpreter::new(writer);
-    let source = fs::read_to_string(path).expect("Failed to read file");
-    run(&source, &mut interpreter);
+/// How programs are read, as picked on the command line.
+#[derive(Clone, Copy)]
+struct Grammar {
+    dialect: Dialect,
+    case_insensitive_keywords: bool,
+    raw_identifiers: bool,
 }
 
-fn run_prompt() {
-    let writer = Rc::new(RefCell::new(io::stdout()));
-    let mut interpreter = Interpreter::new(writer.clone());
-    let mut resolver = Resolver::new(&mut interpreter);
-    loop {
-        write!(writer.borrow_mut(), "> ").unwrap();
-        std::io::stdout().flush().expect("Failed to flush stdout");
-        let mut input = String::new();
-        std::io::stdin()
-            .read_line(&mut input)
-            .expect("Failed to read line");
+impl Grammar {
+    fn scanner<'a>(&self, source: &'a str) -> Scanner<'a> {
+        Scanner::new(source)
+            .with_dialect(self.dialect)
+            .with_case_insensitive_keywords(self.case_insensitive_keywords)
+            .with_raw_identifiers(self.raw_identifiers)
+    }
 
-        let scanner = Scanner::new(&input);
-        let tokens: Vec<Token> = scanner.into_iter().collect();
-        let mut parser = Parser::new(tokens);
-        let statements = match parser.parse() {
-            Ok(stmts) => stmts,
-            Err(e) => {
-                writeln!(writer.borrow_mut(), "{e}").unwrap();
-                continue;
+    fn parser(&self, tokens: Vec<Token>) -> Parser {
+        Parser::new(tokens).with_dialect(self.dialect)
+    }
+
+    /// Has `interpreter` read the programs it runs, and the modules they import, this way.
+    fn apply(&self, interpreter: Interpreter) -> Interpreter {
+        interpreter
+            .with_dialect(self.dialect)
+            .with_case_insensitive_keywords(self.case_insensitive_keywords)
+            .with_raw_identifiers(self.raw_identifiers)
+    }
+}
+
+/// The source of the script at `path`, or of the one piped to stdin if it's `-`.
+fn read_source(path: &str) -> String {
+    if path == "-" {
+        io::read_to_string(io::stdin()).expect("Failed to read stdin")
+    } else {
+        fs::read_to_string(path).expect("Failed to read file")
+    }
+}
+
+fn run_file(path: &str, uninitialized_read: UninitializedRead, grammar: Grammar, args: &Args) {
+    let out: Box<dyn Write> = match &args.out {
+        Some(out) => Box::new(File::create(out).expect("Failed to create output file")),
+        None => Box::new(io::stdout()),
+    };
+    let writer = Rc::new(RefCell::new(BufWriter::new(out)));
+    let opt_level = match args.opt_level {
+        0 => OptLevel::None,
+        1 => OptLevel::Constants,
+        _ => OptLevel::Inline,
+    };
+    // Without `--err`, errors follow the output they interrupt and the rest goes to stderr.
+    let mut err = args
+        .err
+        .as_ref()
+        .map(|err| File::create(err).expect("Failed to create error file"));
+    let mut interpreter = grammar
+        .apply(Interpreter::new(writer))
+        .with_uninitialized_read(uninitialized_read)
+        .with_opt_level(opt_level)
+        .with_script_args(args.script_args.clone())
+        .with_module_dir(Path::new(path).parent().unwrap_or(Path::new(".")));
+    if let Some(err) = &err {
+        let diagnostics = err.try_clone().expect("Failed to open error file");
+        interpreter = interpreter.with_diagnostics(Rc::new(RefCell::new(diagnostics)));
+    }
+    let source = read_source(path);
+    let (result, timings) = run_source(&source, &mut interpreter, args);
+
+    let mut status = 0;
+    match result {
+        Ok(_) => {}
+        Err(LoxError::Runtime(RuntimeException::Break | RuntimeException::Continue)) => {
+            todo!("Why hit this?")
+        }
+        Err(LoxError::Runtime(RuntimeException::Exit(code))) => status = code,
+        Err(e) => {
+            let mut writer = interpreter.writer.borrow_mut();
+            // The output may be what failed, such as when it's piped to a command that already
+            // exited, so the error goes to stderr when it can't be written after it.
+            let reported = match &mut err {
+                Some(err) => writeln!(err, "{e}"),
+                None => writeln!(writer, "{e}"),
+            };
+            if reported.and_then(|_| writer.flush()).is_err() {
+                eprintln!("{e}");
             }
-        };
-        if let Err(e) = resolver.resolve_stmts(&statements) {
-            writeln!(writer.borrow_mut(), "{e}").unwrap();
-            continue;
         }
-        if let Err(e) = resolver.interpreter.interpret(&statements) {
-            writeln!(writer.borrow_mut(), "{e}").unwrap();
-            continue;
+    }
+    let mut diagnostics: Box<dyn Write> = match err {
+        Some(err) => Box::new(err),
+        None => Box::new(io::stderr()),
+    };
+    if args.timings {
+        writeln!(diagnostics, "{timings}").unwrap();
+    }
+    if args.env_graph {
+        write!(diagnostics, "{}", interpreter.env_graph()).unwrap();
+    }
+    if status != 0 {
+        // Exiting skips destructors, so flush what the script printed first.
+        let _ = interpreter.writer.borrow_mut().flush();
+        process::exit(status);
+    }
+}
+
+/// Runs `source` as Lox, or as a program parsed to JSON with `--from-ast`.
+#[cfg(feature = "serde")]
+fn run_source(
+    source: &str,
+    interpreter: &mut Interpreter,
+    args: &Args,
+) -> (Result<Object, LoxError>, Timings) {
+    if !args.from_ast {
+        return run_timed(source, interpreter);
+    }
+    match serde_json::from_str(source) {
+        Ok(statements) => run_parsed(statements, interpreter),
+        Err(e) => {
+            eprintln!("Invalid AST: {e}");
+            process::exit(1);
+        }
+    }
+}
+
+#[cfg(not(feature = "serde"))]
+fn run_source(
+    source: &str,
+    interpreter: &mut Interpreter,
+    _args: &Args,
+) -> (Result<Object, LoxError>, Timings) {
+    run_timed(source, interpreter)
+}
+
+fn check_file(path: &str, grammar: Grammar) {
+    let source = read_source(path);
+    let tokens = grammar.scanner(&source).collect();
+    // Resolving a program that didn't parse would report errors caused by the missing parts.
+    let errors = match grammar.parser(tokens).parse_all() {
+        Ok(statements) => {
+            let mut interpreter = grammar
+                .apply(Interpreter::new(Rc::new(RefCell::new(io::sink()))))
+                .with_module_dir(Path::new(path).parent().unwrap_or(Path::new(".")));
+            let resolved = Resolver::new(&mut interpreter).resolve_all(&statements);
+            resolved
+                .err()
+                .unwrap_or_default()
+                .into_iter()
+                .map(LoxError::Resolving)
+                .collect()
+        }
+        Err(errors) => errors
+            .into_iter()
+            .map(LoxError::Parsing)
+            .collect::<Vec<_>>(),
+    };
+    for error in &errors {
+        eprintln!("{error}");
+    }
+    if !errors.is_empty() {
+        process::exit(1);
+    }
+}
+
+fn dump_tokens(path: &str, grammar: Grammar) {
+    let source = read_source(path);
+    // Text the scanner can't read shows up as an `Error` token among the others.
+    for token in grammar.scanner(&source) {
+        println!(
+            "{}:{} {:?} {:?}",
+            token.line,
+            token.column,
+            token.id,
+            token.span.text(&source)
+        );
+    }
+}
+
+fn dump_ast(path: &str, grammar: Grammar) {
+    let source = read_source(path);
+    let tokens = grammar.scanner(&source).collect();
+    match grammar.parser(tokens).parse() {
+        Ok(statements) => print!("{}", AstPrinter::new().print(&statements)),
+        Err(e) => {
+            eprintln!("{e}");
+            process::exit(1);
         }
     }
 }
 
-fn run(source: &str, interpreter: &mut Interpreter) {
-    let scanner = Scanner::new(source);
-    let tokens = scanner.into_iter().collect::<Vec<Token>>();
-    let mut parser = Parser::new(tokens);
-    let statements = match parser.parse() {
-        Ok(stmts) => stmts,
+fn eval(code: &str, uninitialized_read: UninitializedRead, grammar: Grammar) {
+    let writer = Rc::new(RefCell::new(io::stdout()));
+    let mut lox = Lox::from(
+        grammar
+            .apply(Interpreter::new(writer.clone()))
+            .with_uninitialized_read(uninitialized_read),
+    );
+    // Like in the REPL, the last expression can leave out its `;`, and its value is shown.
+    match lox.run_input(code, 1) {
+        Ok(Some(value)) => writeln!(writer.borrow_mut(), "{value}").unwrap(),
+        Ok(None) => {}
+        Err(LoxError::Runtime(RuntimeException::Exit(status))) => process::exit(status),
+        Err(e) => exit_with_error(e),
+    }
+}
+
+fn exit_with_error(error: LoxError) -> ! {
+    eprintln!("{error}");
+    process::exit(1)
+}
+
+fn grade_file(script: &str, expected: &str) -> ! {
+    match grade(Path::new(script), Path::new(expected)) {
+        Ok(grade) if grade.passed() => {
+            println!("PASS {script}");
+            process::exit(0)
+        }
+        Ok(grade) => {
+            println!("FAIL {script}");
+            print!("{}", grade.diff());
+            process::exit(1)
+        }
         Err(e) => {
-            writeln!(interpreter.writer.borrow_mut(), "{e}").unwrap();
+            eprintln!("Failed to grade {script}: {e}");
+            process::exit(2)
+        }
+    }
+}
+
+/// The inputs of a REPL session. Their lines are numbered on from one input to the next, so
+/// that an error can tell which input it comes from, even when it's raised by a function
+/// declared in an earlier one.
+#[derive(Default)]
+struct History {
+    inputs: Vec<String>,
+    /// The number of the first line of each input.
+    first_lines: Vec<usize>,
+}
+
+impl History {
+    /// Records `input`, returning the number of its first line.
+    fn push(&mut self, input: String) -> usize {
+        let first_line = match (self.inputs.last(), self.first_lines.last()) {
+            (Some(last), Some(first_line)) => first_line + last.lines().count().max(1),
+            _ => 1,
+        };
+        self.inputs.push(input);
+        self.first_lines.push(first_line);
+        first_line
+    }
+
+    /// The index of the input holding `line`, and the line's number within it.
+    fn find(&self, line: usize) -> (usize, usize) {
+        let index = self
+            .first_lines
+            .partition_point(|first_line| *first_line <= line)
+            .saturating_sub(1);
+        (index, line + 1 - self.first_lines.get(index).unwrap_or(&1))
+    }
+
+    fn label(&self, line: usize) -> String {
+        let (index, line) = self.find(line);
+        format!("input {}, line {line}", index + 1)
+    }
+
+    /// Prints `error`, followed by the line it points at with a caret under its column.
+    fn report(&self, writer: &mut impl Write, error: &LoxError) {
+        writeln!(writer, "{}", error.labelled(&|line| self.label(line))).unwrap();
+        let Some(token) = error.token() else {
             return;
+        };
+        let (index, line) = self.find(token.line);
+        if let Some(text) = self
+            .inputs
+            .get(index)
+            .and_then(|input| input.lines().nth(line - 1))
+        {
+            writeln!(writer, "    {text}").unwrap();
+            writeln!(
+                writer,
+                "    {}^",
+                " ".repeat(token.column.saturating_sub(1))
+            )
+            .unwrap();
         }
-    };
-    let mut resolver = Resolver::new(interpreter);
-    if let Err(e) = resolver.resolve_stmts(&statements) {
-        writeln!(interpreter.writer.borrow_mut(), "{e}").unwrap();
-        return;
     }
-    match interpreter.interpret(&statements) {
-        Ok(_) => {}
-        Err(e) => match e {
-            RuntimeException::Error(runtime_error) => {
-                writeln!(interpreter.writer.borrow_mut(), "{runtime_error}").unwrap();
-            }
-            RuntimeException::Return(runtime_return) => {
-                writeln!(interpreter.writer.borrow_mut(), "{runtime_return}").unwrap();
+}
+
+/// A line read by the REPL.
+enum Line {
+    /// The text of the line, ending with a newline.
+    Text(String),
+    /// Ctrl-C was pressed, dropping the input typed so far.
+    Interrupted,
+    /// The end of the input.
+    End,
+}
+
+/// Where the REPL reads its lines from.
+trait LineReader {
+    /// Shows `prompt` and reads the next line.
+    fn read_line(&mut self, prompt: &str) -> Line;
+
+    /// Records an input of one or more lines once it's complete.
+    fn add_history(&mut self, _input: &str) {}
+}
+
+/// Reads lines as they come, when stdin isn't a terminal or the editor can't be set up.
+/// They're read through the interpreter, so that lines buffered for a script's `readLine`
+/// aren't lost.
+struct PlainReader {
+    reader: Rc<RefCell<dyn BufRead>>,
+}
+
+impl LineReader for PlainReader {
+    fn read_line(&mut self, prompt: &str) -> Line {
+        print!("{prompt}");
+        io::stdout().flush().expect("Failed to flush stdout");
+        let mut line = String::new();
+        let read = self
+            .reader
+            .borrow_mut()
+            .read_line(&mut line)
+            .expect("Failed to read line");
+        if read == 0 {
+            Line::End
+        } else {
+            Line::Text(line)
+        }
+    }
+}
+
+/// Reads lines from the terminal with a line editor: the arrow keys move along the line and
+/// through the history, and the usual shortcuts such as Ctrl-A and Ctrl-E work. The history
+/// is kept between sessions in `~/.rlox_history`.
+struct EditorReader {
+    editor: DefaultEditor,
+    history_path: Option<PathBuf>,
+}
+
+impl EditorReader {
+    fn new() -> rustyline::Result<Self> {
+        let mut editor = DefaultEditor::new()?;
+        let history_path = env::home_dir().map(|home| home.join(".rlox_history"));
+        if let Some(path) = &history_path {
+            // There's no history yet on the first run.
+            let _ = editor.load_history(path);
+        }
+        Ok(EditorReader {
+            editor,
+            history_path,
+        })
+    }
+}
+
+impl LineReader for EditorReader {
+    fn read_line(&mut self, prompt: &str) -> Line {
+        match self.editor.readline(prompt) {
+            Ok(line) => Line::Text(line + "\n"),
+            Err(ReadlineError::Interrupted) => Line::Interrupted,
+            Err(ReadlineError::Eof) => Line::End,
+            Err(e) => panic!("Failed to read line: {e}"),
+        }
+    }
+
+    fn add_history(&mut self, input: &str) {
+        let input = input.trim_end();
+        if input.is_empty() || self.editor.add_history_entry(input).is_err() {
+            return;
+        }
+        // Saved after each input, since `exit()` ends the process without unwinding.
+        if let Some(path) = &self.history_path
+            && let Err(e) = self.editor.save_history(path)
+        {
+            eprintln!("Failed to save the history to {}: {e}", path.display());
+            self.history_path = None;
+        }
+    }
+}
+
+/// The line editor when stdin is a terminal, or else plain lines read through `interpreter`.
+fn line_reader(interpreter: &Interpreter) -> Box<dyn LineReader> {
+    if io::stdin().is_terminal() {
+        match EditorReader::new() {
+            Ok(editor) => return Box::new(editor),
+            Err(e) => eprintln!("Failed to set up line editing: {e}"),
+        }
+    }
+    Box::new(PlainReader {
+        reader: interpreter.reader.clone(),
+    })
+}
+
+/// The error `input` fails to parse with when it only stops too soon, such as inside a string
+/// or in `var x = 1` without its semicolon, so that more lines could complete it.
+fn ends_early(input: &str, grammar: Grammar) -> Option<ParsingError> {
+    let tokens = grammar.scanner(input).collect();
+    let mut parser = grammar.parser(tokens).with_optional_final_semicolon(true);
+    parser.parse().err().filter(ParsingError::is_at_end)
+}
+
+fn run_prompt(uninitialized_read: UninitializedRead, grammar: Grammar) {
+    let writer = Rc::new(RefCell::new(io::stdout()));
+    let mut lox = Lox::from(
+        grammar
+            .apply(Interpreter::new(writer.clone()))
+            .with_uninitialized_read(uninitialized_read),
+    );
+    let mut lines = line_reader(lox.interpreter());
+    let mut history = History::default();
+    let mut input = String::new();
+    loop {
+        let prompt = if input.is_empty() { "> " } else { "... " };
+        let line = match lines.read_line(prompt) {
+            Line::Text(line) => line,
+            Line::Interrupted => {
+                input.clear();
+                continue;
             }
-            RuntimeException::Break | RuntimeException::Continue => todo!("Why hit this?"),
-        },
+            Line::End => break,
+        };
+        input.push_str(&line);
+        // An empty line ends an incomplete input, to see what's wrong with it, unless it's
+        // part of a string.
+        if let Some(error) = ends_early(&input, grammar)
+            && (error.token().id == TokenIdentity::Error || !line.trim().is_empty())
+        {
+            continue;
+        }
+        let input = mem::take(&mut input);
+        lines.add_history(&input);
+
+        let first_line = history.push(input.clone());
+        match lox.run_input(&input, first_line) {
+            // Like other REPLs, show the value of an expression, but not of a declaration.
+            Ok(Some(value)) => writeln!(writer.borrow_mut(), "{value}").unwrap(),
+            Ok(None) => {}
+            Err(LoxError::Runtime(RuntimeException::Exit(status))) => process::exit(status),
+            Err(e) => history.report(&mut *writer.borrow_mut(), &e),
+        }
     }
 }
@@ -0,0 +1,124 @@
+//! The macro the syntax tree is declared with.
+
+/// Declares the nodes of a syntax tree from a spec: a struct for each node with a `new`
+/// constructor taking its fields in order, the enum of all the nodes and the visitor trait
+/// dispatching on it. Nodes listed under `boxed` are boxed in the enum, to keep it small, and
+/// those under `shared` are reference counted, so the values made from them at runtime, like
+/// closures, can hold on to the node without copying it. Their visit methods get the `Rc`.
+///
+/// ```ignore
+/// ast! {
+///     /// A node of the tree.
+///     enum Node, visited by NodeVisitor with node;
+///     boxed {
+///         Pair(PairNode) => visit_pair_node { left: Node, right: Node },
+///     }
+///     shared {
+///         Tree(TreeNode) => visit_tree_node { root: Node },
+///     }
+///     inline {
+///         Leaf(LeafNode) => visit_leaf_node { token: Token },
+///     }
+/// }
+/// ```
+///
+/// With the `serde` feature, the nodes and the enum derive `Serialize` and `Deserialize`.
+///
+/// Anything else a node needs, such as a `Drop` or helper methods, goes in its own `impl`
+/// next to the spec.
+macro_rules! ast {
+    (
+        $(#[$enum_meta:meta])*
+        enum $enum:ident, visited by $visitor:ident with $arg:ident;
+        boxed {
+            $(
+                $(#[$boxed_meta:meta])*
+                $boxed:ident($boxed_node:ident) => $boxed_visit:ident {
+                    $($(#[$boxed_field_meta:meta])* $boxed_field:ident: $boxed_type:ty),* $(,)?
+                }
+            ),* $(,)?
+        }
+        shared {
+            $(
+                $(#[$shared_meta:meta])*
+                $shared:ident($shared_node:ident) => $shared_visit:ident {
+                    $($(#[$shared_field_meta:meta])* $shared_field:ident: $shared_type:ty),* $(,)?
+                }
+            ),* $(,)?
+        }
+        inline {
+            $(
+                $(#[$inline_meta:meta])*
+                $inline:ident($inline_node:ident) => $inline_visit:ident {
+                    $($(#[$inline_field_meta:meta])* $inline_field:ident: $inline_type:ty),* $(,)?
+                }
+            ),* $(,)?
+        }
+    ) => {
+        pub trait $visitor {
+            type Output;
+
+            $(fn $boxed_visit(&mut self, $arg: &$boxed_node) -> Self::Output;)*
+            $(fn $shared_visit(
+                &mut self,
+                $arg: &::std::rc::Rc<$shared_node>,
+            ) -> Self::Output;)*
+            $(fn $inline_visit(&mut self, $arg: &$inline_node) -> Self::Output;)*
+
+            fn accept(&mut self, $arg: &$enum) -> Self::Output {
+                match $arg {
+                    $($enum::$boxed($arg) => self.$boxed_visit($arg),)*
+                    $($enum::$shared($arg) => self.$shared_visit($arg),)*
+                    $($enum::$inline($arg) => self.$inline_visit($arg),)*
+                }
+            }
+        }
+
+        $(#[$enum_meta])*
+        #[derive(Clone, Debug)]
+        #[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+        pub enum $enum {
+            $($boxed(Box<$boxed_node>),)*
+            $($shared(::std::rc::Rc<$shared_node>),)*
+            $($inline($inline_node),)*
+        }
+
+        $(
+            $crate::ast::ast!(@node
+                $(#[$boxed_meta])*
+                $boxed_node { $($(#[$boxed_field_meta])* $boxed_field: $boxed_type),* }
+            );
+        )*
+        $(
+            $crate::ast::ast!(@node
+                $(#[$shared_meta])*
+                $shared_node { $($(#[$shared_field_meta])* $shared_field: $shared_type),* }
+            );
+        )*
+        $(
+            $crate::ast::ast!(@node
+                $(#[$inline_meta])*
+                $inline_node { $($(#[$inline_field_meta])* $inline_field: $inline_type),* }
+            );
+        )*
+    };
+    (@node
+        $(#[$meta:meta])*
+        $node:ident { $($(#[$field_meta:meta])* $field:ident: $type:ty),* }
+    ) => {
+        $(#[$meta])*
+        #[derive(Clone, Debug)]
+        #[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+        pub struct $node {
+            $($(#[$field_meta])* pub $field: $type,)*
+        }
+
+        impl $node {
+            pub fn new($($field: $type),*) -> Self {
+                Self { $($field),* }
+            }
+        }
+    };
+}
+
+pub(crate) use ast;
@@ -0,0 +1,442 @@
+//! Prints programs as s-expressions, the way the parser understood them, for `rlox
+//! --dump-ast`. Each node is written as its operator or keyword followed by its children, as
+//! in `(; (+ 1 (* 2 3)))`, so precedence and the parser's desugaring show up as nesting.
+
+use std::rc::Rc;
+
+use crate::{
+    expr::{
+        AssignExpr, BinaryExpr, CallExpr, DestructureExpr, Expr, ExprVisitor, GetExpr,
+        GroupingExpr, IndexExpr, IndexSetExpr, LambdaExpr, ListExpr, LiteralExpr, LogicalExpr,
+        MapExpr, Pattern, SetExpr, SliceExpr, SuperExpr, TernaryExpr, ThisExpr, UnaryExpr,
+        VariableExpr,
+    },
+    function::FunctionType,
+    object::Object,
+    stmt::{
+        BlockStmt, BreakStmt, ClassStmt, ContinueStmt, DestructureStmt, ExportStmt, ExpressionStmt,
+        ForInStmt, ForStmt, FunctionStmt, IfStmt, ImportStmt, PrintStmt, ReturnStmt, Stmt,
+        StmtVisitor, SwitchStmt, ThrowStmt, TryStmt, VarStmt, WhileStmt,
+    },
+    token::Token,
+};
+
+/// Written in place of the optional parts of a node that are left out, such as the
+/// initializer of `for (; i < 3;)`.
+const MISSING: &str = "_";
+
+#[derive(Default)]
+pub struct AstPrinter;
+
+impl AstPrinter {
+    pub fn new() -> Self {
+        AstPrinter
+    }
+
+    /// Prints `statements`, one per line.
+    pub fn print(&mut self, statements: &[Stmt]) -> String {
+        statements
+            .iter()
+            .map(|stmt| format!("{}\n", StmtVisitor::accept(self, stmt)))
+            .collect()
+    }
+
+    /// `(name parts...)`, with a space between the parts.
+    fn parenthesize(&self, name: &str, parts: impl IntoIterator<Item = String>) -> String {
+        let mut text = format!("({name}");
+        for part in parts {
+            text.push(' ');
+            text.push_str(&part);
+        }
+        text.push(')');
+        text
+    }
+
+    fn expr(&mut self, expr: &Expr) -> String {
+        ExprVisitor::accept(self, expr)
+    }
+
+    fn optional_expr(&mut self, expr: &Option<Expr>) -> String {
+        match expr {
+            Some(expr) => self.expr(expr),
+            None => MISSING.to_string(),
+        }
+    }
+
+    fn exprs(&mut self, exprs: &[Expr]) -> Vec<String> {
+        exprs.iter().map(|expr| self.expr(expr)).collect()
+    }
+
+    fn block(&mut self, block: &BlockStmt) -> String {
+        self.visit_block_stmt(block)
+    }
+
+    /// The parameter list of a function, as in `(a b ...rest)`.
+    fn params(&self, params: &[Token], variadic: bool) -> String {
+        let names = params.iter().enumerate().map(|(index, param)| {
+            if variadic && index + 1 == params.len() {
+                format!("...{param}")
+            } else {
+                param.to_string()
+            }
+        });
+        format!("({})", names.collect::<Vec<_>>().join(" "))
+    }
+
+    /// The parts of a function after its keyword: its name, if it has one, its parameters and
+    /// the statements of its body.
+    fn function(
+        &mut self,
+        name: Option<&Token>,
+        params: &[Token],
+        variadic: bool,
+        body: &BlockStmt,
+    ) -> Vec<String> {
+        let mut parts = Vec::new();
+        parts.extend(name.map(Token::to_string));
+        parts.push(self.params(params, variadic));
+        parts.extend(
+            body.statements
+                .iter()
+                .map(|stmt| StmtVisitor::accept(self, stmt)),
+        );
+        parts
+    }
+
+    fn pattern(&self, pattern: &Pattern) -> String {
+        let (open, close, names) = match pattern {
+            Pattern::List { names, .. } => ('[', ']', names),
+            Pattern::Fields { names, .. } => ('{', '}', names),
+        };
+        let names = names.iter().map(Token::to_string).collect::<Vec<_>>();
+        format!("{open}{}{close}", names.join(" "))
+    }
+}
+
+impl ExprVisitor for AstPrinter {
+    type Output = String;
+
+    fn visit_assign_expr(&mut self, expr: &AssignExpr) -> String {
+        let value = self.expr(&expr.value);
+        self.parenthesize("=", [expr.name.to_string(), value])
+    }
+
+    fn visit_binary_expr(&mut self, expr: &BinaryExpr) -> String {
+        let parts = [self.expr(&expr.left), self.expr(&expr.right)];
+        self.parenthesize(&expr.operator.to_string(), parts)
+    }
+
+    fn visit_call_expr(&mut self, expr: &CallExpr) -> String {
+        let mut parts = vec![self.expr(&expr.callee)];
+        parts.extend(self.exprs(&expr.arguments));
+        self.parenthesize("call", parts)
+    }
+
+    fn visit_destructure_expr(&mut self, expr: &DestructureExpr) -> String {
+        let parts = [self.pattern(&expr.pattern), self.expr(&expr.value)];
+        self.parenthesize("=", parts)
+    }
+
+    fn visit_get_expr(&mut self, expr: &GetExpr) -> String {
+        let object = self.expr(&expr.object);
+        self.parenthesize(".", [object, expr.name.to_string()])
+    }
+
+    fn visit_grouping_expr(&mut self, expr: &GroupingExpr) -> String {
+        let expression = self.expr(&expr.expression);
+        self.parenthesize("group", [expression])
+    }
+
+    fn visit_index_expr(&mut self, expr: &IndexExpr) -> String {
+        let parts = [self.expr(&expr.object), self.expr(&expr.index)];
+        self.parenthesize("[]", parts)
+    }
+
+    fn visit_index_set_expr(&mut self, expr: &IndexSetExpr) -> String {
+        let parts = [
+            self.expr(&expr.object),
+            self.expr(&expr.index),
+            self.expr(&expr.value),
+        ];
+        self.parenthesize("[]=", parts)
+    }
+
+    fn visit_list_expr(&mut self, expr: &ListExpr) -> String {
+        let elements = self.exprs(&expr.elements);
+        self.parenthesize("list", elements)
+    }
+
+    fn visit_logical_expr(&mut self, expr: &LogicalExpr) -> String {
+        let parts = [self.expr(&expr.left), self.expr(&expr.right)];
+        self.parenthesize(&expr.operator.to_string(), parts)
+    }
+
+    fn visit_map_expr(&mut self, expr: &MapExpr) -> String {
+        let entries = expr
+            .entries
+            .iter()
+            .map(|(key, value)| format!("({} {})", self.expr(key), self.expr(value)))
+            .collect::<Vec<_>>();
+        self.parenthesize("map", entries)
+    }
+
+    fn visit_set_expr(&mut self, expr: &SetExpr) -> String {
+        let object = self.expr(&expr.object);
+        let value = self.expr(&expr.value);
+        self.parenthesize(".=", [object, expr.name.to_string(), value])
+    }
+
+    fn visit_slice_expr(&mut self, expr: &SliceExpr) -> String {
+        let parts = [
+            self.expr(&expr.object),
+            self.optional_expr(&expr.start),
+            self.optional_expr(&expr.end),
+        ];
+        self.parenthesize("slice", parts)
+    }
+
+    fn visit_ternary_expr(&mut self, expr: &TernaryExpr) -> String {
+        let parts = [
+            self.expr(&expr.condition),
+            self.expr(&expr.then_branch),
+            self.expr(&expr.else_branch),
+        ];
+        self.parenthesize("?:", parts)
+    }
+
+    fn visit_unary_expr(&mut self, expr: &UnaryExpr) -> String {
+        let right = self.expr(&expr.right);
+        self.parenthesize(&expr.operator.to_string(), [right])
+    }
+
+    fn visit_lambda_expr(&mut self, expr: &Rc<LambdaExpr>) -> String {
+        let parts = self.function(None, &expr.params, expr.variadic, &expr.body);
+        self.parenthesize("fun", parts)
+    }
+
+    fn visit_literal_expr(&mut self, expr: &LiteralExpr) -> String {
+        match &expr.value {
+            Object::String(string) => format!("{:?}", string.as_str()),
+            value => value.to_string(),
+        }
+    }
+
+    fn visit_super_expr(&mut self, expr: &SuperExpr) -> String {
+        self.parenthesize("super", [expr.method.to_string()])
+    }
+
+    fn visit_this_expr(&mut self, _expr: &ThisExpr) -> String {
+        "this".to_string()
+    }
+
+    fn visit_variable_expr(&mut self, expr: &VariableExpr) -> String {
+        expr.name.to_string()
+    }
+}
+
+impl StmtVisitor for AstPrinter {
+    type Output = String;
+
+    fn visit_function_stmt(&mut self, stmt: &Rc<FunctionStmt>) -> String {
+        let parts = self.function(Some(&stmt.name), &stmt.params, stmt.variadic, &stmt.body);
+        let function = self.parenthesize("fun", parts);
+        match stmt.kind {
+            FunctionType::StaticMethod => self.parenthesize("static", [function]),
+            FunctionType::GetterMethod if stmt.memo => self.parenthesize("memo", [function]),
+            FunctionType::GetterMethod => self.parenthesize("getter", [function]),
+            _ => function,
+        }
+    }
+
+    fn visit_block_stmt(&mut self, stmt: &BlockStmt) -> String {
+        let statements = stmt
+            .statements
+            .iter()
+            .map(|stmt| StmtVisitor::accept(self, stmt))
+            .collect::<Vec<_>>();
+        self.parenthesize("block", statements)
+    }
+
+    fn visit_break_stmt(&mut self, _stmt: &BreakStmt) -> String {
+        "(break)".to_string()
+    }
+
+    fn visit_class_stmt(&mut self, stmt: &ClassStmt) -> String {
+        let mut parts = vec![stmt.name.to_string()];
+        if let Some(superclass) = &stmt.superclass {
+            parts.push(self.parenthesize("<", [superclass.name.to_string()]));
+        }
+        parts.extend(stmt.fields.iter().map(|field| self.visit_var_stmt(field)));
+        for method in stmt
+            .methods
+            .iter()
+            .chain(&stmt.static_methods)
+            .chain(&stmt.getter_methods)
+        {
+            parts.push(self.visit_function_stmt(method));
+        }
+        parts.extend(stmt.static_blocks.iter().map(|block| {
+            let block = self.block(block);
+            self.parenthesize("class", [block])
+        }));
+        self.parenthesize("class", parts)
+    }
+
+    fn visit_continue_stmt(&mut self, _stmt: &ContinueStmt) -> String {
+        "(continue)".to_string()
+    }
+
+    fn visit_destructure_stmt(&mut self, stmt: &DestructureStmt) -> String {
+        let keyword = if stmt.is_const { "const" } else { "var" };
+        let parts = [self.pattern(&stmt.pattern), self.expr(&stmt.initializer)];
+        self.parenthesize(keyword, parts)
+    }
+
+    fn visit_export_stmt(&mut self, stmt: &ExportStmt) -> String {
+        let declaration = StmtVisitor::accept(self, &stmt.declaration);
+        self.parenthesize("export", [declaration])
+    }
+
+    fn visit_expression_stmt(&mut self, stmt: &ExpressionStmt) -> String {
+        let expr = self.expr(&stmt.expr);
+        self.parenthesize(";", [expr])
+    }
+
+    fn visit_for_stmt(&mut self, stmt: &ForStmt) -> String {
+        let initializer = match &stmt.initializer {
+            Some(initializer) => StmtVisitor::accept(self, initializer),
+            None => MISSING.to_string(),
+        };
+        let parts = [
+            initializer,
+            self.expr(&stmt.condition),
+            self.optional_expr(&stmt.increment),
+            self.block(&stmt.body),
+        ];
+        self.parenthesize("for", parts)
+    }
+
+    fn visit_for_in_stmt(&mut self, stmt: &ForInStmt) -> String {
+        let parts = [
+            stmt.name.to_string(),
+            self.expr(&stmt.iterable),
+            self.block(&stmt.body),
+        ];
+        self.parenthesize("for-in", parts)
+    }
+
+    fn visit_if_stmt(&mut self, stmt: &IfStmt) -> String {
+        let mut parts = vec![self.expr(&stmt.condition), self.block(&stmt.then_branch)];
+        if let Some(else_branch) = &stmt.else_branch {
+            parts.push(StmtVisitor::accept(self, else_branch));
+        }
+        self.parenthesize("if", parts)
+    }
+
+    fn visit_import_stmt(&mut self, stmt: &ImportStmt) -> String {
+        let path = format!("{:?}", stmt.path.value.to_string());
+        self.parenthesize("import", [path, stmt.name.to_string()])
+    }
+
+    fn visit_print_stmt(&mut self, stmt: &PrintStmt) -> String {
+        let expr = self.expr(&stmt.expr);
+        self.parenthesize("print", [expr])
+    }
+
+    fn visit_return_stmt(&mut self, stmt: &ReturnStmt) -> String {
+        let value = stmt.value.as_ref().map(|value| self.expr(value));
+        self.parenthesize("return", value)
+    }
+
+    fn visit_switch_stmt(&mut self, stmt: &SwitchStmt) -> String {
+        let mut parts = vec![self.expr(&stmt.subject)];
+        for (values, body) in &stmt.cases {
+            let values = format!("({})", self.exprs(values).join(" "));
+            let body = self.block(body);
+            parts.push(self.parenthesize("case", [values, body]));
+        }
+        if let Some(default) = &stmt.default {
+            let body = self.block(default);
+            parts.push(self.parenthesize("default", [body]));
+        }
+        self.parenthesize("switch", parts)
+    }
+
+    fn visit_throw_stmt(&mut self, stmt: &ThrowStmt) -> String {
+        let value = self.expr(&stmt.value);
+        self.parenthesize("throw", [value])
+    }
+
+    fn visit_try_stmt(&mut self, stmt: &TryStmt) -> String {
+        let mut parts = vec![self.block(&stmt.body)];
+        if let Some((name, body)) = &stmt.catch {
+            let mut catch = vec![name.to_string()];
+            catch.extend(stmt.kinds.iter().map(|kind| kind.to_string()));
+            catch.push(self.block(body));
+            parts.push(self.parenthesize("catch", catch));
+        }
+        if let Some(finally) = &stmt.finally {
+            let body = self.block(finally);
+            parts.push(self.parenthesize("finally", [body]));
+        }
+        self.parenthesize("try", parts)
+    }
+
+    fn visit_var_stmt(&mut self, stmt: &VarStmt) -> String {
+        let keyword = if stmt.is_const { "const" } else { "var" };
+        let mut parts = vec![stmt.name.to_string()];
+        if let Some(initializer) = &stmt.initializer {
+            parts.push(self.expr(initializer));
+        }
+        self.parenthesize(keyword, parts)
+    }
+
+    fn visit_while_stmt(&mut self, stmt: &WhileStmt) -> String {
+        let parts = [self.expr(&stmt.condition), self.block(&stmt.body)];
+        self.parenthesize("while", parts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parser::Parser, scanner::Scanner};
+
+    fn dump(source: &str) -> String {
+        let statements = Parser::new(Scanner::new(source).collect()).parse().unwrap();
+        AstPrinter::new().print(&statements)
+    }
+
+    #[test]
+    fn test_print() {
+        assert_eq!(dump("1 + 2 * 3;"), "(; (+ 1 (* 2 3)))\n");
+        assert_eq!(dump("(1 + 2) * -3;"), "(; (* (group (+ 1 2)) (- 3)))\n");
+        assert_eq!(dump("a = b or c and d;"), "(; (= a (or b (and c d))))\n");
+        assert_eq!(dump("a ? b : c or d;"), "(; (?: a b (or c d)))\n");
+        assert_eq!(
+            dump("var s = \"a\nb\"; print(s);"),
+            "(var s \"a\\nb\")\n(print s)\n"
+        );
+        assert_eq!(
+            dump("for (var i = 0; i < 3;) print(i);"),
+            "(for (var i 0) (< i 3) _ (block (print i)))\n"
+        );
+        assert_eq!(
+            dump("if (a) x(); else if (b) {} else y();"),
+            "(if a (block (; (call x))) (if b (block) (block (; (call y)))))\n"
+        );
+        assert_eq!(
+            dump("fun square(x) = x * x;"),
+            "(fun square (x) (return (* x x)))\n"
+        );
+        assert_eq!(
+            dump("class B < A { init(...xs) { super.init(xs); } class sum() {} area { } }"),
+            "(class B (< A) (fun init (...xs) (; (call (super init) xs))) \
+             (static (fun sum ())) (getter (fun area ())))\n"
+        );
+        assert_eq!(
+            dump("o.p[1] = {\"k\": [1, 2]}[\"k\"][0:];"),
+            "(; ([]= (. o p) 1 (slice ([] (map (\"k\" (list 1 2))) \"k\") 0 _)))\n"
+        );
+    }
+}
@@ -0,0 +1,135 @@
+use std::{
+    borrow::Borrow,
+    collections::HashSet,
+    fmt,
+    hash::{Hash, Hasher},
+    ops::Deref,
+    rc::Rc,
+};
+
+/// An immutable, cheaply cloned string. Atoms handed out by the same [`AtomTable`] share
+/// storage, so comparing two of them is usually a pointer check.
+#[derive(Clone, Eq)]
+pub struct Atom(Rc<str>);
+
+impl Atom {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl PartialEq for Atom {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0) || self.0 == other.0
+    }
+}
+
+// Hashes like `str` so that maps keyed by atoms can be queried with a plain `&str`.
+impl Hash for Atom {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.hash(state);
+    }
+}
+
+impl Borrow<str> for Atom {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Deref for Atom {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<&str> for Atom {
+    fn from(value: &str) -> Self {
+        Atom(Rc::from(value))
+    }
+}
+
+impl From<String> for Atom {
+    fn from(value: String) -> Self {
+        Atom(Rc::from(value))
+    }
+}
+
+impl fmt::Display for Atom {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl fmt::Debug for Atom {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.0)
+    }
+}
+
+// Atoms are written as plain strings. Those read back don't share storage with any table.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Atom {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Atom {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(Atom::from)
+    }
+}
+
+/// Interns identifiers and string literals so that every occurrence of the same text in a
+/// program shares one allocation. The REPL keeps a single table across lines.
+#[derive(Debug, Default)]
+pub struct AtomTable {
+    atoms: HashSet<Rc<str>>,
+}
+
+impl AtomTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn intern(&mut self, value: &str) -> Atom {
+        if let Some(atom) = self.atoms.get(value) {
+            return Atom(atom.clone());
+        }
+        let atom: Rc<str> = Rc::from(value);
+        self.atoms.insert(atom.clone());
+        Atom(atom)
+    }
+
+    pub fn len(&self) -> usize {
+        self.atoms.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.atoms.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_intern() {
+        let mut atoms = AtomTable::new();
+        let a = atoms.intern("foo");
+        let b = atoms.intern("foo");
+        let c = atoms.intern("bar");
+        assert!(Rc::ptr_eq(&a.0, &b.0));
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+        assert_eq!(atoms.len(), 2);
+
+        // Atoms built outside the table still compare by content.
+        assert_eq!(a, Atom::from("foo"));
+    }
+}
@@ -1,9 +1,24 @@
 use std::{
-    fmt,
-    time::{SystemTime, UNIX_EPOCH},
+    cell::RefCell,
+    collections::{BTreeSet, HashMap, HashSet},
+    env,
+    f64::consts,
+    fmt, fs,
+    path::PathBuf,
+    rc::Rc,
+    sync::OnceLock,
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
-use crate::{error::RuntimeException, interpreter::Interpreter, object::Object};
+use crate::{
+    atom::Atom,
+    environment::Environment,
+    error::{RuntimeError, RuntimeErrorKind, RuntimeException},
+    interpreter::{Interpreter, output_error},
+    map::LoxMap,
+    object::{LoxModule, Object},
+};
 
 pub trait LoxCallable: fmt::Display + fmt::Debug {
     fn call(
@@ -11,28 +26,1216 @@ pub trait LoxCallable: fmt::Display + fmt::Debug {
         interpreter: &mut Interpreter,
         args: Vec<Object>,
     ) -> Result<Object, RuntimeException>;
+
+    /// The number of arguments the callable expects, or the least it accepts if it is
+    /// variadic.
+    fn arity(&self) -> usize;
+
+    /// Whether the callable accepts any number of arguments beyond its arity.
+    fn is_variadic(&self) -> bool {
+        false
+    }
+
+    /// The declared name of the callable, or `None` for lambdas.
+    fn name(&self) -> Option<String>;
+
+    /// Calls the callable in place of a Lox function that returns the call. Lox functions
+    /// override it to raise the tail calls of their own body rather than make them, for the
+    /// first function of the chain to make in a loop.
+    fn tail_call(
+        &self,
+        interpreter: &mut Interpreter,
+        args: Vec<Object>,
+    ) -> Result<Object, RuntimeException> {
+        self.call(interpreter, args)
+    }
+
+    /// Whether the callable is implemented in Rust rather than in Lox.
+    fn is_native(&self) -> bool {
+        false
+    }
+
+    /// The environment the callable captured when it was created, if it's written in Lox.
+    fn closure(&self) -> Option<Rc<RefCell<Environment>>> {
+        None
+    }
+}
+
+/// The display format shared by every function: `<native fn clock>`, `<fn foo>` or `<lambda>`.
+pub fn fmt_callable(callable: &dyn LoxCallable, f: &mut fmt::Formatter) -> fmt::Result {
+    match callable.name() {
+        Some(name) if callable.is_native() => write!(f, "<native fn {name}>"),
+        Some(name) => write!(f, "<fn {name}>"),
+        None => write!(f, "<lambda>"),
+    }
+}
+
+/// The Rust code behind a [`NativeFunction`], called once the number of arguments is
+/// checked.
+pub type NativeFn = dyn Fn(&mut Interpreter, Vec<Object>) -> Result<Object, RuntimeException>;
+
+/// A native function made from a closure, with the name and arity it's checked against like
+/// any other callable. See [`Interpreter::define_native`].
+pub struct NativeFunction {
+    name: String,
+    arity: usize,
+    function: Box<NativeFn>,
+}
+
+impl NativeFunction {
+    pub fn new(
+        name: impl Into<String>,
+        arity: usize,
+        function: impl Fn(&mut Interpreter, Vec<Object>) -> Result<Object, RuntimeException> + 'static,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            arity,
+            function: Box::new(function),
+        }
+    }
+}
+
+impl LoxCallable for NativeFunction {
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        args: Vec<Object>,
+    ) -> Result<Object, RuntimeException> {
+        (self.function)(interpreter, args)
+    }
+
+    fn arity(&self) -> usize {
+        self.arity
+    }
+
+    fn name(&self) -> Option<String> {
+        Some(self.name.clone())
+    }
+
+    fn is_native(&self) -> bool {
+        true
+    }
+}
+
+impl fmt::Debug for NativeFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("NativeFunction")
+            .field("name", &self.name)
+            .field("arity", &self.arity)
+            .finish_non_exhaustive()
+    }
+}
+
+impl fmt::Display for NativeFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt_callable(self, f)
+    }
+}
+
+/// Raises an error with `message` when `condition` is falsey, so that scripts can check
+/// themselves.
+#[derive(Debug)]
+pub struct AssertFunction;
+
+impl LoxCallable for AssertFunction {
+    fn call(
+        &self,
+        _interpreter: &mut Interpreter,
+        args: Vec<Object>,
+    ) -> Result<Object, RuntimeException> {
+        if args[0].is_truthy() {
+            Ok(Object::Nil)
+        } else {
+            Err(RuntimeException::Error(RuntimeError::native(
+                RuntimeErrorKind::AssertionFailed,
+                &format!("Assertion failed: {}", args[1]),
+            )))
+        }
+    }
+
+    fn arity(&self) -> usize {
+        2
+    }
+
+    fn name(&self) -> Option<String> {
+        Some("assert".to_string())
+    }
+
+    fn is_native(&self) -> bool {
+        true
+    }
+}
+
+impl fmt::Display for AssertFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt_callable(self, f)
+    }
+}
+
+/// Pauses the script for the given number of milliseconds.
+#[derive(Debug)]
+pub struct SleepFunction;
+
+impl LoxCallable for SleepFunction {
+    fn call(
+        &self,
+        _interpreter: &mut Interpreter,
+        args: Vec<Object>,
+    ) -> Result<Object, RuntimeException> {
+        match args[0].maybe_to_number() {
+            Some(millis) if millis >= 0.0 && millis.is_finite() => {
+                thread::sleep(Duration::from_secs_f64(millis / 1000.0));
+                Ok(Object::Nil)
+            }
+            _ => Err(RuntimeException::Error(RuntimeError::native(
+                RuntimeErrorKind::InvalidArgument,
+                &format!(
+                    "sleep() expects a non-negative number of milliseconds, got {}.",
+                    args[0]
+                ),
+            ))),
+        }
+    }
+
+    fn arity(&self) -> usize {
+        1
+    }
+
+    fn name(&self) -> Option<String> {
+        Some("sleep".to_string())
+    }
+
+    fn is_native(&self) -> bool {
+        true
+    }
+}
+
+impl fmt::Display for SleepFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt_callable(self, f)
+    }
+}
+
+/// Splits a Unix timestamp in seconds into a map of its UTC `year`, `month`, `day`, `hour`,
+/// `minute` and `second`, as in `date(clock())`.
+#[derive(Debug)]
+pub struct DateFunction;
+
+impl LoxCallable for DateFunction {
+    fn call(
+        &self,
+        _interpreter: &mut Interpreter,
+        args: Vec<Object>,
+    ) -> Result<Object, RuntimeException> {
+        let Some(timestamp) = args[0].maybe_to_number() else {
+            return Err(RuntimeException::Error(RuntimeError::native(
+                RuntimeErrorKind::TypeMismatch {
+                    expected: "number",
+                    found: args[0].type_name(),
+                },
+                &format!("date() expects a timestamp, got {}.", args[0]),
+            )));
+        };
+        let seconds = timestamp.floor() as i64;
+        let (year, month, day) = civil_from_days(seconds.div_euclid(86_400));
+        let time = seconds.rem_euclid(86_400);
+        let mut date = LoxMap::new();
+        for (part, value) in [
+            ("year", year),
+            ("month", month),
+            ("day", day),
+            ("hour", time / 3600),
+            ("minute", time / 60 % 60),
+            ("second", time % 60),
+        ] {
+            date.insert(Object::String(part.into()), Object::Integer(value));
+        }
+        Ok(Object::new_map(date))
+    }
+
+    fn arity(&self) -> usize {
+        1
+    }
+
+    fn name(&self) -> Option<String> {
+        Some("date".to_string())
+    }
+
+    fn is_native(&self) -> bool {
+        true
+    }
+}
+
+impl fmt::Display for DateFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt_callable(self, f)
+    }
 }
 
+/// The year, month and day of the Gregorian calendar that is `days` days after 1970-01-01,
+/// following Howard Hinnant's `civil_from_days`.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Prints every name visible from the calling scope, grouped by the kind of its value.
+/// Inner declarations shadow outer ones, so each name is listed once.
 #[derive(Debug)]
-pub struct ClockFunction;
+pub struct DirFunction;
 
-impl LoxCallable for ClockFunction {
+impl LoxCallable for DirFunction {
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        _args: Vec<Object>,
+    ) -> Result<Object, RuntimeException> {
+        let mut seen = HashSet::new();
+        let mut builtins = BTreeSet::new();
+        let mut functions = BTreeSet::new();
+        let mut classes = BTreeSet::new();
+        let mut variables = BTreeSet::new();
+
+        let mut environment = Some(interpreter.environment.clone());
+        while let Some(current) = environment {
+            for (name, value) in &current.borrow().values {
+                if !seen.insert(name.clone()) {
+                    continue;
+                }
+                let group = match value {
+                    value if value.is_native() => &mut builtins,
+                    Object::Function(_) => &mut functions,
+                    Object::Class(_) => &mut classes,
+                    _ => &mut variables,
+                };
+                group.insert(name.to_string());
+            }
+            environment = current.borrow().enclosing.clone();
+        }
+
+        let mut writer = interpreter.writer.borrow_mut();
+        for (kind, names) in [
+            ("builtins", builtins),
+            ("functions", functions),
+            ("classes", classes),
+            ("variables", variables),
+        ] {
+            if !names.is_empty() {
+                let names = names.into_iter().collect::<Vec<String>>();
+                writeln!(writer, "{kind}: {}", names.join(", ")).map_err(output_error)?;
+            }
+        }
+
+        Ok(Object::Nil)
+    }
+
+    fn arity(&self) -> usize {
+        0
+    }
+
+    fn name(&self) -> Option<String> {
+        Some("dir".to_string())
+    }
+
+    fn is_native(&self) -> bool {
+        true
+    }
+}
+
+impl fmt::Display for DirFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt_callable(self, f)
+    }
+}
+
+/// Returns the list of integers from `start` up to, but excluding, `end`.
+#[derive(Debug)]
+pub struct RangeFunction;
+
+impl LoxCallable for RangeFunction {
     fn call(
         &self,
         _interpreter: &mut Interpreter,
+        args: Vec<Object>,
+    ) -> Result<Object, RuntimeException> {
+        match (args[0].maybe_to_integer(), args[1].maybe_to_integer()) {
+            (Some(start), Some(end)) => Ok(Object::new_list(
+                (start..end).map(Object::Integer).collect(),
+            )),
+            _ => Err(RuntimeException::Error(RuntimeError::native(
+                RuntimeErrorKind::InvalidArgument,
+                "range() bounds must be integers.",
+            ))),
+        }
+    }
+
+    fn arity(&self) -> usize {
+        2
+    }
+
+    fn name(&self) -> Option<String> {
+        Some("range".to_string())
+    }
+
+    fn is_native(&self) -> bool {
+        true
+    }
+}
+
+impl fmt::Display for RangeFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt_callable(self, f)
+    }
+}
+
+/// Returns a random number from 0 up to, but excluding, 1.
+#[derive(Debug)]
+pub struct RandomFunction;
+
+impl LoxCallable for RandomFunction {
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
         _args: Vec<Object>,
     ) -> Result<Object, RuntimeException> {
-        Ok(Object::Number(
-            SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .expect("Time went backwards")
-                .as_secs() as f64,
-        ))
+        Ok(Object::Number(interpreter.random.next_f64()))
+    }
+
+    fn arity(&self) -> usize {
+        0
+    }
+
+    fn name(&self) -> Option<String> {
+        Some("random".to_string())
+    }
+
+    fn is_native(&self) -> bool {
+        true
     }
 }
 
-impl fmt::Display for ClockFunction {
+impl fmt::Display for RandomFunction {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "<fn native clock>")
+        fmt_callable(self, f)
+    }
+}
+
+/// Returns a random integer from `start` up to, but excluding, `end`.
+#[derive(Debug)]
+pub struct RandomIntFunction;
+
+impl LoxCallable for RandomIntFunction {
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        args: Vec<Object>,
+    ) -> Result<Object, RuntimeException> {
+        match (args[0].maybe_to_integer(), args[1].maybe_to_integer()) {
+            (Some(start), Some(end)) if start < end => Ok(Object::Integer(
+                interpreter.random.next_in_range(start, end),
+            )),
+            _ => Err(RuntimeException::Error(RuntimeError::native(
+                RuntimeErrorKind::InvalidArgument,
+                "randomInt() bounds must be integers, the first less than the second.",
+            ))),
+        }
+    }
+
+    fn arity(&self) -> usize {
+        2
+    }
+
+    fn name(&self) -> Option<String> {
+        Some("randomInt".to_string())
+    }
+
+    fn is_native(&self) -> bool {
+        true
+    }
+}
+
+impl fmt::Display for RandomIntFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt_callable(self, f)
+    }
+}
+
+/// Restarts the sequence of `random` and `randomInt` from `seed`, so that a script behaves
+/// the same on every run.
+#[derive(Debug)]
+pub struct RandomSeedFunction;
+
+impl LoxCallable for RandomSeedFunction {
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        args: Vec<Object>,
+    ) -> Result<Object, RuntimeException> {
+        match args[0].maybe_to_integer() {
+            Some(seed) => {
+                interpreter.random.seed(seed as u64);
+                Ok(Object::Nil)
+            }
+            _ => Err(RuntimeException::Error(RuntimeError::native(
+                RuntimeErrorKind::InvalidArgument,
+                "randomSeed() seed must be an integer.",
+            ))),
+        }
+    }
+
+    fn arity(&self) -> usize {
+        1
+    }
+
+    fn name(&self) -> Option<String> {
+        Some("randomSeed".to_string())
+    }
+
+    fn is_native(&self) -> bool {
+        true
+    }
+}
+
+impl fmt::Display for RandomSeedFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt_callable(self, f)
+    }
+}
+
+/// Parses a string into a number, returning `nil` when it doesn't hold one. Numbers are
+/// returned as they are.
+#[derive(Debug)]
+pub struct NumFunction;
+
+impl LoxCallable for NumFunction {
+    fn call(
+        &self,
+        _interpreter: &mut Interpreter,
+        args: Vec<Object>,
+    ) -> Result<Object, RuntimeException> {
+        match args[0].clone().flatten() {
+            number @ (Object::Number(_) | Object::Integer(_)) => Ok(number),
+            Object::String(string) => Ok(match string.trim().parse::<i64>() {
+                Ok(integer) => Object::Integer(integer),
+                Err(_) => string
+                    .trim()
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|number| number.is_finite())
+                    .map_or(Object::Nil, Object::Number),
+            }),
+            other => Err(RuntimeException::Error(RuntimeError::native(
+                RuntimeErrorKind::TypeMismatch {
+                    expected: "string",
+                    found: other.type_name(),
+                },
+                &format!("num() expects a string, got {other}."),
+            ))),
+        }
+    }
+
+    fn arity(&self) -> usize {
+        1
+    }
+
+    fn name(&self) -> Option<String> {
+        Some("num".to_string())
+    }
+
+    fn is_native(&self) -> bool {
+        true
+    }
+}
+
+impl fmt::Display for NumFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt_callable(self, f)
+    }
+}
+
+/// Reads a line from the interpreter's input, without its line ending, or returns `nil` at
+/// the end of the input.
+#[derive(Debug)]
+pub struct ReadLineFunction;
+
+impl LoxCallable for ReadLineFunction {
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        _args: Vec<Object>,
+    ) -> Result<Object, RuntimeException> {
+        let mut line = String::new();
+        let read = interpreter
+            .reader
+            .borrow_mut()
+            .read_line(&mut line)
+            .map_err(|e| io_error(&format!("readLine() failed: {e}")))?;
+        if read == 0 {
+            return Ok(Object::Nil);
+        }
+        let len = line.trim_end_matches(['\n', '\r']).len();
+        line.truncate(len);
+        Ok(Object::String(line.into()))
+    }
+
+    fn arity(&self) -> usize {
+        0
+    }
+
+    fn name(&self) -> Option<String> {
+        Some("readLine".to_string())
+    }
+
+    fn is_native(&self) -> bool {
+        true
+    }
+}
+
+impl fmt::Display for ReadLineFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt_callable(self, f)
+    }
+}
+
+/// Returns the contents of the text file at `path`, relative to the running script like
+/// `import` paths.
+#[derive(Debug)]
+pub struct ReadFileFunction;
+
+impl LoxCallable for ReadFileFunction {
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        args: Vec<Object>,
+    ) -> Result<Object, RuntimeException> {
+        let path = path_arg(interpreter, "readFile", &args[0])?;
+        fs::read_to_string(&path)
+            .map(|contents| Object::String(contents.into()))
+            .map_err(|e| io_error(&format!("Failed to read '{}': {e}", path.display())))
+    }
+
+    fn arity(&self) -> usize {
+        1
+    }
+
+    fn name(&self) -> Option<String> {
+        Some("readFile".to_string())
+    }
+
+    fn is_native(&self) -> bool {
+        true
+    }
+}
+
+impl fmt::Display for ReadFileFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt_callable(self, f)
+    }
+}
+
+/// Writes the string `contents` to the file at `path`, relative to the running script,
+/// replacing the file if it exists.
+#[derive(Debug)]
+pub struct WriteFileFunction;
+
+impl LoxCallable for WriteFileFunction {
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        args: Vec<Object>,
+    ) -> Result<Object, RuntimeException> {
+        let path = path_arg(interpreter, "writeFile", &args[0])?;
+        let Object::String(contents) = &args[1] else {
+            return Err(RuntimeException::Error(RuntimeError::native(
+                RuntimeErrorKind::TypeMismatch {
+                    expected: "string",
+                    found: args[1].type_name(),
+                },
+                &format!("writeFile() contents must be a string, got {}.", args[1]),
+            )));
+        };
+        fs::write(&path, contents.as_str())
+            .map(|_| Object::Nil)
+            .map_err(|e| io_error(&format!("Failed to write '{}': {e}", path.display())))
+    }
+
+    fn arity(&self) -> usize {
+        2
+    }
+
+    fn name(&self) -> Option<String> {
+        Some("writeFile".to_string())
+    }
+
+    fn is_native(&self) -> bool {
+        true
+    }
+}
+
+impl fmt::Display for WriteFileFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt_callable(self, f)
+    }
+}
+
+/// Returns the value of the environment variable `name`, or `nil` when it isn't set.
+#[derive(Debug)]
+pub struct EnvFunction;
+
+impl LoxCallable for EnvFunction {
+    fn call(
+        &self,
+        _interpreter: &mut Interpreter,
+        args: Vec<Object>,
+    ) -> Result<Object, RuntimeException> {
+        let Object::String(name) = &args[0] else {
+            return Err(RuntimeException::Error(RuntimeError::native(
+                RuntimeErrorKind::TypeMismatch {
+                    expected: "string",
+                    found: args[0].type_name(),
+                },
+                &format!("env() name must be a string, got {}.", args[0]),
+            )));
+        };
+        Ok(env::var(name.as_str()).map_or(Object::Nil, |value| Object::String(value.into())))
+    }
+
+    fn arity(&self) -> usize {
+        1
+    }
+
+    fn name(&self) -> Option<String> {
+        Some("env".to_string())
+    }
+
+    fn is_native(&self) -> bool {
+        true
+    }
+}
+
+impl fmt::Display for EnvFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt_callable(self, f)
+    }
+}
+
+/// The path a file native was given, resolved against the running script's directory. It's
+/// an error when the interpreter doesn't allow file access.
+fn path_arg(
+    interpreter: &Interpreter,
+    native: &str,
+    path: &Object,
+) -> Result<PathBuf, RuntimeException> {
+    if !interpreter.file_access {
+        return Err(io_error(&format!(
+            "{native}() can't access files: file access is off."
+        )));
+    }
+    match path {
+        Object::String(path) => Ok(interpreter.module_dir.join(path.as_str())),
+        other => Err(RuntimeException::Error(RuntimeError::native(
+            RuntimeErrorKind::TypeMismatch {
+                expected: "string",
+                found: other.type_name(),
+            },
+            &format!("{native}() path must be a string, got {other}."),
+        ))),
+    }
+}
+
+fn io_error(message: &str) -> RuntimeException {
+    RuntimeException::Error(RuntimeError::native(RuntimeErrorKind::IoFailed, message))
+}
+
+/// A function of the `math` module, such as `math.sqrt`. `min` and `max` take any number of
+/// arguments, at least one.
+#[derive(Debug)]
+pub struct MathFunction {
+    name: &'static str,
+    arity: usize,
+}
+
+impl MathFunction {
+    const FUNCTIONS: [(&'static str, usize); 8] = [
+        ("abs", 1),
+        ("ceil", 1),
+        ("floor", 1),
+        ("max", 1),
+        ("min", 1),
+        ("pow", 2),
+        ("round", 1),
+        ("sqrt", 1),
+    ];
+}
+
+impl LoxCallable for MathFunction {
+    fn call(
+        &self,
+        _interpreter: &mut Interpreter,
+        args: Vec<Object>,
+    ) -> Result<Object, RuntimeException> {
+        let numbers = args
+            .iter()
+            .map(|arg| {
+                arg.maybe_to_number().ok_or_else(|| {
+                    RuntimeException::Error(RuntimeError::native(
+                        RuntimeErrorKind::TypeMismatch {
+                            expected: "number",
+                            found: arg.type_name(),
+                        },
+                        &format!("math.{}() expects numbers, got {arg}.", self.name),
+                    ))
+                })
+            })
+            .collect::<Result<Vec<f64>, _>>()?;
+        let result = match self.name {
+            "abs" => numbers[0].abs(),
+            "ceil" => numbers[0].ceil(),
+            "floor" => numbers[0].floor(),
+            "max" => numbers.into_iter().fold(f64::NEG_INFINITY, f64::max),
+            "min" => numbers.into_iter().fold(f64::INFINITY, f64::min),
+            "pow" => numbers[0].powf(numbers[1]),
+            "round" => numbers[0].round(),
+            "sqrt" => numbers[0].sqrt(),
+            _ => unreachable!("Unknown math function {}", self.name),
+        };
+        Ok(Object::Number(result))
+    }
+
+    fn arity(&self) -> usize {
+        self.arity
+    }
+
+    fn is_variadic(&self) -> bool {
+        matches!(self.name, "max" | "min")
+    }
+
+    fn name(&self) -> Option<String> {
+        Some(self.name.to_string())
+    }
+
+    fn is_native(&self) -> bool {
+        true
+    }
+}
+
+impl fmt::Display for MathFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt_callable(self, f)
+    }
+}
+
+/// The `math` module, holding the math functions and the constants `pi` and `e`.
+fn math_module() -> LoxModule {
+    let mut exports: HashMap<Atom, Object> = MathFunction::FUNCTIONS
+        .into_iter()
+        .map(|(name, arity)| {
+            let function = MathFunction { name, arity };
+            (name.into(), Object::Function(Rc::new(function)))
+        })
+        .collect();
+    exports.insert("pi".into(), Object::Number(consts::PI));
+    exports.insert("e".into(), Object::Number(consts::E));
+    LoxModule::native("math".to_string(), exports)
+}
+
+/// `div(a, b)`, `mod(a, b)` or `checked_add(a, b)`: arithmetic on two numbers that returns
+/// `nil` instead of raising an error when an operand isn't a number, when dividing by zero,
+/// or when the result overflows. `mod` keeps the sign of `a`.
+#[derive(Debug)]
+pub struct CheckedArithmetic {
+    name: &'static str,
+}
+
+impl LoxCallable for CheckedArithmetic {
+    fn call(
+        &self,
+        _interpreter: &mut Interpreter,
+        args: Vec<Object>,
+    ) -> Result<Object, RuntimeException> {
+        // Integers stay exact, and give `nil` where their result would overflow.
+        if let (Object::Integer(a), Object::Integer(b)) = (&args[0], &args[1]) {
+            let result = match self.name {
+                "div" => match a.checked_rem(*b) {
+                    Some(0) => a.checked_div(*b).map(Object::Integer),
+                    Some(_) => Some(Object::Number(*a as f64 / *b as f64)),
+                    None => None,
+                },
+                "mod" => a.checked_rem(*b).map(Object::Integer),
+                "checked_add" => a.checked_add(*b).map(Object::Integer),
+                _ => unreachable!("Unknown checked function {}", self.name),
+            };
+            return Ok(result.unwrap_or(Object::Nil));
+        }
+        let (Some(a), Some(b)) = (args[0].maybe_to_number(), args[1].maybe_to_number()) else {
+            return Ok(Object::Nil);
+        };
+        let (a, b) = (&a, &b);
+        let result = match self.name {
+            "div" if *b != 0.0 => a / b,
+            "mod" if *b != 0.0 => a % b,
+            "checked_add" => a + b,
+            "div" | "mod" => return Ok(Object::Nil),
+            _ => unreachable!("Unknown checked function {}", self.name),
+        };
+        Ok(if result.is_finite() {
+            Object::Number(result)
+        } else {
+            Object::Nil
+        })
+    }
+
+    fn arity(&self) -> usize {
+        2
+    }
+
+    fn name(&self) -> Option<String> {
+        Some(self.name.to_string())
+    }
+
+    fn is_native(&self) -> bool {
+        true
+    }
+}
+
+impl fmt::Display for CheckedArithmetic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt_callable(self, f)
+    }
+}
+
+fn since_epoch() -> Duration {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+}
+
+/// A native function made from a plain function, for the builtins.
+fn native(
+    name: &'static str,
+    arity: usize,
+    function: fn(&mut Interpreter, Vec<Object>) -> Result<Object, RuntimeException>,
+) -> Object {
+    Object::Function(Rc::new(NativeFunction::new(name, arity, function)))
+}
+
+/// A native function or module, as a name and a way to make the value bound to it.
+pub struct Builtin {
+    pub name: &'static str,
+    make: fn() -> Object,
+}
+
+thread_local! {
+    /// The builtin values made on this thread so far, by name.
+    static VALUES: RefCell<HashMap<&'static str, Object>> = RefCell::new(HashMap::new());
+}
+
+impl Builtin {
+    /// The value bound to the builtin. It's made the first time it's asked for on a thread,
+    /// and shared by every interpreter running on that thread afterwards.
+    pub fn value(&self) -> Object {
+        VALUES.with_borrow_mut(|values| values.entry(self.name).or_insert_with(self.make).clone())
+    }
+}
+
+/// The native functions and modules every interpreter starts with. The list is built once and
+/// shared between threads, while the values bound to the names are reference counted, so each
+/// thread makes its own.
+pub fn builtins() -> &'static [Builtin] {
+    static BUILTINS: OnceLock<Vec<Builtin>> = OnceLock::new();
+    BUILTINS.get_or_init(|| {
+        vec![
+            // The arguments given to the script after its path.
+            Builtin {
+                name: "args",
+                make: || {
+                    native("args", 0, |interpreter, _| {
+                        let args = interpreter
+                            .script_args
+                            .iter()
+                            .map(|arg| Object::String(arg.as_str().into()))
+                            .collect();
+                        Ok(Object::new_list(args))
+                    })
+                },
+            },
+            Builtin {
+                name: "assert",
+                make: || Object::Function(Rc::new(AssertFunction)),
+            },
+            // Whether a value is truthy, as conditions test it.
+            Builtin {
+                name: "bool",
+                make: || {
+                    native("bool", 1, |_, args| {
+                        Ok(Object::Boolean(args[0].is_truthy()))
+                    })
+                },
+            },
+            Builtin {
+                name: "checked_add",
+                make: || {
+                    Object::Function(Rc::new(CheckedArithmetic {
+                        name: "checked_add",
+                    }))
+                },
+            },
+            Builtin {
+                name: "clock",
+                make: || {
+                    native("clock", 0, |_, _| {
+                        Ok(Object::Integer(since_epoch().as_secs() as i64))
+                    })
+                },
+            },
+            // Frees the reference cycles the program can no longer reach, and returns how many
+            // objects were freed. It also runs on its own as the program allocates.
+            Builtin {
+                name: "collect",
+                make: || {
+                    native("collect", 0, |interpreter, _| {
+                        Ok(Object::Integer(interpreter.collect_garbage() as i64))
+                    })
+                },
+            },
+            // Ends the program, which then exits with the given status.
+            Builtin {
+                name: "exit",
+                make: || {
+                    native("exit", 1, |_, args| match args[0].maybe_to_integer() {
+                        Some(status) if (0..=255).contains(&status) => {
+                            Err(RuntimeException::Exit(status as i32))
+                        }
+                        _ => Err(RuntimeException::Error(RuntimeError::native(
+                            RuntimeErrorKind::InvalidArgument,
+                            &format!(
+                                "exit() status must be an integer from 0 to 255, got {}.",
+                                args[0]
+                            ),
+                        ))),
+                    })
+                },
+            },
+            // Finer than `clock`, for timing code.
+            Builtin {
+                name: "clockMillis",
+                make: || {
+                    native("clockMillis", 0, |_, _| {
+                        Ok(Object::Integer(since_epoch().as_millis() as i64))
+                    })
+                },
+            },
+            Builtin {
+                name: "date",
+                make: || Object::Function(Rc::new(DateFunction)),
+            },
+            Builtin {
+                name: "dir",
+                make: || Object::Function(Rc::new(DirFunction)),
+            },
+            Builtin {
+                name: "div",
+                make: || Object::Function(Rc::new(CheckedArithmetic { name: "div" })),
+            },
+            // The environment chain, and the environments captured by the functions reachable
+            // from it, as a Graphviz DOT graph.
+            Builtin {
+                name: "dump_env_graph",
+                make: || {
+                    native("dump_env_graph", 0, |interpreter, _| {
+                        Ok(Object::String(interpreter.env_graph().into()))
+                    })
+                },
+            },
+            Builtin {
+                name: "env",
+                make: || Object::Function(Rc::new(EnvFunction)),
+            },
+            Builtin {
+                name: "math",
+                make: || Object::Module(Rc::new(math_module())),
+            },
+            Builtin {
+                name: "mod",
+                make: || Object::Function(Rc::new(CheckedArithmetic { name: "mod" })),
+            },
+            Builtin {
+                name: "num",
+                make: || Object::Function(Rc::new(NumFunction)),
+            },
+            // The name of the operating system, such as "linux", "macos" or "windows".
+            Builtin {
+                name: "platform",
+                make: || {
+                    native("platform", 0, |_, _| {
+                        Ok(Object::String(env::consts::OS.into()))
+                    })
+                },
+            },
+            Builtin {
+                name: "range",
+                make: || Object::Function(Rc::new(RangeFunction)),
+            },
+            Builtin {
+                name: "random",
+                make: || Object::Function(Rc::new(RandomFunction)),
+            },
+            Builtin {
+                name: "randomInt",
+                make: || Object::Function(Rc::new(RandomIntFunction)),
+            },
+            Builtin {
+                name: "randomSeed",
+                make: || Object::Function(Rc::new(RandomSeedFunction)),
+            },
+            Builtin {
+                name: "readFile",
+                make: || Object::Function(Rc::new(ReadFileFunction)),
+            },
+            Builtin {
+                name: "readLine",
+                make: || Object::Function(Rc::new(ReadLineFunction)),
+            },
+            // The `//! key: value` header of the script, as a map.
+            Builtin {
+                name: "script_meta",
+                make: || {
+                    native("script_meta", 0, |interpreter, _| {
+                        let mut meta = LoxMap::new();
+                        for (key, value) in &interpreter.script_meta {
+                            meta.insert(Object::String(key.clone()), Object::String(value.clone()));
+                        }
+                        Ok(Object::new_map(meta))
+                    })
+                },
+            },
+            Builtin {
+                name: "sleep",
+                make: || Object::Function(Rc::new(SleepFunction)),
+            },
+            // What the interpreter did so far, as a map of counters.
+            Builtin {
+                name: "stats",
+                make: || {
+                    native("stats", 0, |interpreter, _| {
+                        let stats = &interpreter.stats;
+                        let mut map = LoxMap::new();
+                        for (key, value) in [
+                            ("statements", stats.statements),
+                            ("calls", stats.calls),
+                            ("collections", stats.collections),
+                            ("collected", stats.collected),
+                            ("environments", stats.environments()),
+                            ("peak_call_depth", stats.peak_call_depth),
+                        ] {
+                            map.insert(Object::String(key.into()), Object::Integer(value as i64));
+                        }
+                        Ok(Object::new_map(map))
+                    })
+                },
+            },
+            // A value as `print` would show it.
+            Builtin {
+                name: "str",
+                make: || {
+                    native("str", 1, |_, args| match &args[0] {
+                        Object::String(_) | Object::Rope(_) => Ok(args[0].clone()),
+                        other => Ok(Object::String(other.to_string().into())),
+                    })
+                },
+            },
+            // The type of a value, or the class of an instance, where `typeof` only says
+            // "instance".
+            Builtin {
+                name: "type",
+                make: || {
+                    native("type", 1, |_, args| {
+                        Ok(Object::String(args[0].class_or_type_name().into()))
+                    })
+                },
+            },
+            Builtin {
+                name: "writeFile",
+                make: || Object::Function(Rc::new(WriteFileFunction)),
+            },
+        ]
+    })
+}
+
+/// The natives of the optional features built, such as `now()` with `datetime`. Interpreters
+/// start with them too, unless made with [`Interpreter::without_features`].
+pub fn feature_builtins() -> &'static [Builtin] {
+    static BUILTINS: OnceLock<Vec<Builtin>> = OnceLock::new();
+    BUILTINS.get_or_init(|| {
+        #[allow(unused_mut)]
+        let mut builtins = Vec::new();
+        #[cfg(feature = "datetime")]
+        {
+            use crate::datetime::{FormatTimeFunction, NowFunction, ParseTimeFunction};
+
+            builtins.extend([
+                Builtin {
+                    name: "now",
+                    make: || Object::Function(Rc::new(NowFunction)),
+                },
+                Builtin {
+                    name: "format_time",
+                    make: || Object::Function(Rc::new(FormatTimeFunction)),
+                },
+                Builtin {
+                    name: "parse_time",
+                    make: || Object::Function(Rc::new(ParseTimeFunction)),
+                },
+            ]);
+        }
+        builtins
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn test_builtins_shared() {
+        let registry = builtins().as_ptr() as usize;
+        let from_threads: Vec<usize> = (0..4)
+            .map(|_| thread::spawn(|| builtins().as_ptr() as usize))
+            .map(|handle| handle.join().unwrap())
+            .collect();
+        assert!(from_threads.iter().all(|&ptr| ptr == registry));
+
+        let names: HashSet<&str> = builtins()
+            .iter()
+            .chain(feature_builtins())
+            .map(|builtin| builtin.name)
+            .collect();
+        assert_eq!(names.len(), builtins().len() + feature_builtins().len());
+        assert!(names.contains("clock"));
+    }
+
+    #[test]
+    fn test_civil_from_days() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        assert_eq!(civil_from_days(11_016), (2000, 2, 29));
+        assert_eq!(civil_from_days(19_782), (2024, 2, 29));
+        assert_eq!(civil_from_days(-719_468), (0, 3, 1));
     }
 }
@@ -0,0 +1,56 @@
+use std::{cell::RefCell, io, rc::Rc};
+
+/// An in-memory sink for the output of a program. Clones share the same buffer, so one can
+/// be handed to an [`Interpreter`](crate::interpreter::Interpreter) while another is kept to
+/// read what was printed.
+#[derive(Clone, Debug, Default)]
+pub struct CaptureWriter {
+    buffer: Rc<RefCell<Vec<u8>>>,
+}
+
+impl CaptureWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The shared buffer, in the shape [`Interpreter::new`](crate::interpreter::Interpreter::new)
+    /// expects.
+    pub fn buffer(&self) -> Rc<RefCell<Vec<u8>>> {
+        self.buffer.clone()
+    }
+
+    pub fn bytes(&self) -> Vec<u8> {
+        self.buffer.borrow().clone()
+    }
+
+    /// Everything written so far, with invalid UTF-8 replaced.
+    pub fn into_string(self) -> String {
+        String::from_utf8_lossy(&self.buffer.borrow()).into_owned()
+    }
+}
+
+impl io::Write for CaptureWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    #[test]
+    fn test_clones_share_buffer() {
+        let capture = CaptureWriter::new();
+        let mut writer = capture.clone();
+        write!(writer, "hello").unwrap();
+        capture.buffer().borrow_mut().extend_from_slice(b", world");
+        assert_eq!(capture.into_string(), "hello, world");
+    }
+}
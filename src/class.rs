@@ -1,32 +1,118 @@
-use std::{cell::RefCell, collections::HashMap, fmt, rc::Rc};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt,
+    rc::{Rc, Weak},
+};
 
 use crate::{
+    atom::Atom,
     builtin_funcs::LoxCallable,
-    error::{RuntimeError, RuntimeException},
+    environment::Environment,
+    error::{RuntimeError, RuntimeErrorKind, RuntimeException},
     function::{FunctionType, LoxFunction},
+    gc::{self, Tracer},
     interpreter::Interpreter,
     object::Object,
+    stmt::VarStmt,
     token::Token,
 };
 
-#[derive(Clone, Debug)]
+#[derive(Debug)]
 pub struct LoxClass {
     pub name: String,
+    /// The class itself, which the instances made by calling it share. That gives classes an
+    /// identity for `is`.
+    this: Weak<LoxClass>,
     superclass: Option<Rc<LoxClass>>,
-    methods: HashMap<String, Rc<LoxFunction>>,
+    methods: HashMap<Atom, Rc<LoxFunction>>,
+    fields: Rc<Vec<VarStmt>>,
+    /// The environment the class was declared in, where its field defaults are evaluated.
+    closure: Rc<RefCell<Environment>>,
+    /// Properties set on the class itself, as in `Config.defaults = {}`, which subclasses
+    /// inherit.
+    statics: RefCell<HashMap<Atom, Object>>,
 }
 
 impl LoxClass {
     pub fn new(
         name: String,
         superclass: Option<Rc<LoxClass>>,
-        methods: HashMap<String, Rc<LoxFunction>>,
-    ) -> Self {
-        LoxClass {
+        methods: HashMap<Atom, Rc<LoxFunction>>,
+        fields: Rc<Vec<VarStmt>>,
+        closure: Rc<RefCell<Environment>>,
+    ) -> Rc<Self> {
+        gc::track_environment(&closure);
+        Rc::new_cyclic(|this| LoxClass {
             name,
+            this: this.clone(),
             superclass,
             methods,
+            fields,
+            closure,
+            statics: RefCell::new(HashMap::new()),
+        })
+    }
+
+    pub fn get_static(&self, name: &str) -> Option<Object> {
+        match self.statics.borrow().get(name) {
+            Some(value) => Some(value.clone()),
+            None => self.superclass.as_ref()?.get_static(name),
+        }
+    }
+
+    pub fn set_static(&self, name: Atom, value: Object) {
+        self.statics.borrow_mut().insert(name, value);
+    }
+
+    /// Reports what the class holds on to to the cycle collector. Returns `false` when its
+    /// static properties are borrowed.
+    pub fn trace(&self, tracer: &mut Tracer) -> bool {
+        let Ok(statics) = self.statics.try_borrow() else {
+            return false;
+        };
+        if let Some(superclass) = &self.superclass {
+            tracer.class(superclass);
+        }
+        self.methods
+            .values()
+            .for_each(|method| tracer.method(method));
+        statics.values().for_each(|value| tracer.object(value));
+        tracer.environment(&self.closure);
+        true
+    }
+
+    /// Drops the static properties of a class the cycle collector found unreachable.
+    pub fn clear_statics(&self) {
+        if let Ok(mut statics) = self.statics.try_borrow_mut() {
+            statics.clear();
+        }
+    }
+
+    /// Sets the declared fields of `instance` to their defaults, those of superclasses first so
+    /// that subclasses can override them. Each default is evaluated anew for every instance.
+    fn initialize_fields(
+        &self,
+        interpreter: &mut Interpreter,
+        instance: &Rc<RefCell<LoxInstance>>,
+    ) -> Result<(), RuntimeException> {
+        if let Some(superclass) = &self.superclass {
+            superclass.initialize_fields(interpreter, instance)?;
         }
+        if self.fields.is_empty() {
+            return Ok(());
+        }
+        let mut environment = Environment::new(Some(self.closure.clone()));
+        environment.define("this", Object::Instance(instance.clone()));
+        let environment = Rc::new(RefCell::new(environment));
+        for field in self.fields.iter() {
+            let value = match &field.initializer {
+                Some(initializer) => interpreter.evaluate_in(initializer, environment.clone())?,
+                None => Object::Nil,
+            };
+            instance.borrow_mut().define_field(field.name.atom(), value);
+        }
+        Ok(())
     }
 
     pub fn find_method(&self, name: &str) -> Option<&Rc<LoxFunction>> {
@@ -38,6 +124,18 @@ impl LoxClass {
                 None
             })
     }
+
+    /// Whether this class is `other` or inherits from it.
+    pub fn is_subclass_of(self: &Rc<Self>, other: &Rc<LoxClass>) -> bool {
+        let mut class = Some(self);
+        while let Some(current) = class {
+            if Rc::ptr_eq(current, other) {
+                return true;
+            }
+            class = current.superclass.as_ref();
+        }
+        false
+    }
 }
 
 impl fmt::Display for LoxClass {
@@ -52,59 +150,124 @@ impl LoxCallable for LoxClass {
         interpreter: &mut Interpreter,
         args: Vec<Object>,
     ) -> Result<Object, RuntimeException> {
-        let instance = Object::Instance(Rc::new(RefCell::new(LoxInstance::new(self.clone()))));
+        let class = self.this.upgrade().expect("A class being called is alive");
+        let lox_instance = Rc::new(RefCell::new(LoxInstance::new(class)));
+        self.initialize_fields(interpreter, &lox_instance)?;
+        let instance = Object::Instance(lox_instance);
+        gc::track(&instance);
         if let Some(initializer) = self.find_method("init") {
             initializer.bind(instance.clone()).call(interpreter, args)?;
         }
 
         Ok(instance)
     }
+
+    fn arity(&self) -> usize {
+        self.find_method("init")
+            .map_or(0, |initializer| initializer.arity())
+    }
+
+    fn is_variadic(&self) -> bool {
+        self.find_method("init")
+            .is_some_and(|initializer| initializer.is_variadic())
+    }
+
+    fn name(&self) -> Option<String> {
+        Some(self.name.clone())
+    }
 }
 
 #[derive(Clone, Debug)]
 pub struct LoxInstance {
-    class: LoxClass,
-    fields: HashMap<String, Object>,
+    class: Rc<LoxClass>,
+    fields: HashMap<Atom, Object>,
+    /// Results of `memo` getters, cleared whenever a field is written.
+    memo: HashMap<Atom, Object>,
 }
 
 impl LoxInstance {
-    pub fn new(class: LoxClass) -> Self {
+    pub fn new(class: Rc<LoxClass>) -> Self {
         LoxInstance {
             class,
             fields: HashMap::new(),
+            memo: HashMap::new(),
         }
     }
 
-    pub fn get(&self, name: &Token) -> Result<Object, RuntimeException> {
-        if let Some(value) = self.fields.get(&name.value.to_string()) {
+    pub fn memoized(&self, name: &Token) -> Option<&Object> {
+        self.memo.get(&name.atom())
+    }
+
+    pub fn memoize(&mut self, name: &Token, value: Object) {
+        self.memo.insert(name.atom(), value);
+    }
+
+    pub fn class(&self) -> &Rc<LoxClass> {
+        &self.class
+    }
+
+    /// Looks up a field or a method. Methods are bound to the shared `instance` rather than a
+    /// copy of it, so they keep observing its fields after being stored elsewhere.
+    pub fn get(
+        instance: &Rc<RefCell<LoxInstance>>,
+        name: &Token,
+    ) -> Result<Object, RuntimeException> {
+        let this = instance.borrow();
+        if let Some(value) = this.fields.get(&name.atom()) {
             return Ok(value.clone());
         }
 
-        if let Some(method) = self.class.find_method(&name.value.to_string()) {
+        if let Some(method) = this.class.find_method(&name.atom()) {
             return Ok(Object::Function(Rc::new(
-                method.bind(Object::Instance(Rc::new(RefCell::new(self.clone())))),
+                method.bind(Object::Instance(instance.clone())),
             )));
         }
 
         Err(RuntimeException::Error(RuntimeError::new(
             name.to_owned(),
+            RuntimeErrorKind::UndefinedProperty,
             "Undefined property.",
         )))
     }
 
     pub fn get_getter(&self, name: &Token) -> Option<&Rc<LoxFunction>> {
-        if let Some(method) = self.class.find_method(&name.value.to_string()) {
-            if method.kind == FunctionType::GetterMethod {
-                return Some(method);
-            }
+        if let Some(method) = self.class.find_method(&name.atom())
+            && method.kind == FunctionType::GetterMethod
+        {
+            return Some(method);
         }
         None
     }
 
+    pub fn field(&self, name: &str) -> Option<&Object> {
+        self.fields.get(name)
+    }
+
+    pub fn define_field(&mut self, name: impl Into<Atom>, value: Object) {
+        self.memo.clear();
+        self.fields.insert(name.into(), value);
+    }
+
     pub fn set(&mut self, name: Token, value: Object) -> Result<(), RuntimeException> {
-        self.fields.insert(name.value.to_string(), value);
+        self.memo.clear();
+        self.fields.insert(name.atom(), value);
         Ok(())
     }
+
+    /// Reports what the instance holds on to to the cycle collector.
+    pub fn trace(&self, tracer: &mut Tracer) {
+        tracer.class(&self.class);
+        self.fields
+            .values()
+            .chain(self.memo.values())
+            .for_each(|value| tracer.object(value));
+    }
+
+    /// Drops the fields of an instance the cycle collector found unreachable.
+    pub fn clear(&mut self) {
+        self.fields.clear();
+        self.memo.clear();
+    }
 }
 
 impl fmt::Display for LoxInstance {
@@ -0,0 +1,204 @@
+//! Natives for reading and formatting wall-clock time, built with the `datetime` feature.
+//! Times are Unix timestamps in seconds, interpreted as UTC. Formats use the syntax of the
+//! `time` crate, such as `"[year]-[month]-[day] [hour]:[minute]:[second]"`.
+
+use std::fmt;
+
+use time::{
+    Date, OffsetDateTime, PrimitiveDateTime,
+    format_description::{BorrowedFormatItem, parse_borrowed},
+};
+
+use crate::{
+    builtin_funcs::{LoxCallable, fmt_callable},
+    error::{RuntimeError, RuntimeErrorKind, RuntimeException},
+    interpreter::Interpreter,
+    object::Object,
+};
+
+fn format_items<'a>(
+    function: &str,
+    format: &'a str,
+) -> Result<Vec<BorrowedFormatItem<'a>>, RuntimeException> {
+    parse_borrowed::<1>(format).map_err(|error| {
+        RuntimeException::Error(RuntimeError::native(
+            RuntimeErrorKind::InvalidArgument,
+            &format!("{function}() format is invalid: {error}."),
+        ))
+    })
+}
+
+/// Returns the current time, with sub-second precision.
+#[derive(Debug)]
+pub struct NowFunction;
+
+impl LoxCallable for NowFunction {
+    fn call(
+        &self,
+        _interpreter: &mut Interpreter,
+        _args: Vec<Object>,
+    ) -> Result<Object, RuntimeException> {
+        let nanos = OffsetDateTime::now_utc().unix_timestamp_nanos();
+        Ok(Object::Number(nanos as f64 / 1e9))
+    }
+
+    fn arity(&self) -> usize {
+        0
+    }
+
+    fn name(&self) -> Option<String> {
+        Some("now".to_string())
+    }
+
+    fn is_native(&self) -> bool {
+        true
+    }
+}
+
+impl fmt::Display for NowFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt_callable(self, f)
+    }
+}
+
+/// Renders a timestamp with the given format.
+#[derive(Debug)]
+pub struct FormatTimeFunction;
+
+impl LoxCallable for FormatTimeFunction {
+    fn call(
+        &self,
+        _interpreter: &mut Interpreter,
+        args: Vec<Object>,
+    ) -> Result<Object, RuntimeException> {
+        let (Some(epoch), Object::String(format)) = (args[0].maybe_to_number(), &args[1]) else {
+            return Err(RuntimeException::Error(RuntimeError::native(
+                RuntimeErrorKind::InvalidArgument,
+                "format_time() expects a timestamp and a format string.",
+            )));
+        };
+        let time =
+            OffsetDateTime::from_unix_timestamp_nanos((epoch * 1e9) as i128).map_err(|_| {
+                RuntimeException::Error(RuntimeError::native(
+                    RuntimeErrorKind::InvalidArgument,
+                    "format_time() timestamp is out of range.",
+                ))
+            })?;
+        let formatted = time
+            .format(&format_items("format_time", format)?)
+            .map_err(|error| {
+                RuntimeException::Error(RuntimeError::native(
+                    RuntimeErrorKind::InvalidArgument,
+                    &format!("format_time() can't format this time: {error}."),
+                ))
+            })?;
+        Ok(Object::String(formatted.into()))
+    }
+
+    fn arity(&self) -> usize {
+        2
+    }
+
+    fn name(&self) -> Option<String> {
+        Some("format_time".to_string())
+    }
+
+    fn is_native(&self) -> bool {
+        true
+    }
+}
+
+impl fmt::Display for FormatTimeFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt_callable(self, f)
+    }
+}
+
+/// Reads a timestamp back from text in the given format. A format without a time of day
+/// gives midnight.
+#[derive(Debug)]
+pub struct ParseTimeFunction;
+
+impl LoxCallable for ParseTimeFunction {
+    fn call(
+        &self,
+        _interpreter: &mut Interpreter,
+        args: Vec<Object>,
+    ) -> Result<Object, RuntimeException> {
+        let (Object::String(text), Object::String(format)) = (&args[0], &args[1]) else {
+            return Err(RuntimeException::Error(RuntimeError::native(
+                RuntimeErrorKind::InvalidArgument,
+                "parse_time() expects two strings.",
+            )));
+        };
+        let items = format_items("parse_time", format)?;
+        let time = PrimitiveDateTime::parse(text, &items)
+            .or_else(|_| Date::parse(text, &items).map(Date::midnight))
+            .map_err(|error| {
+                RuntimeException::Error(RuntimeError::native(
+                    RuntimeErrorKind::InvalidArgument,
+                    &format!("parse_time() can't parse '{text}': {error}."),
+                ))
+            })?;
+        Ok(Object::Integer(time.assume_utc().unix_timestamp()))
+    }
+
+    fn arity(&self) -> usize {
+        2
+    }
+
+    fn name(&self) -> Option<String> {
+        Some("parse_time".to_string())
+    }
+
+    fn is_native(&self) -> bool {
+        true
+    }
+}
+
+impl fmt::Display for ParseTimeFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt_callable(self, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::prelude::*;
+
+    fn run(source: &str) -> String {
+        let output = CaptureWriter::new();
+        if let Err(error) = Lox::new().with_writer(output.buffer()).run(source) {
+            return error.to_string();
+        }
+        output.into_string()
+    }
+
+    #[test]
+    fn test_format_and_parse() {
+        let format = "\"[year]-[month]-[day] [hour]:[minute]:[second]\"";
+        assert_eq!(
+            run(&format!("print(format_time(86400 * 365, {format}));")),
+            "1971-01-01 00:00:00\n"
+        );
+        assert_eq!(
+            run(&format!(
+                "print(parse_time(\"2001-09-09 01:46:40\", {format}));"
+            )),
+            "1000000000\n"
+        );
+        assert_eq!(
+            run("print(parse_time(\"1970-01-02\", \"[year]-[month]-[day]\"));"),
+            "86400\n"
+        );
+        assert_eq!(run("print(now() > 1000000000);"), "true\n");
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(run("format_time(0, \"[nonsense]\");").contains("format_time() format is invalid"));
+        assert!(
+            run("parse_time(\"today\", \"[year]\");").contains("parse_time() can't parse 'today'")
+        );
+    }
+}
@@ -1,11 +1,13 @@
 use std::{
-    cell::RefCell,
-    collections::{HashMap, hash_map::Entry},
+    cell::{Cell, RefCell},
+    collections::{HashMap, HashSet, VecDeque, hash_map::Entry},
+    fmt::Write,
     rc::Rc,
 };
 
 use crate::{
-    error::{RuntimeError, RuntimeException},
+    atom::Atom,
+    error::{RuntimeError, RuntimeErrorKind, RuntimeException},
     object::Object,
     token::Token,
 };
@@ -13,27 +15,50 @@ use crate::{
 #[derive(Clone, Debug)]
 pub struct Environment {
     pub enclosing: Option<Rc<RefCell<Environment>>>,
-    pub values: HashMap<String, Object>,
+    pub values: HashMap<Atom, Object>,
+    /// The names of `values` declared with `const`. The resolver rejects the assignments it
+    /// can see, but not those it resolves as globals before the declaration runs.
+    constants: HashSet<Atom>,
+    /// Whether this is the top level of a program or module: the outermost environment, or
+    /// one [shadowing](Environment::shadowing) it.
+    pub top_level: bool,
+}
+
+thread_local! {
+    /// The environments made on this thread so far, reported by `stats()`.
+    static ALLOCATED: Cell<usize> = const { Cell::new(0) };
+}
+
+/// The number of environments made on this thread so far.
+pub fn allocated() -> usize {
+    ALLOCATED.get()
 }
 
 impl Environment {
     pub fn new(enclosing: Option<Rc<RefCell<Environment>>>) -> Self {
+        ALLOCATED.set(ALLOCATED.get() + 1);
         Environment {
+            top_level: enclosing.is_none(),
             enclosing,
             values: HashMap::new(),
+            constants: HashSet::new(),
         }
     }
 
+    /// A new version of the top level `top_level`, for globals redefined after the functions
+    /// that captured the old one, which keep seeing the old values.
+    pub fn shadowing(top_level: Rc<RefCell<Environment>>) -> Self {
+        Self {
+            top_level: true,
+            ..Self::new(Some(top_level))
+        }
+    }
+
+    /// Looks up a variable. A variable declared without an initializer is returned as
+    /// `Object::Undefined`; the interpreter decides how reading it behaves.
     pub fn get(&self, name: &Token) -> Result<&Object, RuntimeException> {
-        if let Some(value) = self.values.get(&name.value.to_string()) {
-            if *value != Object::Undefined {
-                return Ok(value);
-            } else {
-                return Err(RuntimeException::Error(RuntimeError::new(
-                    name.to_owned(),
-                    "The variable isn't initialized.",
-                )));
-            }
+        if let Some(value) = self.values.get(&name.atom()) {
+            return Ok(value);
         }
 
         if let Some(enclosing) = &self.enclosing {
@@ -42,12 +67,20 @@ impl Environment {
 
         Err(RuntimeException::Error(RuntimeError::new(
             name.to_owned(),
+            RuntimeErrorKind::UndefinedVariable,
             "Undefined variable.",
         )))
     }
 
     pub fn assign(&mut self, name: &Token, value: Object) -> Result<(), RuntimeException> {
-        if let Entry::Occupied(mut e) = self.values.entry(name.value.to_string()) {
+        if let Entry::Occupied(mut e) = self.values.entry(name.atom()) {
+            if self.constants.contains(&name.atom()) {
+                return Err(RuntimeException::Error(RuntimeError::new(
+                    name.to_owned(),
+                    RuntimeErrorKind::ConstantAssignment,
+                    "Can't assign to a constant.",
+                )));
+            }
             e.insert(value);
             return Ok(());
         }
@@ -56,12 +89,22 @@ impl Environment {
         }
         Err(RuntimeException::Error(RuntimeError::new(
             name.to_owned(),
+            RuntimeErrorKind::UndefinedVariable,
             "Unclarified variable.",
         )))
     }
 
-    pub fn define(&mut self, name: &str, value: Object) {
-        self.values.insert(name.to_string(), value);
+    pub fn define(&mut self, name: impl Into<Atom>, value: Object) {
+        let name = name.into();
+        if !self.constants.is_empty() {
+            self.constants.remove(&name);
+        }
+        self.values.insert(name, value);
+    }
+
+    /// Makes the variable `name`, defined in this environment, reject assignments.
+    pub fn make_const(&mut self, name: impl Into<Atom>) {
+        self.constants.insert(name.into());
     }
 
     pub fn ancestor(&mut self, distance: usize) -> Option<&mut Environment> {
@@ -81,6 +124,7 @@ impl Environment {
             Some(env) => env.get(name),
             None => Err(RuntimeException::Error(RuntimeError::new(
                 name.clone(),
+                RuntimeErrorKind::UndefinedVariable,
                 "The variable isn't declared.",
             ))),
         }
@@ -96,8 +140,68 @@ impl Environment {
             Some(env) => env.assign(name, value),
             None => Err(RuntimeException::Error(RuntimeError::new(
                 name.to_owned(),
+                RuntimeErrorKind::UndefinedVariable,
                 "Unclarified variable.",
             ))),
         }
     }
 }
+
+/// Renders `environment`, the environments enclosing it and those captured by the functions
+/// they hold as a Graphviz DOT graph. Solid edges point to the enclosing environment and dashed
+/// ones to the closure of a function. Natives are left out to keep the global node readable.
+pub fn dot_graph(environment: &Rc<RefCell<Environment>>) -> String {
+    let mut ids: HashMap<*const RefCell<Environment>, usize> = HashMap::new();
+    let mut queue = VecDeque::from([environment.clone()]);
+    ids.insert(Rc::as_ptr(environment), 0);
+
+    let mut nodes = String::new();
+    let mut edges = String::new();
+    while let Some(current) = queue.pop_front() {
+        let id = ids[&Rc::as_ptr(&current)];
+        let mut id_of = |environment: &Rc<RefCell<Environment>>| {
+            let next = ids.len();
+            *ids.entry(Rc::as_ptr(environment)).or_insert_with(|| {
+                queue.push_back(environment.clone());
+                next
+            })
+        };
+
+        let current = current.borrow();
+        let mut values: Vec<_> = current
+            .values
+            .iter()
+            .filter(|(_, value)| !value.is_native())
+            .collect();
+        values.sort_by_key(|(name, _)| name.as_str());
+
+        let mut label = format!("env{id}\\n");
+        for (name, value) in &values {
+            write!(label, "{name} = {}\\l", escape(&value.to_string())).unwrap();
+            if let Object::Function(function) = value
+                && let Some(closure) = function.closure()
+            {
+                let target = id_of(&closure);
+                writeln!(
+                    edges,
+                    "  env{id} -> env{target} [style=dashed, label=\"{name}\"];"
+                )
+                .unwrap();
+            }
+        }
+        writeln!(nodes, "  env{id} [label=\"{label}\"];").unwrap();
+        if let Some(enclosing) = &current.enclosing {
+            let target = id_of(enclosing);
+            writeln!(edges, "  env{id} -> env{target};").unwrap();
+        }
+    }
+
+    format!("digraph environments {{\n  node [shape=box];\n{nodes}{edges}}}\n")
+}
+
+fn escape(label: &str) -> String {
+    label
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
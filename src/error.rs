@@ -1,7 +1,9 @@
 use std::fmt;
 
 use crate::{
+    function::TailCall,
     object::Object,
+    scanner::UNTERMINATED_STRING,
     token::{Token, TokenIdentity},
 };
 
@@ -10,15 +12,50 @@ pub enum RuntimeException {
     Continue,
     Error(RuntimeError),
     Return(RuntimeReturn),
+    /// A value raised by `throw`, unwinding until a `catch` handles it.
+    Throw(Object),
+    /// Raised by `exit(status)`, unwinding the whole program, which then ends with `status`.
+    Exit(i32),
+    /// Raised by `return f(...)`, unwinding to the function returning, which makes the call.
+    TailCall(Box<TailCall>),
+}
+
+/// Names a line of the source in error messages: `line 3`, unless the lines of the program
+/// are numbered differently, as in the REPL.
+pub type LineLabel<'a> = &'a dyn Fn(usize) -> String;
+
+fn line_label(line: usize) -> String {
+    format!("line {line}")
 }
 
 impl fmt::Display for RuntimeException {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_labelled(f, &line_label)
+    }
+}
+
+impl RuntimeException {
+    fn fmt_labelled(&self, f: &mut fmt::Formatter<'_>, label: LineLabel) -> fmt::Result {
         match self {
-            Self::Error(err) => write!(f, "{err}"),
+            Self::Error(err) => err.fmt_labelled(f, label),
             Self::Return(ret) => write!(f, "{ret}"),
+            Self::Throw(Object::Instance(instance)) => {
+                let instance = instance.borrow();
+                match (instance.field("message"), instance.field("line")) {
+                    (Some(message), Some(Object::Integer(line))) => write!(
+                        f,
+                        "[{}] Uncaught {}: {message}",
+                        label(*line as usize),
+                        instance.class().name
+                    ),
+                    _ => write!(f, "Uncaught exception: {instance}"),
+                }
+            }
+            Self::Throw(value) => write!(f, "Uncaught exception: {value}"),
             Self::Break => write!(f, "break"),
             Self::Continue => write!(f, "continue"),
+            Self::Exit(status) => write!(f, "Exited with status {status}."),
+            Self::TailCall(call) => write!(f, "tail call to {}", call.callee),
         }
     }
 }
@@ -40,35 +77,180 @@ impl RuntimeReturn {
     }
 }
 
+/// What went wrong, so that embedders can tell runtime errors apart without matching on
+/// their messages.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RuntimeErrorKind {
+    UndefinedVariable,
+    /// A variable read before anything was assigned to it.
+    UninitializedVariable,
+    UndefinedProperty,
+    UndefinedKey,
+    NotCallable,
+    /// For variadic callables, `expected` is the least number of arguments.
+    ArityMismatch {
+        expected: usize,
+        found: usize,
+    },
+    /// `expected` describes the accepted types, such as `"list or string"`, and `found` is
+    /// the [type name](crate::object::Object::type_name) of the actual value.
+    TypeMismatch {
+        expected: &'static str,
+        found: &'static str,
+    },
+    DivisionByZero,
+    IndexOutOfRange {
+        index: i64,
+        length: usize,
+    },
+    UnhashableKey,
+    /// A list destructured into a different number of variables.
+    DestructureMismatch {
+        expected: usize,
+        found: usize,
+    },
+    UnsupportedOperator,
+    /// A declaration of a name that already exists.
+    Redefinition,
+    ConstantAssignment,
+    /// A construct used where it isn't allowed, such as `this` outside of a class.
+    InvalidContext,
+    /// An argument rejected by a native function, or an operand of the right type but an
+    /// invalid value, such as a fractional repetition count.
+    InvalidArgument,
+    /// A failed `assert`.
+    AssertionFailed,
+    /// A module that couldn't be read, parsed or resolved.
+    ImportFailed,
+    /// A native that failed to read or write a file or stdin.
+    IoFailed,
+    /// A call nested deeper than the interpreter's
+    /// [`max_call_depth`](crate::interpreter::Interpreter::max_call_depth).
+    StackOverflow,
+}
+
+impl RuntimeErrorKind {
+    /// The names of all the kinds, as [`name`](Self::name) gives them.
+    pub const NAMES: [&'static str; 20] = [
+        "UndefinedVariable",
+        "UninitializedVariable",
+        "UndefinedProperty",
+        "UndefinedKey",
+        "NotCallable",
+        "ArityMismatch",
+        "TypeMismatch",
+        "DivisionByZero",
+        "IndexOutOfRange",
+        "UnhashableKey",
+        "DestructureMismatch",
+        "UnsupportedOperator",
+        "Redefinition",
+        "ConstantAssignment",
+        "InvalidContext",
+        "InvalidArgument",
+        "AssertionFailed",
+        "ImportFailed",
+        "IoFailed",
+        "StackOverflow",
+    ];
+
+    /// The name of the variant, such as `"TypeMismatch"`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::UndefinedVariable => "UndefinedVariable",
+            Self::UninitializedVariable => "UninitializedVariable",
+            Self::UndefinedProperty => "UndefinedProperty",
+            Self::UndefinedKey => "UndefinedKey",
+            Self::NotCallable => "NotCallable",
+            Self::ArityMismatch { .. } => "ArityMismatch",
+            Self::TypeMismatch { .. } => "TypeMismatch",
+            Self::DivisionByZero => "DivisionByZero",
+            Self::IndexOutOfRange { .. } => "IndexOutOfRange",
+            Self::UnhashableKey => "UnhashableKey",
+            Self::DestructureMismatch { .. } => "DestructureMismatch",
+            Self::UnsupportedOperator => "UnsupportedOperator",
+            Self::Redefinition => "Redefinition",
+            Self::ConstantAssignment => "ConstantAssignment",
+            Self::InvalidContext => "InvalidContext",
+            Self::InvalidArgument => "InvalidArgument",
+            Self::AssertionFailed => "AssertionFailed",
+            Self::ImportFailed => "ImportFailed",
+            Self::IoFailed => "IoFailed",
+            Self::StackOverflow => "StackOverflow",
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct RuntimeError {
-    message: String,
-    token: Token,
+    kind: RuntimeErrorKind,
+    message: Box<str>,
+    token: Option<Token>,
 }
 
 impl RuntimeError {
-    pub fn new(token: Token, message: &str) -> Self {
+    pub fn new(token: Token, kind: RuntimeErrorKind, message: &str) -> Self {
         Self {
-            message: message.to_string(),
-            token,
+            kind,
+            message: message.into(),
+            token: Some(token),
         }
     }
+
+    /// An error raised by a native function, which doesn't know where it was called from.
+    /// The interpreter locates it at the call site with [`RuntimeError::or_at`].
+    pub fn native(kind: RuntimeErrorKind, message: &str) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+            token: None,
+        }
+    }
+
+    pub fn kind(&self) -> &RuntimeErrorKind {
+        &self.kind
+    }
+
+    /// The message, without the location.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn token(&self) -> Option<&Token> {
+        self.token.as_ref()
+    }
+
+    pub fn or_at(mut self, token: &Token) -> Self {
+        self.token.get_or_insert_with(|| token.to_owned());
+        self
+    }
 }
 
 impl fmt::Display for RuntimeError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        if self.token.id == TokenIdentity::Eof {
-            write!(
+        self.fmt_labelled(f, &line_label)
+    }
+}
+
+impl RuntimeError {
+    fn fmt_labelled(&self, f: &mut fmt::Formatter<'_>, label: LineLabel) -> fmt::Result {
+        match &self.token {
+            Some(token) if token.id == TokenIdentity::Eof => write!(
                 f,
-                "[line {}:{}] Runtime error at end: {}",
-                self.token.line, self.token.column, self.message
-            )
-        } else {
-            write!(
+                "[{}:{}] Runtime error at end: {}",
+                label(token.line),
+                token.column,
+                self.message
+            ),
+            Some(token) => write!(
                 f,
-                "[line {}:{}] Runtime error at '{}': {}",
-                self.token.line, self.token.column, self.token, self.message
-            )
+                "[{}:{}] Runtime error at '{}': {}",
+                label(token.line),
+                token.column,
+                token,
+                self.message
+            ),
+            None => write!(f, "Runtime error: {}", self.message),
         }
     }
 }
@@ -86,22 +268,99 @@ impl ParsingError {
             token,
         }
     }
-}
 
-impl fmt::Display for ParsingError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        if self.token.id == TokenIdentity::Eof {
+    pub fn token(&self) -> &Token {
+        &self.token
+    }
+
+    /// Whether the input ended before the error, such as inside a string or before the end of
+    /// a statement, so that more input could fix it.
+    pub fn is_at_end(&self) -> bool {
+        self.token.id == TokenIdentity::Eof
+            || (self.token.id == TokenIdentity::Error && self.message == UNTERMINATED_STRING)
+    }
+
+    fn fmt_labelled(&self, f: &mut fmt::Formatter<'_>, label: LineLabel) -> fmt::Result {
+        if self.token.id == TokenIdentity::Error {
+            write!(
+                f,
+                "[{}:{}] Scanning error: {}",
+                label(self.token.line),
+                self.token.column,
+                self.message
+            )
+        } else if self.token.id == TokenIdentity::Eof {
             write!(
                 f,
-                "[line {}:{}] Parsing error at end: {}",
-                self.token.line, self.token.column, self.message
+                "[{}:{}] Parsing error at end: {}",
+                label(self.token.line),
+                self.token.column,
+                self.message
             )
         } else {
             write!(
                 f,
-                "[line {}:{}] Parsing error at '{}': {}",
-                self.token.line, self.token.column, self.token, self.message
+                "[{}:{}] Parsing error at '{}': {}",
+                label(self.token.line),
+                self.token.column,
+                self.token,
+                self.message
             )
         }
     }
 }
+
+impl fmt::Display for ParsingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_labelled(f, &line_label)
+    }
+}
+
+/// An error from any stage of running a program.
+pub enum LoxError {
+    Parsing(ParsingError),
+    Resolving(RuntimeError),
+    Runtime(RuntimeException),
+}
+
+impl LoxError {
+    /// The token the error points at, if it has one.
+    pub fn token(&self) -> Option<&Token> {
+        match self {
+            Self::Parsing(err) => Some(err.token()),
+            Self::Resolving(err) | Self::Runtime(RuntimeException::Error(err)) => err.token(),
+            Self::Runtime(_) => None,
+        }
+    }
+
+    /// Displays the error with its lines named by `label` rather than as `line 3`.
+    pub fn labelled<'a>(&'a self, label: LineLabel<'a>) -> Labelled<'a> {
+        Labelled { error: self, label }
+    }
+
+    fn fmt_labelled(&self, f: &mut fmt::Formatter<'_>, label: LineLabel) -> fmt::Result {
+        match self {
+            Self::Parsing(err) => err.fmt_labelled(f, label),
+            Self::Resolving(err) => err.fmt_labelled(f, label),
+            Self::Runtime(err) => err.fmt_labelled(f, label),
+        }
+    }
+}
+
+impl fmt::Display for LoxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_labelled(f, &line_label)
+    }
+}
+
+/// An error displayed with its own line labels, made by [`LoxError::labelled`].
+pub struct Labelled<'a> {
+    error: &'a LoxError,
+    label: LineLabel<'a>,
+}
+
+impl fmt::Display for Labelled<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.error.fmt_labelled(f, self.label)
+    }
+}
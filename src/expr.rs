@@ -1,61 +1,61 @@
-use std::hash::{DefaultHasher, Hash, Hasher};
-
-use crate::{object::Object, stmt::BlockStmt, token::Token};
-
-pub trait ExprVisitor {
-    type Output;
-
-    fn visit_assign_expr(&mut self, expr: &AssignExpr) -> Self::Output;
-    fn visit_binary_expr(&mut self, expr: &BinaryExpr) -> Self::Output;
-    fn visit_call_expr(&mut self, expr: &CallExpr) -> Self::Output;
-    fn visit_get_expr(&mut self, expr: &GetExpr) -> Self::Output;
-    fn visit_grouping_expr(&mut self, expr: &GroupingExpr) -> Self::Output;
-    fn visit_lambda_expr(&mut self, expr: &LambdaExpr) -> Self::Output;
-    fn visit_literal_expr(&self, expr: &LiteralExpr) -> Self::Output;
-    fn visit_logical_expr(&mut self, expr: &LogicalExpr) -> Self::Output;
-    fn visit_set_expr(&mut self, expr: &SetExpr) -> Self::Output;
-    fn visit_super_expr(&mut self, expr: &SuperExpr) -> Self::Output;
-    fn visit_this_expr(&mut self, expr: &ThisExpr) -> Self::Output;
-    fn visit_ternary_expr(&mut self, expr: &TernaryExpr) -> Self::Output;
-    fn visit_unary_expr(&mut self, expr: &UnaryExpr) -> Self::Output;
-    fn visit_variable_expr(&mut self, expr: &VariableExpr) -> Self::Output;
-
-    fn accept(&mut self, expr: &Expr) -> Self::Output {
-        match expr {
-            Expr::Assign(expr) => self.visit_assign_expr(expr),
-            Expr::Binary(expr) => self.visit_binary_expr(expr),
-            Expr::Call(expr) => self.visit_call_expr(expr),
-            Expr::Get(expr) => self.visit_get_expr(expr),
-            Expr::Grouping(expr) => self.visit_grouping_expr(expr),
-            Expr::Lambda(expr) => self.visit_lambda_expr(expr),
-            Expr::Literal(expr) => self.visit_literal_expr(expr),
-            Expr::Logical(expr) => self.visit_logical_expr(expr),
-            Expr::Set(expr) => self.visit_set_expr(expr),
-            Expr::Super(expr) => self.visit_super_expr(expr),
-            Expr::This(expr) => self.visit_this_expr(expr),
-            Expr::Ternary(expr) => self.visit_ternary_expr(expr),
-            Expr::Unary(expr) => self.visit_unary_expr(expr),
-            Expr::Variable(expr) => self.visit_variable_expr(expr),
-        }
+use std::{
+    hash::{DefaultHasher, Hash, Hasher},
+    mem,
+};
+
+use crate::{ast::ast, object::Object, stmt::BlockStmt, token::Token};
+
+ast! {
+    enum Expr, visited by ExprVisitor with expr;
+    boxed {
+        Assign(AssignExpr) => visit_assign_expr { name: Token, value: Expr },
+        Binary(BinaryExpr) => visit_binary_expr { left: Expr, operator: Token, right: Expr },
+        Call(CallExpr) => visit_call_expr { callee: Expr, paren: Token, arguments: Vec<Expr> },
+        Destructure(DestructureExpr) => visit_destructure_expr { pattern: Pattern, value: Expr },
+        Get(GetExpr) => visit_get_expr { object: Expr, name: Token },
+        Grouping(GroupingExpr) => visit_grouping_expr { expression: Expr },
+        Index(IndexExpr) => visit_index_expr { object: Expr, bracket: Token, index: Expr },
+        IndexSet(IndexSetExpr) => visit_index_set_expr {
+            object: Expr,
+            bracket: Token,
+            index: Expr,
+            value: Expr,
+        },
+        List(ListExpr) => visit_list_expr { bracket: Token, elements: Vec<Expr> },
+        Logical(LogicalExpr) => visit_logical_expr { left: Expr, operator: Token, right: Expr },
+        Map(MapExpr) => visit_map_expr { brace: Token, entries: Vec<(Expr, Expr)> },
+        Set(SetExpr) => visit_set_expr { object: Expr, name: Token, value: Expr },
+        Slice(SliceExpr) => visit_slice_expr {
+            object: Expr,
+            bracket: Token,
+            start: Option<Expr>,
+            end: Option<Expr>,
+        },
+        Ternary(TernaryExpr) => visit_ternary_expr {
+            condition: Expr,
+            then_branch: Expr,
+            else_branch: Expr,
+        },
+        Unary(UnaryExpr) => visit_unary_expr { operator: Token, right: Expr },
+    }
+    shared {
+        Lambda(LambdaExpr) => visit_lambda_expr {
+            params: Vec<Token>,
+            /// Whether the last parameter collects the remaining arguments into a list.
+            variadic: bool,
+            body: BlockStmt,
+        },
+    }
+    inline {
+        Literal(LiteralExpr) => visit_literal_expr {
+            #[cfg_attr(feature = "serde", serde(with = "literal"))]
+            value: Object,
+        },
+        Super(SuperExpr) => visit_super_expr { keyword: Token, method: Token },
+        This(ThisExpr) => visit_this_expr { keyword: Token },
+        Variable(VariableExpr) => visit_variable_expr { name: Token },
     }
 }
-#[derive(Clone, Debug)]
-pub enum Expr {
-    Assign(Box<AssignExpr>),
-    Binary(Box<BinaryExpr>),
-    Call(Box<CallExpr>),
-    Get(Box<GetExpr>),
-    Grouping(Box<GroupingExpr>),
-    Lambda(Box<LambdaExpr>),
-    Literal(LiteralExpr),
-    Logical(Box<LogicalExpr>),
-    Set(Box<SetExpr>),
-    Super(SuperExpr),
-    This(ThisExpr),
-    Ternary(Box<TernaryExpr>),
-    Unary(Box<UnaryExpr>),
-    Variable(VariableExpr),
-}
 
 impl Expr {
     pub fn to_hash(&self) -> u64 {
@@ -65,182 +65,86 @@ impl Expr {
     }
 }
 
-#[derive(Clone, Debug)]
-pub struct AssignExpr {
-    pub name: Token,
-    pub value: Expr,
-}
-
-impl AssignExpr {
-    pub fn new(name: Token, value: Expr) -> Self {
-        AssignExpr { name, value }
-    }
-}
-#[derive(Clone, Debug)]
-pub struct BinaryExpr {
-    pub left: Expr,
-    pub operator: Token,
-    pub right: Expr,
-}
-
-impl BinaryExpr {
-    pub fn new(left: Expr, operator: Token, right: Expr) -> Self {
-        BinaryExpr {
-            left,
-            operator,
-            right,
-        }
-    }
-}
-#[derive(Clone, Debug)]
-pub struct CallExpr {
-    pub callee: Expr,
-    pub paren: Token,
-    pub arguments: Vec<Expr>,
-}
-
-impl CallExpr {
-    pub fn new(callee: Expr, paren: Token, arguments: Vec<Expr>) -> Self {
-        CallExpr {
-            callee,
-            paren,
-            arguments,
-        }
-    }
-}
-#[derive(Clone, Debug)]
-pub struct GetExpr {
-    pub object: Expr,
-    pub name: Token,
-}
-
 impl GetExpr {
-    pub fn new(object: Expr, name: Token) -> Self {
-        GetExpr { object, name }
-    }
-}
-#[derive(Clone, Debug)]
-pub struct GroupingExpr {
-    pub expression: Expr,
-}
-
-impl GroupingExpr {
-    pub fn new(expression: Expr) -> Self {
-        GroupingExpr { expression }
-    }
-}
-
-#[derive(Clone, Debug)]
-pub struct LambdaExpr {
-    pub params: Vec<Token>,
-    pub body: BlockStmt,
-}
-
-impl LambdaExpr {
-    pub fn new(params: Vec<Token>, body: BlockStmt) -> Self {
-        LambdaExpr { params, body }
-    }
-}
-
-#[derive(Clone, Debug)]
-pub struct LiteralExpr {
-    pub value: Object,
-}
-
-impl LiteralExpr {
-    pub fn new(value: Object) -> Self {
-        LiteralExpr { value }
-    }
-}
-#[derive(Clone, Debug)]
-pub struct LogicalExpr {
-    pub left: Expr,
-    pub operator: Token,
-    pub right: Expr,
-}
-
-impl LogicalExpr {
-    pub fn new(left: Expr, operator: Token, right: Expr) -> Self {
-        Self {
-            left,
-            operator,
-            right,
+    /// Moves the object out, leaving `nil` in its place.
+    pub fn take_object(&mut self) -> Expr {
+        mem::replace(
+            &mut self.object,
+            Expr::Literal(LiteralExpr::new(Object::Nil)),
+        )
+    }
+}
+
+impl Drop for GetExpr {
+    // Generated code can chain thousands of properties, and dropping such a chain
+    // recursively could overflow the stack.
+    fn drop(&mut self) {
+        let mut object = self.take_object();
+        while let Expr::Get(mut get) = object {
+            object = get.take_object();
         }
     }
 }
-#[derive(Clone, Debug)]
-pub struct SetExpr {
-    pub object: Expr,
-    pub name: Token,
-    pub value: Expr,
-}
 
-impl SetExpr {
-    pub fn new(object: Expr, name: Token, value: Expr) -> Self {
-        Self {
-            object,
-            name,
-            value,
-        }
-    }
-}
+/// The names bound by a destructuring declaration or assignment.
 #[derive(Clone, Debug)]
-pub struct SuperExpr {
-    pub keyword: Token,
-    pub method: Token,
-}
-
-impl SuperExpr {
-    pub fn new(keyword: Token, method: Token) -> Self {
-        Self { keyword, method }
-    }
-}
-#[derive(Clone, Debug)]
-pub struct ThisExpr {
-    pub keyword: Token,
-}
-
-impl ThisExpr {
-    pub fn new(keyword: Token) -> Self {
-        Self { keyword }
-    }
-}
-
-#[derive(Clone, Debug)]
-pub struct TernaryExpr {
-    pub condition: Expr,
-    pub then_branch: Expr,
-    pub else_branch: Expr,
-}
-
-impl TernaryExpr {
-    pub fn new(condition: Expr, then_branch: Expr, else_branch: Expr) -> Self {
-        Self {
-            condition,
-            then_branch,
-            else_branch,
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Pattern {
+    /// `[a, b]`, bound to the elements of a list.
+    List { bracket: Token, names: Vec<Token> },
+    /// `{x, y}`, bound to the fields of an instance or the entries of a map.
+    Fields { brace: Token, names: Vec<Token> },
+}
+
+impl Pattern {
+    pub fn names(&self) -> &[Token] {
+        match self {
+            Pattern::List { names, .. } | Pattern::Fields { names, .. } => names,
         }
     }
 }
 
-#[derive(Clone, Debug)]
-pub struct UnaryExpr {
-    pub operator: Token,
-    pub right: Expr,
-}
-
-impl UnaryExpr {
-    pub fn new(operator: Token, right: Expr) -> Self {
-        UnaryExpr { operator, right }
-    }
-}
-#[derive(Clone, Debug)]
-pub struct VariableExpr {
-    pub name: Token,
-}
-
-impl VariableExpr {
-    pub fn new(name: Token) -> Self {
-        Self { name }
+/// Serializes the value of a literal, which is always `nil`, a boolean, a number or a string,
+/// as one of those rather than as a runtime object.
+#[cfg(feature = "serde")]
+mod literal {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer, ser::Error};
+
+    use crate::{atom::Atom, object::Object};
+
+    #[derive(Serialize, Deserialize)]
+    enum Literal {
+        Nil,
+        Boolean(bool),
+        Number(f64),
+        Integer(i64),
+        String(Atom),
+    }
+
+    pub fn serialize<S: Serializer>(value: &Object, serializer: S) -> Result<S::Ok, S::Error> {
+        let literal = match value {
+            Object::Nil => Literal::Nil,
+            Object::Boolean(value) => Literal::Boolean(*value),
+            Object::Number(value) => Literal::Number(*value),
+            Object::Integer(value) => Literal::Integer(*value),
+            Object::String(value) => Literal::String(value.clone()),
+            value => {
+                return Err(S::Error::custom(format!(
+                    "a {} can't be a literal",
+                    value.type_name()
+                )));
+            }
+        };
+        literal.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Object, D::Error> {
+        Ok(match Literal::deserialize(deserializer)? {
+            Literal::Nil => Object::Nil,
+            Literal::Boolean(value) => Object::Boolean(value),
+            Literal::Number(value) => Object::Number(value),
+            Literal::Integer(value) => Object::Integer(value),
+            Literal::String(value) => Object::String(value),
+        })
     }
 }
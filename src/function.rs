@@ -1,10 +1,12 @@
 use std::{cell::RefCell, fmt, rc::Rc};
 
 use crate::{
-    builtin_funcs::LoxCallable,
+    atom::Atom,
+    builtin_funcs::{LoxCallable, fmt_callable},
     environment::Environment,
     error::RuntimeException,
     expr::LambdaExpr,
+    gc,
     interpreter::Interpreter,
     object::Object,
     stmt::FunctionStmt,
@@ -12,6 +14,7 @@ use crate::{
 };
 
 #[derive(Copy, Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum FunctionType {
     #[default]
     None,
@@ -35,9 +38,59 @@ impl fmt::Display for FunctionType {
     }
 }
 
+/// Defines each parameter in `environment`. The rest parameter of a variadic function is
+/// bound to a list of the arguments left over after the others.
+fn bind_arguments(
+    environment: &mut Environment,
+    params: &[Token],
+    variadic: bool,
+    mut args: Vec<Object>,
+) {
+    if let (true, Some(rest)) = (variadic, params.last()) {
+        let rest_args = args.split_off((params.len() - 1).min(args.len()));
+        environment.define(rest.atom(), Object::new_list(rest_args));
+    }
+    for (param, arg) in params.iter().zip(args) {
+        environment.define(param.atom(), arg);
+    }
+}
+
+/// The call of `return f(...)`, made by the function returning it once its body is left
+/// rather than by the `return`, so that recursion in tail position runs in constant stack
+/// space. Returns inside a `try` make their calls as usual, to keep them inside it.
+#[derive(Debug)]
+pub struct TailCall {
+    pub callee: Rc<dyn LoxCallable>,
+    pub arguments: Vec<Object>,
+    /// Where the errors of natives are located.
+    pub paren: Token,
+}
+
+/// Makes the tail calls raised by the body of a function, one after the other, until one
+/// returns.
+fn run_tail_calls(
+    interpreter: &mut Interpreter,
+    mut result: Result<Object, RuntimeException>,
+) -> Result<Object, RuntimeException> {
+    while let Err(RuntimeException::TailCall(call)) = result {
+        let TailCall {
+            callee,
+            arguments,
+            paren,
+        } = *call;
+        result = callee
+            .tail_call(interpreter, arguments)
+            .map_err(|error| match error {
+                RuntimeException::Error(error) => RuntimeException::Error(error.or_at(&paren)),
+                _ => error,
+            });
+    }
+    result
+}
+
 #[derive(Clone)]
 pub struct LoxFunction {
-    declaration: FunctionStmt,
+    declaration: Rc<FunctionStmt>,
     closure: Rc<RefCell<Environment>>,
     pub kind: FunctionType,
 }
@@ -53,10 +106,11 @@ impl fmt::Debug for LoxFunction {
 
 impl LoxFunction {
     pub fn new(
-        declaration: FunctionStmt,
+        declaration: Rc<FunctionStmt>,
         closure: Rc<RefCell<Environment>>,
         kind: FunctionType,
     ) -> Self {
+        gc::track_environment(&closure);
         Self {
             declaration,
             closure,
@@ -64,6 +118,10 @@ impl LoxFunction {
         }
     }
 
+    pub fn is_memo(&self) -> bool {
+        self.declaration.memo
+    }
+
     pub fn bind(&self, instance: Object) -> LoxFunction {
         if let Object::Instance(_) = instance {
             let mut environment = Environment::new(Some(self.closure.clone()));
@@ -84,13 +142,25 @@ impl LoxCallable for LoxFunction {
         &self,
         interpreter: &mut Interpreter,
         args: Vec<Object>,
+    ) -> Result<Object, RuntimeException> {
+        let result = self.tail_call(interpreter, args);
+        run_tail_calls(interpreter, result)
+    }
+
+    fn tail_call(
+        &self,
+        interpreter: &mut Interpreter,
+        args: Vec<Object>,
     ) -> Result<Object, RuntimeException> {
         let mut environment = Environment::new(Some(self.closure.clone()));
-        for (i, param) in self.declaration.params.iter().enumerate().take(args.len()) {
-            environment.define(&param.value.to_string(), args[i].clone());
-        }
+        bind_arguments(
+            &mut environment,
+            &self.declaration.params,
+            self.declaration.variadic,
+            args,
+        );
 
-        match interpreter.execute_block(
+        match interpreter.execute_body(
             &self.declaration.body.statements,
             Rc::new(RefCell::new(environment)),
         ) {
@@ -102,7 +172,7 @@ impl LoxCallable for LoxFunction {
                             0,
                             &Token::new(
                                 TokenIdentity::This,
-                                TokenValue::String("this".to_string()),
+                                TokenValue::String(Atom::from("this")),
                                 0,
                                 0,
                             ),
@@ -113,7 +183,10 @@ impl LoxCallable for LoxFunction {
                 }
             }
             Err(e) => match e {
-                RuntimeException::Error(err) => Err(RuntimeException::Error(err)),
+                RuntimeException::Error(_)
+                | RuntimeException::Throw(_)
+                | RuntimeException::Exit(_)
+                | RuntimeException::TailCall(_) => Err(e),
                 RuntimeException::Return(ret) => {
                     if self.kind == FunctionType::Initializer {
                         self.closure
@@ -122,7 +195,7 @@ impl LoxCallable for LoxFunction {
                                 0,
                                 &Token::new(
                                     TokenIdentity::This,
-                                    TokenValue::String("this".to_string()),
+                                    TokenValue::String(Atom::from("this")),
                                     0,
                                     0,
                                 ),
@@ -136,22 +209,51 @@ impl LoxCallable for LoxFunction {
             },
         }
     }
+
+    fn arity(&self) -> usize {
+        self.declaration.params.len() - usize::from(self.declaration.variadic)
+    }
+
+    fn is_variadic(&self) -> bool {
+        self.declaration.variadic
+    }
+
+    fn name(&self) -> Option<String> {
+        Some(self.declaration.name.value.to_string())
+    }
+
+    fn closure(&self) -> Option<Rc<RefCell<Environment>>> {
+        Some(self.closure.clone())
+    }
 }
 
 impl fmt::Display for LoxFunction {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "<fn {}>", self.declaration.name.value)
+        fmt_callable(self, f)
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone)]
 pub struct LambdaFunction {
-    declaration: LambdaExpr,
+    declaration: Rc<LambdaExpr>,
+    closure: Rc<RefCell<Environment>>,
+}
+
+impl fmt::Debug for LambdaFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LambdaFunction")
+            .field("declaration", &self.declaration)
+            .finish_non_exhaustive()
+    }
 }
 
 impl LambdaFunction {
-    pub fn new(declaration: LambdaExpr) -> Self {
-        LambdaFunction { declaration }
+    pub fn new(declaration: Rc<LambdaExpr>, closure: Rc<RefCell<Environment>>) -> Self {
+        gc::track_environment(&closure);
+        LambdaFunction {
+            declaration,
+            closure,
+        }
     }
 }
 
@@ -161,21 +263,52 @@ impl LoxCallable for LambdaFunction {
         interpreter: &mut Interpreter,
         args: Vec<Object>,
     ) -> Result<Object, RuntimeException> {
-        let mut environment = Environment::new(Some(interpreter.global.clone()));
+        let result = self.tail_call(interpreter, args);
+        run_tail_calls(interpreter, result)
+    }
 
-        for (i, param) in self.declaration.params.iter().enumerate() {
-            environment.define(&param.value.to_string(), args[i].clone());
-        }
+    fn tail_call(
+        &self,
+        interpreter: &mut Interpreter,
+        args: Vec<Object>,
+    ) -> Result<Object, RuntimeException> {
+        let mut environment = Environment::new(Some(self.closure.clone()));
+        bind_arguments(
+            &mut environment,
+            &self.declaration.params,
+            self.declaration.variadic,
+            args,
+        );
 
-        interpreter.execute_block(
+        match interpreter.execute_body(
             &self.declaration.body.statements,
             Rc::new(RefCell::new(environment)),
-        )
+        ) {
+            Ok(_) => Ok(Object::Nil),
+            Err(RuntimeException::Return(ret)) => Ok(ret.value),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn arity(&self) -> usize {
+        self.declaration.params.len() - usize::from(self.declaration.variadic)
+    }
+
+    fn is_variadic(&self) -> bool {
+        self.declaration.variadic
+    }
+
+    fn name(&self) -> Option<String> {
+        None
+    }
+
+    fn closure(&self) -> Option<Rc<RefCell<Environment>>> {
+        Some(self.closure.clone())
     }
 }
 
 impl fmt::Display for LambdaFunction {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "<fn lambda>")
+        fmt_callable(self, f)
     }
 }
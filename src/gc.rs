@@ -0,0 +1,371 @@
+//! The cycle collector. Values are reference counted, so a cycle, like a closure stored in
+//! the environment it captured or two instances pointing at each other, keeps itself alive
+//! after the program lets go of it. The collector finds such cycles and empties the
+//! containers in them, after which reference counting frees them as usual.
+//!
+//! It works by trial deletion, as CPython does. Starting from the containers that could be
+//! part of a cycle, it walks everything they reach and subtracts, from the reference count of
+//! each object, the references coming from the objects walked. Those with references left are
+//! held from somewhere the collector can't see, such as a variable of the running program or
+//! the interpreter itself, and keep alive everything they reach. The rest is garbage. An
+//! object the collector can't look into, because it's borrowed, is treated as held, so a
+//! collection can keep too much but never frees something in use.
+
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    mem,
+    rc::{Rc, Weak},
+};
+
+use crate::{
+    builtin_funcs::LoxCallable,
+    class::{LoxClass, LoxInstance},
+    environment::Environment,
+    function::LoxFunction,
+    map::LoxMap,
+    object::{LoxModule, Object},
+};
+
+/// How many containers are tracked before the first collection.
+const MIN_THRESHOLD: usize = 10_000;
+
+thread_local! {
+    /// The containers that could be part of a cycle, since the last collection.
+    static TRACKED: RefCell<Vec<Tracked>> = const { RefCell::new(Vec::new()) };
+    /// The number of containers tracked that makes the next collection due.
+    static THRESHOLD: Cell<usize> = const { Cell::new(MIN_THRESHOLD) };
+}
+
+/// A container tracked without keeping it alive.
+enum Tracked {
+    Environment(Weak<RefCell<Environment>>),
+    Instance(Weak<RefCell<LoxInstance>>),
+    List(Weak<RefCell<Vec<Object>>>),
+    Map(Weak<RefCell<LoxMap>>),
+}
+
+impl Tracked {
+    fn upgrade(&self) -> Option<Node> {
+        match self {
+            Tracked::Environment(environment) => environment.upgrade().map(Node::Environment),
+            Tracked::Instance(instance) => instance.upgrade().map(Node::Instance),
+            Tracked::List(list) => list.upgrade().map(Node::List),
+            Tracked::Map(map) => map.upgrade().map(Node::Map),
+        }
+    }
+
+    fn is_alive(&self) -> bool {
+        match self {
+            Tracked::Environment(environment) => environment.strong_count() > 0,
+            Tracked::Instance(instance) => instance.strong_count() > 0,
+            Tracked::List(list) => list.strong_count() > 0,
+            Tracked::Map(map) => map.strong_count() > 0,
+        }
+    }
+}
+
+/// Tracks an instance, list or map, which could end up in a cycle.
+pub fn track(object: &Object) {
+    let tracked = match object {
+        Object::Instance(instance) => Tracked::Instance(Rc::downgrade(instance)),
+        Object::List(list) => Tracked::List(Rc::downgrade(list)),
+        Object::Map(map) => Tracked::Map(Rc::downgrade(map)),
+        _ => return,
+    };
+    TRACKED.with_borrow_mut(|tracked_objects| tracked_objects.push(tracked));
+}
+
+/// Tracks an environment captured by a function or a class. The other environments can't be
+/// part of a cycle without one of those.
+pub fn track_environment(environment: &Rc<RefCell<Environment>>) {
+    TRACKED.with_borrow_mut(|tracked| {
+        tracked.push(Tracked::Environment(Rc::downgrade(environment)));
+    });
+}
+
+/// Whether enough containers were made since the last collection to run another.
+pub fn is_due() -> bool {
+    TRACKED.with_borrow(Vec::len) >= THRESHOLD.get()
+}
+
+/// An object that holds references to others.
+#[derive(Clone)]
+enum Node {
+    Environment(Rc<RefCell<Environment>>),
+    Instance(Rc<RefCell<LoxInstance>>),
+    List(Rc<RefCell<Vec<Object>>>),
+    Map(Rc<RefCell<LoxMap>>),
+    Class(Rc<LoxClass>),
+    Function(Rc<dyn LoxCallable>),
+    /// A method in the table of its class, which isn't bound yet.
+    Method(Rc<LoxFunction>),
+    Module(Rc<LoxModule>),
+}
+
+impl Node {
+    fn of(object: &Object) -> Option<Node> {
+        match object {
+            Object::Instance(instance) => Some(Node::Instance(instance.clone())),
+            Object::List(list) => Some(Node::List(list.clone())),
+            Object::Map(map) => Some(Node::Map(map.clone())),
+            Object::Class(class) => Some(Node::Class(class.clone())),
+            Object::Function(function) => Some(Node::Function(function.clone())),
+            Object::Module(module) => Some(Node::Module(module.clone())),
+            _ => None,
+        }
+    }
+
+    fn address(&self) -> *const () {
+        match self {
+            Node::Environment(environment) => Rc::as_ptr(environment).cast(),
+            Node::Instance(instance) => Rc::as_ptr(instance).cast(),
+            Node::List(list) => Rc::as_ptr(list).cast(),
+            Node::Map(map) => Rc::as_ptr(map).cast(),
+            Node::Class(class) => Rc::as_ptr(class).cast(),
+            Node::Function(function) => Rc::as_ptr(function).cast(),
+            Node::Method(method) => Rc::as_ptr(method).cast(),
+            Node::Module(module) => Rc::as_ptr(module).cast(),
+        }
+    }
+
+    fn strong_count(&self) -> usize {
+        match self {
+            Node::Environment(environment) => Rc::strong_count(environment),
+            Node::Instance(instance) => Rc::strong_count(instance),
+            Node::List(list) => Rc::strong_count(list),
+            Node::Map(map) => Rc::strong_count(map),
+            Node::Class(class) => Rc::strong_count(class),
+            Node::Function(function) => Rc::strong_count(function),
+            Node::Method(method) => Rc::strong_count(method),
+            Node::Module(module) => Rc::strong_count(module),
+        }
+    }
+
+    /// Reports each reference the object holds to `tracer`. Returns `false`, reporting
+    /// nothing, when the object is borrowed.
+    fn trace(&self, tracer: &mut Tracer) -> bool {
+        match self {
+            Node::Environment(environment) => {
+                let Ok(environment) = environment.try_borrow() else {
+                    return false;
+                };
+                if let Some(enclosing) = &environment.enclosing {
+                    tracer.environment(enclosing);
+                }
+                environment
+                    .values
+                    .values()
+                    .for_each(|value| tracer.object(value));
+            }
+            Node::Instance(instance) => {
+                let Ok(instance) = instance.try_borrow() else {
+                    return false;
+                };
+                instance.trace(tracer);
+            }
+            Node::List(list) => {
+                let Ok(list) = list.try_borrow() else {
+                    return false;
+                };
+                list.iter().for_each(|element| tracer.object(element));
+            }
+            Node::Map(map) => {
+                let Ok(map) = map.try_borrow() else {
+                    return false;
+                };
+                for (key, value) in map.entries() {
+                    tracer.object(key);
+                    tracer.object(value);
+                }
+            }
+            Node::Class(class) => return class.trace(tracer),
+            Node::Function(function) => {
+                if let Some(closure) = function.closure() {
+                    tracer.environment(&closure);
+                }
+            }
+            Node::Method(method) => {
+                if let Some(closure) = method.closure() {
+                    tracer.environment(&closure);
+                }
+            }
+            Node::Module(module) => module.exports().for_each(|value| tracer.object(value)),
+        }
+        true
+    }
+
+    /// Drops the references a garbage container holds, breaking the cycles it's part of.
+    fn clear(&self) {
+        match self {
+            Node::Environment(environment) => {
+                if let Ok(mut environment) = environment.try_borrow_mut() {
+                    environment.values.clear();
+                    environment.enclosing = None;
+                }
+            }
+            Node::Instance(instance) => {
+                if let Ok(mut instance) = instance.try_borrow_mut() {
+                    instance.clear();
+                }
+            }
+            Node::List(list) => {
+                if let Ok(mut list) = list.try_borrow_mut() {
+                    list.clear();
+                }
+            }
+            Node::Map(map) => {
+                if let Ok(mut map) = map.try_borrow_mut() {
+                    *map = LoxMap::new();
+                }
+            }
+            Node::Class(class) => class.clear_statics(),
+            Node::Function(_) | Node::Method(_) | Node::Module(_) => {}
+        }
+    }
+}
+
+/// Collects the objects reachable from the tracked containers, with the references between
+/// them.
+#[derive(Default)]
+pub struct Tracer {
+    nodes: Vec<Node>,
+    indices: HashMap<*const (), usize>,
+    /// The objects referenced by the one being traced, by index in `nodes`.
+    references: Vec<usize>,
+}
+
+impl Tracer {
+    pub fn object(&mut self, object: &Object) {
+        if let Some(node) = Node::of(object) {
+            self.node(node);
+        }
+    }
+
+    pub fn environment(&mut self, environment: &Rc<RefCell<Environment>>) {
+        self.node(Node::Environment(environment.clone()));
+    }
+
+    pub fn class(&mut self, class: &Rc<LoxClass>) {
+        self.node(Node::Class(class.clone()));
+    }
+
+    pub fn method(&mut self, method: &Rc<LoxFunction>) {
+        self.node(Node::Method(method.clone()));
+    }
+
+    fn node(&mut self, node: Node) {
+        let index = match self.indices.get(&node.address()) {
+            Some(index) => *index,
+            None => {
+                self.indices.insert(node.address(), self.nodes.len());
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        };
+        self.references.push(index);
+    }
+}
+
+/// Frees the cycles the program can no longer reach. Returns the number of objects freed.
+pub fn collect() -> usize {
+    let candidates = TRACKED.with_borrow_mut(|tracked| {
+        tracked.retain(Tracked::is_alive);
+        tracked
+            .iter()
+            .filter_map(Tracked::upgrade)
+            .collect::<Vec<_>>()
+    });
+    let mut tracer = Tracer::default();
+    candidates.into_iter().for_each(|node| tracer.node(node));
+    tracer.references.clear();
+
+    let mut references = Vec::new();
+    let mut borrowed = Vec::new();
+    let mut index = 0;
+    while index < tracer.nodes.len() {
+        let node = tracer.nodes[index].clone();
+        borrowed.push(!node.trace(&mut tracer));
+        references.push(mem::take(&mut tracer.references));
+        index += 1;
+    }
+
+    // Every node is held once by the tracer, on top of the references counted.
+    let mut held = tracer
+        .nodes
+        .iter()
+        .map(|node| node.strong_count() as isize - 1)
+        .collect::<Vec<_>>();
+    for &target in references.iter().flatten() {
+        held[target] -= 1;
+    }
+
+    let mut reachable = vec![false; tracer.nodes.len()];
+    let mut pending = (0..tracer.nodes.len())
+        .filter(|&index| held[index] > 0 || borrowed[index])
+        .collect::<Vec<_>>();
+    while let Some(index) = pending.pop() {
+        if !mem::replace(&mut reachable[index], true) {
+            pending.extend(&references[index]);
+        }
+    }
+
+    let mut collected = 0;
+    for (node, _) in tracer
+        .nodes
+        .iter()
+        .zip(&reachable)
+        .filter(|(_, reachable)| !**reachable)
+    {
+        node.clear();
+        collected += 1;
+    }
+    drop(tracer);
+
+    TRACKED.with_borrow_mut(|tracked| {
+        tracked.retain(Tracked::is_alive);
+        THRESHOLD.set(MIN_THRESHOLD.max(2 * tracked.len()));
+    });
+    collected
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A list holding itself.
+    fn cycle() -> (Object, Weak<RefCell<Vec<Object>>>) {
+        let list = Object::new_list(Vec::new());
+        let Object::List(elements) = &list else {
+            unreachable!()
+        };
+        elements.borrow_mut().push(list.clone());
+        let weak = Rc::downgrade(elements);
+        (list, weak)
+    }
+
+    #[test]
+    fn test_collect() {
+        let (dropped, dropped_weak) = cycle();
+        let (kept, kept_weak) = cycle();
+        drop(dropped);
+        assert!(dropped_weak.upgrade().is_some());
+
+        assert_eq!(collect(), 1);
+        assert!(dropped_weak.upgrade().is_none());
+        assert!(kept_weak.upgrade().is_some());
+        assert_eq!(collect(), 0);
+
+        // What a live object reaches stays, however it's reached.
+        let (reached, reached_weak) = cycle();
+        let Object::List(elements) = &kept else {
+            unreachable!()
+        };
+        elements.borrow_mut().push(reached);
+        assert_eq!(collect(), 0);
+        assert!(reached_weak.upgrade().is_some());
+        drop(kept);
+        assert_eq!(collect(), 2);
+        assert!(kept_weak.upgrade().is_none() && reached_weak.upgrade().is_none());
+    }
+}
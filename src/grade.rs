@@ -0,0 +1,270 @@
+//! Runs scripts and compares what they print with an expected output, the way the test suite
+//! checks `tests/scripts`.
+
+use std::{
+    cell::RefCell,
+    fmt::Write as _,
+    fs,
+    io::{self, BufWriter, Write},
+    path::Path,
+    rc::Rc,
+};
+
+use crate::{
+    capture::CaptureWriter,
+    error::{LoxError, RuntimeException},
+    interpreter::{Interpreter, OptLevel},
+    parser::Dialect,
+    timings::run_timed,
+};
+
+/// The lines of context around each change in a diff.
+const CONTEXT: usize = 3;
+
+/// The grammar a script declares it's written in, with `// dialect: lox` for the book's.
+pub fn dialect(script: &str) -> Dialect {
+    match script
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("// dialect:"))
+        .map(str::trim)
+    {
+        Some("lox") => Dialect::Lox,
+        _ => Dialect::Rlox,
+    }
+}
+
+/// The error a script declares it ends with, written `// expect-error: Kind @ line:column`.
+pub fn expected_error(script: &str) -> Option<String> {
+    script
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("// expect-error:"))
+        .map(|expectation| expectation.trim().to_string())
+}
+
+/// Describes an error in the format of [`expected_error`].
+pub fn describe(error: &LoxError) -> String {
+    let error = match error {
+        LoxError::Resolving(error) | LoxError::Runtime(RuntimeException::Error(error)) => error,
+        _ => return format!("{error}"),
+    };
+    match error.token() {
+        Some(token) => format!("{} @ {}:{}", error.kind().name(), token.line, token.column),
+        None => error.kind().name().to_string(),
+    }
+}
+
+/// Runs `source`, the script at `path`, optimized at `opt_level`, and returns what it printed
+/// followed by the error it failed with, if any. A script declaring its error with
+/// `// expect-error:` is checked against the kind and location of the error instead, which is
+/// then left out of the output; a mismatch is returned as `Err`. A script declaring
+/// `// dialect: lox` is parsed and run as the book's Lox. A script ending with
+/// `exit(status)` gets its status appended to the output unless it's 0.
+///
+/// The output is buffered as in the `rlox` binary, so whatever the script prints before
+/// failing has to be flushed by the interpreter ahead of the error. The script's input is
+/// empty, so that `readLine` returns `nil` rather than waiting for stdin, and its globals are
+/// those of [`Interpreter::without_features`], whatever features are built.
+pub fn run_script(path: &Path, source: &str, opt_level: OptLevel) -> Result<Vec<u8>, String> {
+    let mut output = CaptureWriter::new();
+    let writer = Rc::new(RefCell::new(BufWriter::new(output.clone())));
+    let module_dir = path.parent().unwrap_or(Path::new("."));
+    let mut interpreter = Interpreter::without_features(writer)
+        .with_reader(Rc::new(RefCell::new(io::empty())))
+        .with_opt_level(opt_level)
+        .with_dialect(dialect(source))
+        .with_module_dir(module_dir);
+    let (result, _) = run_timed(source, &mut interpreter);
+    match (expected_error(source), result) {
+        (Some(expected), Err(error)) if expected != describe(&error) => Err(format!(
+            "Expected error {expected}, but got {}",
+            describe(&error)
+        )),
+        (Some(expected), Ok(_)) => Err(format!(
+            "Expected error {expected}, but the script succeeded"
+        )),
+        (None, Err(LoxError::Runtime(RuntimeException::Exit(status)))) => {
+            // A successful exit looks like reaching the end of the script.
+            if status != 0 {
+                writeln!(output, "{}", RuntimeException::Exit(status)).unwrap();
+            }
+            Ok(output.bytes())
+        }
+        (None, Err(error)) => {
+            writeln!(output, "{error}").unwrap();
+            Ok(output.bytes())
+        }
+        _ => Ok(output.bytes()),
+    }
+}
+
+/// What a script printed next to what it was expected to print.
+#[derive(Debug)]
+pub struct Grade {
+    pub expected: String,
+    pub actual: String,
+}
+
+impl Grade {
+    pub fn passed(&self) -> bool {
+        self.expected == self.actual
+    }
+
+    /// A unified diff from the expected output to the actual one, empty if they match.
+    pub fn diff(&self) -> String {
+        unified_diff(&self.expected, &self.actual)
+    }
+}
+
+/// Runs the script at `script` and compares its output with the file at `expected_output`.
+pub fn grade(script: &Path, expected_output: &Path) -> io::Result<Grade> {
+    let source = fs::read_to_string(script)?;
+    let expected = fs::read_to_string(expected_output)?;
+    let actual = match run_script(script, &source, OptLevel::default()) {
+        Ok(output) => String::from_utf8_lossy(&output).into_owned(),
+        Err(mismatch) => format!("{mismatch}\n"),
+    };
+    Ok(Grade { expected, actual })
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Change {
+    Same,
+    Removed,
+    Added,
+}
+
+/// Pairs each line of `old` and `new` with how it changed, following a longest common
+/// subsequence of their lines.
+fn line_changes<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<(Change, &'a str)> {
+    // `common[i][j]` is the length of the longest common subsequence of `old[i..]` and
+    // `new[j..]`.
+    let mut common = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i][j] = if old[i] == new[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    let mut changes = Vec::new();
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            changes.push((Change::Same, old[i]));
+            i += 1;
+            j += 1;
+        } else if j == new.len() || (i < old.len() && common[i + 1][j] >= common[i][j + 1]) {
+            changes.push((Change::Removed, old[i]));
+            i += 1;
+        } else {
+            changes.push((Change::Added, new[j]));
+            j += 1;
+        }
+    }
+    changes
+}
+
+fn unified_diff(expected: &str, actual: &str) -> String {
+    if expected == actual {
+        return String::new();
+    }
+    let old: Vec<&str> = expected.lines().collect();
+    let new: Vec<&str> = actual.lines().collect();
+    let changes = line_changes(&old, &new);
+
+    let mut diff = String::from("--- expected\n+++ actual\n");
+    let mut start = 0;
+    while let Some(first) = changes[start..]
+        .iter()
+        .position(|(change, _)| *change != Change::Same)
+    {
+        // A hunk runs from the context before its first change to the context after its
+        // last one, merging changes closer than twice the context.
+        let first = start + first;
+        let mut end = first;
+        let mut last = first;
+        while end < changes.len() && end - last <= 2 * CONTEXT {
+            if changes[end].0 != Change::Same {
+                last = end;
+            }
+            end += 1;
+        }
+        let hunk_start = first.saturating_sub(CONTEXT).max(start);
+        let hunk_end = (last + CONTEXT + 1).min(changes.len());
+
+        let old_start = changes[..hunk_start]
+            .iter()
+            .filter(|(change, _)| *change != Change::Added)
+            .count();
+        let new_start = changes[..hunk_start]
+            .iter()
+            .filter(|(change, _)| *change != Change::Removed)
+            .count();
+        let hunk = &changes[hunk_start..hunk_end];
+        let old_len = hunk.iter().filter(|(c, _)| *c != Change::Added).count();
+        let new_len = hunk.iter().filter(|(c, _)| *c != Change::Removed).count();
+        writeln!(
+            diff,
+            "@@ -{},{old_len} +{},{new_len} @@",
+            old_start + 1,
+            new_start + 1
+        )
+        .unwrap();
+        for (change, line) in hunk {
+            let marker = match change {
+                Change::Same => ' ',
+                Change::Removed => '-',
+                Change::Added => '+',
+            };
+            writeln!(diff, "{marker}{line}").unwrap();
+        }
+        start = hunk_end;
+    }
+    if expected.lines().eq(actual.lines()) {
+        // Only the final newline differs.
+        diff.push_str("\\ No newline at end of file\n");
+    }
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unified_diff() {
+        assert_eq!(unified_diff("a\nb\n", "a\nb\n"), "");
+        assert_eq!(
+            unified_diff("1\n2\n3\n4\n5\n6\n7\n8\n", "1\n2\n3\n4\nfive\n6\n7\n8\n"),
+            "--- expected\n+++ actual\n@@ -2,7 +2,7 @@\n 2\n 3\n 4\n-5\n+five\n 6\n 7\n 8\n"
+        );
+        assert_eq!(
+            unified_diff("a\n", "a\nb\n"),
+            "--- expected\n+++ actual\n@@ -1,1 +1,2 @@\n a\n+b\n"
+        );
+        let far_apart = unified_diff(
+            "a\nb\nc\nd\ne\nf\ng\nh\ni\nj\n",
+            "A\nb\nc\nd\ne\nf\ng\nh\ni\nJ\n",
+        );
+        assert_eq!(far_apart.matches("@@ -").count(), 2);
+    }
+
+    #[test]
+    fn test_exit_status() {
+        let output = |source: &str| {
+            run_script(Path::new("exit.lox"), source, OptLevel::default())
+                .map(|output| String::from_utf8(output).unwrap())
+        };
+        assert_eq!(
+            output("print(1); exit(0); print(2);"),
+            Ok("1\n".to_string())
+        );
+        assert_eq!(
+            output("print(1); exit(2);"),
+            Ok("1\nExited with status 2.\n".to_string())
+        );
+    }
+}
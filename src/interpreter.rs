@@ -1,61 +1,806 @@
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    fs,
+    io::{self, BufRead, BufReader},
+    mem,
+    ops::Range,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
 
 use crate::{
-    builtin_funcs::{ClockFunction, LoxCallable},
-    class::LoxClass,
-    environment::Environment,
-    error::{RuntimeError, RuntimeException, RuntimeReturn},
+    atom::Atom,
+    builtin_funcs::{LoxCallable, NativeFunction, builtins, feature_builtins},
+    capture::CaptureWriter,
+    class::{LoxClass, LoxInstance},
+    environment::{self, Environment},
+    error::{LoxError, RuntimeError, RuntimeErrorKind, RuntimeException, RuntimeReturn},
     expr::{
-        AssignExpr, BinaryExpr, CallExpr, Expr, ExprVisitor, GetExpr, GroupingExpr, LambdaExpr,
-        LiteralExpr, LogicalExpr, SetExpr, SuperExpr, TernaryExpr, ThisExpr, UnaryExpr,
+        AssignExpr, BinaryExpr, CallExpr, DestructureExpr, Expr, ExprVisitor, GetExpr,
+        GroupingExpr, IndexExpr, IndexSetExpr, LambdaExpr, ListExpr, LiteralExpr, LogicalExpr,
+        MapExpr, Pattern, SetExpr, SliceExpr, SuperExpr, TernaryExpr, ThisExpr, UnaryExpr,
         VariableExpr,
     },
-    function::{FunctionType, LambdaFunction, LoxFunction},
-    object::Object,
+    function::{FunctionType, LambdaFunction, LoxFunction, TailCall},
+    gc,
+    map::LoxMap,
+    object::{LoxModule, Object},
+    parser::{Dialect, Parser},
+    random::Random,
+    resolver::Resolver,
+    rope::{Rope, Strand},
+    scanner::Scanner,
     stmt::{
-        BlockStmt, ClassStmt, ExpressionStmt, FunctionStmt, IfStmt, PrintStmt, ReturnStmt, Stmt,
-        StmtVisitor, VarStmt, WhileStmt,
+        BlockStmt, BreakStmt, ClassStmt, ContinueStmt, DestructureStmt, ExportStmt, ExpressionStmt,
+        ForInStmt, ForStmt, FunctionStmt, IfStmt, ImportStmt, PrintStmt, ReturnStmt, Stmt,
+        StmtVisitor, SwitchStmt, ThrowStmt, TryStmt, VarStmt, WhileStmt,
     },
+    string_methods::StringMethod,
+    timings::run_timed,
     token::{Token, TokenIdentity, TokenValue},
 };
 
+/// What reading a variable declared without an initializer does.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum UninitializedRead {
+    /// Raise a runtime error.
+    #[default]
+    Error,
+    /// Evaluate to `nil`, as in the book.
+    Nil,
+}
+
+/// What declaring a global that already exists does, for example when a file is run again
+/// in the same interpreter.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum GlobalRedefinition {
+    /// Replace the old value silently, as in the book.
+    #[default]
+    Overwrite,
+    /// Raise a runtime error.
+    Error,
+    /// Shadow the old value with a warning: the code run afterwards sees the new one, while
+    /// the functions declared before keep seeing the old one.
+    Warn,
+}
+
+/// The optimizations applied to programs once they're resolved.
+#[derive(Copy, Clone, Debug, Default, PartialEq, PartialOrd)]
+pub enum OptLevel {
+    /// Run programs as written.
+    None,
+    /// Replace the reads of locals initialized with a literal and never assigned to with the
+    /// literal, and drop the statements that can't run.
+    #[default]
+    Constants,
+    /// Also inline the calls to functions that only return an expression of their parameters,
+    /// such as `fun square(x) = x * x;`. Experimental: it assumes that functions aren't
+    /// redefined by later programs run in the same interpreter, and the arguments of an
+    /// inlined call may raise their errors in a different order.
+    Inline,
+}
+
+/// Counts of what the interpreter did since it was made, returned by `stats()`. The work of
+/// the prelude and the standard library isn't counted.
+#[derive(Clone, Debug, Default)]
+pub struct Stats {
+    pub statements: usize,
+    pub calls: usize,
+    /// The calls currently running.
+    pub call_depth: usize,
+    pub peak_call_depth: usize,
+    /// The runs of the cycle collector, and the objects they freed.
+    pub collections: usize,
+    pub collected: usize,
+    /// The environments made on this thread before counting started.
+    environments_before: usize,
+}
+
+impl Stats {
+    fn new() -> Self {
+        Self {
+            environments_before: environment::allocated(),
+            ..Self::default()
+        }
+    }
+
+    /// The environments made since counting started.
+    pub fn environments(&self) -> usize {
+        environment::allocated() - self.environments_before
+    }
+}
+
+/// Lox source for the classes every program starts with. `line` is filled in by `throw`, or
+/// with the location of a runtime error caught as an `Error`, and `kind` with the name of
+/// that error's kind, such as `"DivisionByZero"`.
+const PRELUDE: &str = "
+class Error {
+  init(message) {
+    this.message = message;
+    this.line = nil;
+    this.kind = nil;
+  }
+}
+";
+
+/// Helpers written in Lox, such as `max`, `len` and `reduce`, that every interpreter loads
+/// with [`Interpreter::load_prelude`] when the `stdlib` feature is on.
+#[cfg(feature = "stdlib")]
+pub const STDLIB: &str = include_str!("stdlib.lox");
+
+/// A program every interpreter runs before user code, parsed and resolved once per thread
+/// rather than by each interpreter. Its syntax tree is reference counted, so threads can't
+/// share it.
+struct Compiled {
+    statements: Vec<Stmt>,
+    locals: HashMap<u64, usize>,
+}
+
+impl Compiled {
+    fn new(source: &str, optimize: bool) -> Self {
+        PARSES.set(PARSES.get() + 1);
+        let mut interpreter = Interpreter::without_prelude(Rc::new(RefCell::new(io::sink())));
+        let mut statements = Parser::new(Scanner::new(source).collect())
+            .parse()
+            .unwrap_or_else(|e| panic!("Invalid prelude: {e}"));
+        let mut resolver = Resolver::new(&mut interpreter);
+        resolver
+            .resolve_stmts(&statements)
+            .unwrap_or_else(|e| panic!("Invalid prelude: {e}"));
+        if optimize {
+            resolver.optimize(&mut statements);
+        }
+        Self {
+            statements,
+            locals: interpreter.locals,
+        }
+    }
+}
+
+thread_local! {
+    static PRELUDE_AST: Rc<Compiled> = Rc::new(Compiled::new(PRELUDE, false));
+    #[cfg(feature = "stdlib")]
+    static STDLIB_AST: Rc<Compiled> = Rc::new(Compiled::new(STDLIB, true));
+    /// The preludes parsed on this thread so far.
+    static PARSES: Cell<usize> = const { Cell::new(0) };
+}
+
 pub struct Interpreter {
     pub global: Rc<RefCell<Environment>>,
     pub environment: Rc<RefCell<Environment>>,
     pub locals: HashMap<u64, usize>,
     pub writer: Rc<RefCell<dyn std::io::Write>>,
+    /// Where warnings go, stderr unless set with [`Interpreter::with_diagnostics`].
+    pub diagnostics: Rc<RefCell<dyn std::io::Write>>,
+    /// Where `readLine` reads from, stdin unless set with [`Interpreter::with_reader`].
+    pub reader: Rc<RefCell<dyn BufRead>>,
+    pub uninitialized_read: UninitializedRead,
+    pub global_redefinition: GlobalRedefinition,
+    pub opt_level: OptLevel,
+    /// The grammar of the programs run, and of the modules they import.
+    pub dialect: Dialect,
+    /// Whether keywords are matched regardless of case, as in `IF`.
+    pub case_insensitive_keywords: bool,
+    /// Whether `readFile` and `writeFile` can access the file system. On by default, but off
+    /// for [`Lox`](crate::Lox), which embeds programs that may not be trusted.
+    pub file_access: bool,
+    /// Whether a name written with a leading backslash, as in `\class`, is an identifier even
+    /// when it's a keyword.
+    pub raw_identifiers: bool,
+    pub random: Random,
+    /// The directory `import` paths are relative to: the one of the module being run.
+    pub module_dir: PathBuf,
+    /// The arguments passed to the script, returned by `args()`.
+    pub script_args: Vec<String>,
+    /// The `//! key: value` header of the last program run, returned by `script_meta()`.
+    pub script_meta: Vec<(Atom, Atom)>,
+    pub stats: Stats,
+    /// How deep calls can nest before raising an error, rather than recursing until the
+    /// native stack overflows. Unlimited by default.
+    pub max_call_depth: Option<usize>,
+    /// The `try` statements running in the current function, whose returns can't be tail
+    /// calls: the call has to happen inside them.
+    try_depth: usize,
+    /// The modules imported so far, by canonical path.
+    modules: HashMap<PathBuf, Rc<LoxModule>>,
+    /// The modules being run, each imported by the one before it.
+    importing: Vec<PathBuf>,
+    error_class: Option<Rc<LoxClass>>,
+}
+
+/// The error raised when the output can't be written, such as when it's piped to a command
+/// that already exited.
+pub(crate) fn output_error(error: io::Error) -> RuntimeException {
+    RuntimeException::Error(RuntimeError::native(
+        RuntimeErrorKind::IoFailed,
+        &format!("Failed to write the output: {error}."),
+    ))
 }
 
 impl Interpreter {
     pub fn new(writer: Rc<RefCell<impl std::io::Write + 'static>>) -> Self {
+        #[allow(unused_mut)]
+        let mut interpreter = Self::without_features(writer);
+        for builtin in feature_builtins() {
+            interpreter
+                .global
+                .borrow_mut()
+                .define(builtin.name, builtin.value());
+        }
+        #[cfg(feature = "stdlib")]
+        {
+            interpreter
+                .run_compiled(&STDLIB_AST.with(Rc::clone))
+                .unwrap_or_else(|e| panic!("Invalid standard library: {e}"));
+            interpreter.stats = Stats::new();
+        }
+        interpreter
+    }
+
+    /// An interpreter with only the globals every build defines, leaving out those of the
+    /// optional features, such as the helpers of `stdlib` and the natives of `datetime`. The
+    /// script tests run with it, so that what they list doesn't depend on the features built.
+    pub fn without_features(writer: Rc<RefCell<impl std::io::Write + 'static>>) -> Self {
+        let mut interpreter = Self::without_prelude(writer);
+        interpreter.run_prelude();
+        interpreter.stats = Stats::new();
+        interpreter
+    }
+
+    fn without_prelude(writer: Rc<RefCell<impl std::io::Write + 'static>>) -> Self {
         let global = Rc::new(RefCell::new(Environment::new(None)));
-        global
-            .borrow_mut()
-            .define("clock", Object::Function(Rc::new(ClockFunction)));
+        for builtin in builtins() {
+            global.borrow_mut().define(builtin.name, builtin.value());
+        }
         Self {
             global: global.clone(),
             environment: global,
             locals: HashMap::new(),
             writer,
+            diagnostics: Rc::new(RefCell::new(io::stderr())),
+            reader: Rc::new(RefCell::new(BufReader::new(io::stdin()))),
+            uninitialized_read: UninitializedRead::default(),
+            global_redefinition: GlobalRedefinition::default(),
+            opt_level: OptLevel::default(),
+            dialect: Dialect::default(),
+            case_insensitive_keywords: false,
+            raw_identifiers: false,
+            file_access: true,
+            random: Random::from_time(),
+            module_dir: PathBuf::from("."),
+            script_args: Vec::new(),
+            script_meta: Vec::new(),
+            stats: Stats::default(),
+            max_call_depth: None,
+            try_depth: 0,
+            modules: HashMap::new(),
+            importing: Vec::new(),
+            error_class: None,
         }
     }
 
-    pub fn interpret(&mut self, statements: &[Stmt]) -> Result<Object, RuntimeException> {
-        let mut ret = Object::Undefined;
-        for stmt in statements {
-            ret = self.execute(stmt)?;
+    /// Defines a global native function, which calls are checked to pass `arity` arguments
+    /// like any other function, as in `interpreter.define_native("double", 1, ...)`.
+    pub fn define_native(
+        &mut self,
+        name: &str,
+        arity: usize,
+        function: impl Fn(&mut Interpreter, Vec<Object>) -> Result<Object, RuntimeException> + 'static,
+    ) {
+        let native = NativeFunction::new(name, arity, function);
+        self.global
+            .borrow_mut()
+            .define(name, Object::Function(Rc::new(native)));
+    }
+
+    /// Runs `source` before any user code, so that its declarations become globals that
+    /// every program run afterwards can use, like the natives.
+    pub fn load_prelude(&mut self, source: &str) -> Result<(), LoxError> {
+        run_timed(source, self).0.map(|_| ())
+    }
+
+    fn run_prelude(&mut self) {
+        match self.run_compiled(&PRELUDE_AST.with(Rc::clone)) {
+            Ok(Object::Class(class)) => self.error_class = Some(class),
+            _ => panic!("The prelude should end with the Error class"),
+        }
+    }
+
+    fn run_compiled(&mut self, compiled: &Compiled) -> Result<Object, RuntimeException> {
+        self.locals
+            .extend(compiled.locals.iter().map(|(&expr, &depth)| (expr, depth)));
+        // It prints nothing, so unlike `interpret` it doesn't flush the output, which may not
+        // be writable.
+        compiled
+            .statements
+            .iter()
+            .try_fold(Object::Undefined, |_, stmt| self.execute(stmt))
+    }
+
+    /// Whether `value` is an instance of `Error` or one of its subclasses.
+    fn is_error(&self, value: &Object) -> bool {
+        match (value, &self.error_class) {
+            (Object::Instance(instance), Some(error_class)) => {
+                instance.borrow().class().is_subclass_of(error_class)
+            }
+            _ => false,
+        }
+    }
+
+    /// Converts a runtime error into an `Error` instance, so that it can be caught.
+    fn error_object(&mut self, error: &RuntimeError) -> Result<Object, RuntimeException> {
+        let error_class = self.error_class.clone().expect("The prelude defines Error");
+        let object = error_class.call(self, vec![Object::String(error.message().into())])?;
+        if let Object::Instance(instance) = &object {
+            let mut instance = instance.borrow_mut();
+            if let Some(token) = error.token() {
+                instance.define_field("line", Object::Integer(token.line as i64));
+            }
+            instance.define_field("kind", Object::String(error.kind().name().into()));
+        }
+        Ok(object)
+    }
+
+    /// An interpreter whose output is collected in memory rather than written anywhere.
+    pub fn with_capture() -> (Self, CaptureWriter) {
+        let capture = CaptureWriter::new();
+        (Self::new(capture.buffer()), capture)
+    }
+
+    /// Sets where `readLine` reads from, for example to feed a script its input in tests.
+    pub fn with_reader(mut self, reader: Rc<RefCell<impl BufRead + 'static>>) -> Self {
+        self.reader = reader;
+        self
+    }
+
+    /// Sets where warnings go, for example to collect them or to silence them with
+    /// `io::sink()`.
+    pub fn with_diagnostics(mut self, diagnostics: Rc<RefCell<impl io::Write + 'static>>) -> Self {
+        self.diagnostics = diagnostics;
+        self
+    }
+
+    /// Reports a warning about `token` to the diagnostics. Warnings don't stop the program,
+    /// so failing to write them isn't an error either.
+    pub fn warn(&self, token: &Token, message: &str) {
+        let _ = writeln!(
+            self.diagnostics.borrow_mut(),
+            "[line {}:{}] Warning at '{token}': {message}",
+            token.line,
+            token.column
+        );
+    }
+
+    pub fn with_script_args(mut self, script_args: Vec<String>) -> Self {
+        self.script_args = script_args;
+        self
+    }
+
+    pub fn with_uninitialized_read(mut self, uninitialized_read: UninitializedRead) -> Self {
+        self.uninitialized_read = uninitialized_read;
+        self
+    }
+
+    pub fn with_global_redefinition(mut self, global_redefinition: GlobalRedefinition) -> Self {
+        self.global_redefinition = global_redefinition;
+        self
+    }
+
+    pub fn with_opt_level(mut self, opt_level: OptLevel) -> Self {
+        self.opt_level = opt_level;
+        self
+    }
+
+    pub fn with_dialect(mut self, dialect: Dialect) -> Self {
+        self.dialect = dialect;
+        self
+    }
+
+    pub fn with_case_insensitive_keywords(mut self, case_insensitive_keywords: bool) -> Self {
+        self.case_insensitive_keywords = case_insensitive_keywords;
+        self
+    }
+
+    pub fn with_raw_identifiers(mut self, raw_identifiers: bool) -> Self {
+        self.raw_identifiers = raw_identifiers;
+        self
+    }
+
+    pub fn with_file_access(mut self, file_access: bool) -> Self {
+        self.file_access = file_access;
+        self
+    }
+
+    /// A scanner reading `source` with the grammar of the programs this interpreter runs.
+    pub fn scanner<'a>(&self, source: &'a str) -> Scanner<'a> {
+        Scanner::new(source)
+            .with_dialect(self.dialect)
+            .with_case_insensitive_keywords(self.case_insensitive_keywords)
+            .with_raw_identifiers(self.raw_identifiers)
+    }
+
+    pub fn with_max_call_depth(mut self, max_call_depth: usize) -> Self {
+        self.max_call_depth = Some(max_call_depth);
+        self
+    }
+
+    /// Sets the directory the main program's imports are relative to, usually the one of its
+    /// file.
+    pub fn with_module_dir(mut self, module_dir: impl Into<PathBuf>) -> Self {
+        self.module_dir = module_dir.into();
+        self
+    }
+
+    /// The top level of the code being run: the global environment, or the one of the module
+    /// the code comes from, in the version the code was declared in.
+    fn globals(&self) -> Rc<RefCell<Environment>> {
+        let mut environment = self.environment.clone();
+        loop {
+            if environment.borrow().top_level {
+                return environment;
+            }
+            let enclosing = environment.borrow().enclosing.clone();
+            match enclosing {
+                Some(enclosing) => environment = enclosing,
+                None => return environment,
+            }
+        }
+    }
+
+    /// Imports the module at `path`. A module is only run the first time it's imported; later
+    /// imports of the same file share its namespace.
+    fn import(&mut self, keyword: &Token, path: &str) -> Result<Rc<LoxModule>, RuntimeException> {
+        let path = self.module_dir.join(path);
+        let path = path.canonicalize().map_err(|e| {
+            RuntimeException::Error(RuntimeError::new(
+                keyword.clone(),
+                RuntimeErrorKind::ImportFailed,
+                &format!("Failed to import '{}': {e}", path.display()),
+            ))
+        })?;
+        if let Some(module) = self.modules.get(&path) {
+            return Ok(module.clone());
+        }
+        if let Some(start) = self
+            .importing
+            .iter()
+            .position(|importing| *importing == path)
+        {
+            let chain = self.importing[start..]
+                .iter()
+                .chain([&path])
+                .map(|path| path.file_name().unwrap_or_default().to_string_lossy())
+                .collect::<Vec<_>>();
+            return Err(RuntimeException::Error(RuntimeError::new(
+                keyword.clone(),
+                RuntimeErrorKind::ImportFailed,
+                &format!("Circular import: {}.", chain.join(" imports ")),
+            )));
         }
-        Ok(ret)
+
+        self.importing.push(path.clone());
+        let module = self.run_module(keyword, &path);
+        self.importing.pop();
+        let module = Rc::new(module?);
+        self.modules.insert(path, module.clone());
+        Ok(module)
     }
 
+    /// Runs the module at `path` in an environment of its own and collects its exports.
+    fn run_module(&mut self, keyword: &Token, path: &Path) -> Result<LoxModule, RuntimeException> {
+        let import_error = |message: String| {
+            RuntimeException::Error(RuntimeError::new(
+                keyword.clone(),
+                RuntimeErrorKind::ImportFailed,
+                &format!("Failed to import '{}': {message}", path.display()),
+            ))
+        };
+        let source = fs::read_to_string(path).map_err(|e| import_error(e.to_string()))?;
+        let tokens = self.scanner(&source).collect();
+        let mut statements = Parser::new(tokens)
+            .with_dialect(self.dialect)
+            .parse()
+            .map_err(|e| import_error(e.to_string()))?;
+        let mut resolver = Resolver::new(self);
+        resolver
+            .resolve_stmts(&statements)
+            .map_err(|e| import_error(e.to_string()))?;
+        resolver.optimize(&mut statements);
+
+        let environment = Rc::new(RefCell::new(Environment::new(None)));
+        for builtin in builtins().iter().chain(feature_builtins()) {
+            environment
+                .borrow_mut()
+                .define(builtin.name, builtin.value());
+        }
+        if let Some(error_class) = &self.error_class {
+            environment
+                .borrow_mut()
+                .define("Error", Object::Class(error_class.clone()));
+        }
+
+        let module_dir = path.parent().map_or_else(PathBuf::new, Path::to_path_buf);
+        let previous_dir = std::mem::replace(&mut self.module_dir, module_dir);
+        // Not `execute_block`, as the exports are read from the version of the top level the
+        // module ends with, once globals it redefined shadow the natives.
+        let previous = std::mem::replace(&mut self.environment, environment);
+        let result = statements
+            .iter()
+            .try_for_each(|stmt| self.execute(stmt).map(drop));
+        let environment = std::mem::replace(&mut self.environment, previous);
+        self.module_dir = previous_dir;
+        result?;
+
+        let mut exports = HashMap::new();
+        for stmt in &statements {
+            if let Stmt::Export(export) = stmt {
+                for name in export.names() {
+                    let value = environment.borrow().get(&name)?.clone();
+                    exports.insert(name.atom(), value);
+                }
+            }
+        }
+        let name = path
+            .file_stem()
+            .map_or_else(String::new, |stem| stem.to_string_lossy().into_owned());
+        Ok(LoxModule::new(name, exports))
+    }
+
+    /// The current environment chain, and the closures reachable from it, as a Graphviz DOT
+    /// graph.
+    pub fn env_graph(&self) -> String {
+        environment::dot_graph(&self.environment)
+    }
+
+    /// Applies the operator of `expr` to its evaluated operands.
+    fn apply_binary(
+        &mut self,
+        expr: &BinaryExpr,
+        left: Object,
+        right: Object,
+    ) -> Result<Object, RuntimeException> {
+        if expr.operator.id == TokenIdentity::Plus {
+            return self.add(expr, left, right);
+        }
+        let (left, right) = (left.flatten(), right.flatten());
+
+        match expr.operator.id {
+            TokenIdentity::Greater
+            | TokenIdentity::GreaterEqual
+            | TokenIdentity::Less
+            | TokenIdentity::LessEqual => compare(expr, &left, &right),
+            TokenIdentity::Is => match (&left, &right) {
+                (Object::Instance(instance), Object::Class(class)) => Ok(Object::Boolean(
+                    instance.borrow().class().is_subclass_of(class),
+                )),
+                (_, Object::Class(_)) => Ok(Object::Boolean(false)),
+                _ => Err(RuntimeException::Error(RuntimeError::new(
+                    expr.operator.clone(),
+                    RuntimeErrorKind::TypeMismatch {
+                        expected: "class",
+                        found: right.type_name(),
+                    },
+                    "Right operand of 'is' must be a class.",
+                ))),
+            },
+            TokenIdentity::BangEqual => Ok(Object::Boolean(left != right)),
+            TokenIdentity::EqualEqual => Ok(Object::Boolean(left == right)),
+            TokenIdentity::In => match (&left, &right) {
+                (_, Object::List(list)) => Ok(Object::Boolean(list.borrow().contains(&left))),
+                (_, Object::Map(map)) if LoxMap::is_hashable(&left) => {
+                    Ok(Object::Boolean(map.borrow().get(&left).is_some()))
+                }
+                (_, Object::Map(_)) => Err(RuntimeException::Error(RuntimeError::new(
+                    expr.operator.clone(),
+                    RuntimeErrorKind::UnhashableKey,
+                    &format!("Unhashable map key {left}."),
+                ))),
+                (Object::String(left), Object::String(right)) => {
+                    Ok(Object::Boolean(right.contains(left.as_str())))
+                }
+                _ => Err(RuntimeException::Error(RuntimeError::new(
+                    expr.operator.clone(),
+                    RuntimeErrorKind::TypeMismatch {
+                        expected: "list, map or string",
+                        found: right.type_name(),
+                    },
+                    &format!("Invalid operands {left} and {right} for in operator."),
+                ))),
+            },
+            TokenIdentity::Minus | TokenIdentity::Slash | TokenIdentity::Star => {
+                if let (Some(_), Some(divisor)) = (left.maybe_to_number(), right.maybe_to_number())
+                {
+                    if expr.operator.id == TokenIdentity::Slash && divisor == 0.0 {
+                        return Err(RuntimeException::Error(RuntimeError::new(
+                            expr.operator.clone(),
+                            RuntimeErrorKind::DivisionByZero,
+                            "Divided by zero.",
+                        )));
+                    }
+                    return Ok(arithmetic(expr.operator.id, &left, &right));
+                }
+                match (left, right) {
+                    (Object::String(string), count) | (count, Object::String(string))
+                        if expr.operator.id == TokenIdentity::Star
+                            && count.maybe_to_number().is_some() =>
+                    {
+                        repeat(expr, &string, &count)
+                    }
+                    (left, right) => Err(RuntimeException::Error(RuntimeError::new(
+                        expr.operator.clone(),
+                        RuntimeErrorKind::TypeMismatch {
+                            expected: "number",
+                            found: non_number(&left, &right),
+                        },
+                        "Only support number operands.",
+                    ))),
+                }
+            }
+            _ => Err(RuntimeException::Error(RuntimeError::new(
+                expr.operator.clone(),
+                RuntimeErrorKind::UnsupportedOperator,
+                "Unsupported operator.",
+            ))),
+        }
+    }
+
+    /// `left + right`. Adds two numbers, or joins two strings. A number on either side of a
+    /// string is joined as it would print, so `"x" + 1` and `1 + "x"` both work; any other mix
+    /// is an error. Long strings are joined as ropes, so that building one up by repeated
+    /// concatenation doesn't copy it every time.
+    fn add(
+        &self,
+        expr: &BinaryExpr,
+        left: Object,
+        right: Object,
+    ) -> Result<Object, RuntimeException> {
+        let strand = |object: &Object| match object {
+            Object::Number(_) | Object::Integer(_) => Some(Strand::Flat(object.to_string().into())),
+            other => Strand::of(other),
+        };
+        if left.maybe_to_number().is_some() && right.maybe_to_number().is_some() {
+            return Ok(arithmetic(TokenIdentity::Plus, &left, &right));
+        }
+        match (strand(&left), strand(&right)) {
+            (Some(left), Some(right)) => Ok(Rope::concat(left, right)),
+            (left_strand, _) => Err(RuntimeException::Error(RuntimeError::new(
+                expr.operator.clone(),
+                RuntimeErrorKind::TypeMismatch {
+                    expected: "number or string",
+                    found: if left_strand.is_some() {
+                        right.type_name()
+                    } else {
+                        left.type_name()
+                    },
+                },
+                &format!("Invalid operands {left} and {right} for + operator."),
+            ))),
+        }
+    }
+
+    /// Defines `name` in the current environment, applying the redefinition policy if it is
+    /// the global one.
+    ///
+    /// The resolver rejects redeclarations within one program, except of globals in the
+    /// book's grammar, so the policy mostly applies to globals left by an earlier program or
+    /// natives.
+    fn declare(&mut self, name: &Token, value: Object) -> Result<(), RuntimeException> {
+        let redefined = {
+            let environment = self.environment.borrow();
+            environment.top_level && environment.get(name).is_ok()
+        };
+        if redefined {
+            match self.global_redefinition {
+                GlobalRedefinition::Overwrite => {}
+                GlobalRedefinition::Error => {
+                    return Err(RuntimeException::Error(RuntimeError::new(
+                        name.to_owned(),
+                        RuntimeErrorKind::Redefinition,
+                        "Already a global with this name.",
+                    )));
+                }
+                GlobalRedefinition::Warn => {
+                    self.warn(
+                        name,
+                        "Redefining a global; functions declared before keep the old value.",
+                    );
+                    let shadowing = Environment::shadowing(self.environment.clone());
+                    self.environment = Rc::new(RefCell::new(shadowing));
+                }
+            }
+        }
+        self.environment.borrow_mut().define(name.atom(), value);
+        Ok(())
+    }
+
+    /// Runs `statements`, then flushes the writer so that everything printed before an error
+    /// is out before the caller reports it.
+    pub fn interpret(&mut self, statements: &[Stmt]) -> Result<Object, RuntimeException> {
+        let result = statements
+            .iter()
+            .try_fold(Object::Undefined, |_, stmt| self.execute(stmt));
+        let flushed = self.writer.borrow_mut().flush();
+        let value = result?;
+        flushed.map_err(output_error)?;
+        Ok(value)
+    }
+
+    /// Evaluates `expr`. Operators, groupings and ternaries are evaluated with a stack of
+    /// work of our own rather than by recursion, so that generated code nesting thousands of
+    /// them can't overflow the native stack. Other expressions are visited as usual.
     fn evaluate(&mut self, expr: &Expr) -> Result<Object, RuntimeException> {
-        ExprVisitor::accept(self, expr)
+        if !Work::nests_operators(expr) {
+            return ExprVisitor::accept(self, expr);
+        }
+        let mut work = vec![Work::Evaluate(expr)];
+        let mut values = Vec::new();
+        while let Some(step) = work.pop() {
+            match step {
+                Work::Evaluate(Expr::Binary(expr)) => {
+                    work.push(Work::Binary(expr));
+                    work.push(Work::Evaluate(&expr.right));
+                    work.push(Work::Evaluate(&expr.left));
+                }
+                Work::Evaluate(Expr::Grouping(expr)) => work.push(Work::Evaluate(&expr.expression)),
+                Work::Evaluate(Expr::Logical(expr)) => {
+                    work.push(Work::Logical(expr));
+                    work.push(Work::Evaluate(&expr.left));
+                }
+                Work::Evaluate(Expr::Ternary(expr)) => {
+                    work.push(Work::Ternary(expr));
+                    work.push(Work::Evaluate(&expr.condition));
+                }
+                Work::Evaluate(Expr::Unary(expr)) => {
+                    work.push(Work::Unary(expr));
+                    work.push(Work::Evaluate(&expr.right));
+                }
+                Work::Evaluate(expr) => values.push(ExprVisitor::accept(self, expr)?),
+                Work::Binary(expr) => {
+                    let right = values.pop().unwrap();
+                    let left = values.pop().unwrap();
+                    values.push(self.apply_binary(expr, left, right)?);
+                }
+                Work::Logical(expr) => {
+                    if !short_circuits(expr, values.last().unwrap()) {
+                        values.pop();
+                        work.push(Work::Evaluate(&expr.right));
+                    }
+                }
+                Work::Ternary(expr) => {
+                    let condition = values.pop().unwrap();
+                    work.push(Work::Evaluate(if condition.is_truthy() {
+                        &expr.then_branch
+                    } else {
+                        &expr.else_branch
+                    }));
+                }
+                Work::Unary(expr) => {
+                    let right = values.pop().unwrap();
+                    values.push(apply_unary(expr, right)?);
+                }
+            }
+        }
+        Ok(values.pop().unwrap())
     }
 
     fn execute(&mut self, stmt: &Stmt) -> Result<Object, RuntimeException> {
+        self.stats.statements += 1;
+        if gc::is_due() {
+            self.collect_garbage();
+        }
         StmtVisitor::accept(self, stmt)
     }
 
+    /// Frees the reference cycles the program can no longer reach. Returns the number of
+    /// objects freed.
+    pub fn collect_garbage(&mut self) -> usize {
+        let collected = gc::collect();
+        self.stats.collections += 1;
+        self.stats.collected += collected;
+        collected
+    }
+
     pub fn resolve(&mut self, expr: &Expr, depth: usize) {
         self.locals.insert(expr.to_hash(), depth);
     }
@@ -68,27 +813,433 @@ impl Interpreter {
         let previous = self.environment.clone();
         self.environment = environment;
 
-        let mut ret = Object::Undefined;
+        // The previous environment must be restored even when the block is left early through
+        // `return`, `break` or an error, otherwise the caller keeps running in the callee's scope.
+        let mut ret = Ok(Object::Undefined);
         for stmt in statements {
-            ret = self.execute(stmt)?;
+            ret = self.execute(stmt);
+            if ret.is_err() {
+                break;
+            }
         }
 
         self.environment = previous;
 
-        Ok(ret)
+        ret
     }
 
-    fn lookup_variable(&mut self, name: &Token, expr: &Expr) -> Result<&Object, RuntimeException> {
-        if let Some(distance) = self.locals.get(&expr.to_hash()) {
+    /// Evaluates the callee and the arguments of a call, checking that they match.
+    fn prepare_call(
+        &mut self,
+        expr: &CallExpr,
+    ) -> Result<(Rc<dyn LoxCallable>, Vec<Object>), RuntimeException> {
+        let callee = match &expr.callee {
+            // Getters are invoked by the property access itself, so `obj.getter()` would call
+            // whatever the getter returns. Catch it before running the getter.
+            Expr::Get(get) => {
+                let object = self.evaluate(&get.object)?;
+                if let Object::Instance(instance) = &object
+                    && instance.borrow().get_getter(&get.name).is_some()
+                {
+                    return Err(RuntimeException::Error(RuntimeError::new(
+                        get.name.clone(),
+                        RuntimeErrorKind::NotCallable,
+                        &format!(
+                            "'{}' is a getter; call it without parentheses.",
+                            get.name.value
+                        ),
+                    )));
+                }
+                self.get_property(object, &get.name)?
+            }
+            callee => self.evaluate(callee)?,
+        };
+        let mut arguments = Vec::new();
+
+        for argument in &expr.arguments {
+            arguments.push(self.evaluate(argument)?);
+        }
+        let callee: Rc<dyn LoxCallable> = match callee {
+            Object::Function(function) => function,
+            Object::Class(lox_class) => lox_class,
+            _ => {
+                return Err(RuntimeException::Error(RuntimeError::new(
+                    expr.paren.clone(),
+                    RuntimeErrorKind::NotCallable,
+                    "Can only call functions and classes.",
+                )));
+            }
+        };
+        if callee.is_variadic() && arguments.len() < callee.arity() {
+            return Err(RuntimeException::Error(RuntimeError::new(
+                expr.paren.clone(),
+                RuntimeErrorKind::ArityMismatch {
+                    expected: callee.arity(),
+                    found: arguments.len(),
+                },
+                &format!(
+                    "{callee} expected at least {} but got {}.",
+                    count_arguments(callee.arity()),
+                    arguments.len()
+                ),
+            )));
+        }
+        if !callee.is_variadic() && arguments.len() != callee.arity() {
+            return Err(RuntimeException::Error(RuntimeError::new(
+                expr.paren.clone(),
+                RuntimeErrorKind::ArityMismatch {
+                    expected: callee.arity(),
+                    found: arguments.len(),
+                },
+                &format!(
+                    "{callee} expected {} but got {}.",
+                    count_arguments(callee.arity()),
+                    arguments.len()
+                ),
+            )));
+        }
+        if callee.is_native() {
+            arguments = arguments.into_iter().map(Object::flatten).collect();
+        }
+        Ok((callee, arguments))
+    }
+
+    /// Returns `value`, raising the call it ends with as a [`TailCall`] if it does, as in
+    /// `return f(x);`, `return n == 0 ? f(x) : g(x);` or `return done or f(x);`.
+    fn return_tail(&mut self, mut value: &Expr) -> Result<Object, RuntimeException> {
+        loop {
+            value = match value {
+                Expr::Call(call) => {
+                    let (callee, arguments) = self.prepare_call(call)?;
+                    self.stats.calls += 1;
+                    return Err(RuntimeException::TailCall(Box::new(TailCall {
+                        callee,
+                        arguments,
+                        paren: call.paren.clone(),
+                    })));
+                }
+                Expr::Grouping(grouping) => &grouping.expression,
+                Expr::Logical(logical) => {
+                    let left = self.evaluate(&logical.left)?;
+                    if short_circuits(logical, &left) {
+                        return Err(RuntimeException::Return(RuntimeReturn::new(left)));
+                    }
+                    &logical.right
+                }
+                Expr::Ternary(ternary) => {
+                    if self.evaluate(&ternary.condition)?.is_truthy() {
+                        &ternary.then_branch
+                    } else {
+                        &ternary.else_branch
+                    }
+                }
+                value => {
+                    return Err(RuntimeException::Return(RuntimeReturn::new(
+                        self.evaluate(value)?,
+                    )));
+                }
+            };
+        }
+    }
+
+    /// Runs the blocks of a `try` statement.
+    fn run_try(&mut self, stmt: &TryStmt) -> Result<Object, RuntimeException> {
+        let mut result = self.visit_block_stmt(&stmt.body);
+        if let Some((name, body)) = &stmt.catch {
+            // A catch limited to some kinds of runtime errors lets everything else through.
+            let catches = |error: &RuntimeError| {
+                stmt.kinds.is_empty()
+                    || stmt
+                        .kinds
+                        .iter()
+                        .any(|kind| kind.atom().as_str() == error.kind().name())
+            };
+            let exception = match &result {
+                Err(RuntimeException::Throw(value)) if stmt.kinds.is_empty() => Some(value.clone()),
+                Err(RuntimeException::Error(error)) if catches(error) => {
+                    Some(self.error_object(error)?)
+                }
+                _ => None,
+            };
+            if let Some(exception) = exception {
+                let mut environment = Environment::new(Some(self.environment.clone()));
+                environment.define(name.atom(), exception);
+                result = self.execute_block(&body.statements, Rc::new(RefCell::new(environment)));
+            }
+        }
+        if let Some(finally) = &stmt.finally {
+            // Leaving the finally block early replaces whatever the rest of the statement was
+            // doing, like a pending return or exception.
+            self.visit_block_stmt(finally)?;
+        }
+        result
+    }
+
+    /// Executes the body of a Lox function in `environment`. Its tail calls are raised as
+    /// [`RuntimeException::TailCall`] for the function to make.
+    pub fn execute_body(
+        &mut self,
+        statements: &[Stmt],
+        environment: Rc<RefCell<Environment>>,
+    ) -> Result<Object, RuntimeException> {
+        let try_depth = mem::take(&mut self.try_depth);
+        let result = self.execute_block(statements, environment);
+        self.try_depth = try_depth;
+        result
+    }
+
+    /// Evaluates `expr` in `environment` rather than the current one.
+    pub fn evaluate_in(
+        &mut self,
+        expr: &Expr,
+        environment: Rc<RefCell<Environment>>,
+    ) -> Result<Object, RuntimeException> {
+        let previous = std::mem::replace(&mut self.environment, environment);
+        let value = self.evaluate(expr);
+        self.environment = previous;
+        value
+    }
+
+    /// Runs a `for` loop in the environment holding its loop variables. Each iteration gets a
+    /// fresh copy of that environment before the increment runs, so closures created in the
+    /// body keep the values of their own iteration.
+    fn run_for_loop(
+        &mut self,
+        stmt: &ForStmt,
+        enclosing: &Rc<RefCell<Environment>>,
+    ) -> Result<Object, RuntimeException> {
+        if let Some(initializer) = &stmt.initializer {
+            self.execute(initializer)?;
+        }
+        while self.evaluate(&stmt.condition)?.is_truthy() {
+            match self.visit_block_stmt(&stmt.body) {
+                Ok(_) | Err(RuntimeException::Continue) => {}
+                Err(RuntimeException::Break) => break,
+                Err(error) => return Err(error),
+            }
+
+            let mut next = Environment::new(Some(enclosing.clone()));
+            next.values = self.environment.borrow().values.clone();
+            self.environment = Rc::new(RefCell::new(next));
+            if let Some(increment) = &stmt.increment {
+                self.evaluate(increment)?;
+            }
+        }
+        Ok(Object::Undefined)
+    }
+
+    fn integer(&self, bracket: &Token, index: &Object) -> Result<i64, RuntimeException> {
+        index.maybe_to_integer().ok_or_else(|| {
+            RuntimeException::Error(RuntimeError::new(
+                bracket.clone(),
+                RuntimeErrorKind::TypeMismatch {
+                    expected: "integer",
+                    found: index.type_name(),
+                },
+                &format!("Index must be an integer, got {index}."),
+            ))
+        })
+    }
+
+    /// Converts a possibly negative index, counted from the end, into a position in `0..len`.
+    fn index(
+        &self,
+        bracket: &Token,
+        index: &Object,
+        len: usize,
+    ) -> Result<usize, RuntimeException> {
+        let requested = self.integer(bracket, index)?;
+        let position = if requested < 0 {
+            requested + len as i64
+        } else {
+            requested
+        };
+        if (0..len as i64).contains(&position) {
+            Ok(position as usize)
+        } else {
+            Err(RuntimeException::Error(RuntimeError::new(
+                bracket.clone(),
+                RuntimeErrorKind::IndexOutOfRange {
+                    index: requested,
+                    length: len,
+                },
+                &format!("Index {index} out of range for length {len}."),
+            )))
+        }
+    }
+
+    /// Evaluates the bounds of a slice into a range of `0..len`. Missing bounds extend to the
+    /// ends, negative ones count from the end, and out of range ones are clamped.
+    fn slice_range(
+        &mut self,
+        expr: &SliceExpr,
+        len: usize,
+    ) -> Result<Range<usize>, RuntimeException> {
+        let mut bound = |bound: &Option<Expr>, default: usize| match bound {
+            Some(bound) => {
+                let bound = self.evaluate(bound)?;
+                let position = self.integer(&expr.bracket, &bound)?;
+                let position = if position < 0 {
+                    position + len as i64
+                } else {
+                    position
+                };
+                Ok(position.clamp(0, len as i64) as usize)
+            }
+            None => Ok(default),
+        };
+        let start = bound(&expr.start, 0)?;
+        let end = bound(&expr.end, len)?;
+        Ok(start..end.max(start))
+    }
+
+    fn get_property(&mut self, object: Object, name: &Token) -> Result<Object, RuntimeException> {
+        match object.flatten() {
+            Object::Instance(instance) => {
+                let getter = instance.borrow().get_getter(name).cloned();
+                match getter {
+                    Some(getter) if getter.is_memo() => {
+                        if let Some(value) = instance.borrow().memoized(name) {
+                            return Ok(value.clone());
+                        }
+                        let value = getter
+                            .bind(Object::Instance(instance.clone()))
+                            .call(self, Vec::new())?;
+                        instance.borrow_mut().memoize(name, value.clone());
+                        Ok(value)
+                    }
+                    // We bind the the getter to the instance to be able to call `this` keyword
+                    // Check Test3 in class2.lox test
+                    Some(getter) => getter
+                        .bind(Object::Instance(instance.clone()))
+                        .call(self, Vec::new()),
+                    None => LoxInstance::get(&instance, name),
+                }
+            }
+            Object::Class(class) => {
+                if let Some(value) = class.get_static(&name.atom()) {
+                    return Ok(value);
+                }
+                class.find_method(&name.atom()).map_or(
+                    Err(RuntimeException::Error(RuntimeError::new(
+                        name.clone(),
+                        RuntimeErrorKind::UndefinedProperty,
+                        &format!(
+                            "Class {} doesn't have a method named '{}'.",
+                            class.name, name.value
+                        ),
+                    ))),
+                    |method| Ok(Object::Function(method.to_owned())),
+                )
+            }
+            Object::Module(module) => module.get(&name.atom()).cloned().ok_or_else(|| {
+                RuntimeException::Error(RuntimeError::new(
+                    name.clone(),
+                    RuntimeErrorKind::UndefinedProperty,
+                    &format!("Module {} doesn't export '{}'.", module.name, name.value),
+                ))
+            }),
+            Object::String(string) => StringMethod::bind(string, &name.atom())
+                .map(|method| Object::Function(Rc::new(method)))
+                .ok_or_else(|| {
+                    RuntimeException::Error(RuntimeError::new(
+                        name.clone(),
+                        RuntimeErrorKind::UndefinedProperty,
+                        &format!("String has no method named '{}'.", name.value),
+                    ))
+                }),
+            other => Err(RuntimeException::Error(RuntimeError::new(
+                name.clone(),
+                RuntimeErrorKind::TypeMismatch {
+                    expected: "instance",
+                    found: other.type_name(),
+                },
+                "Only instances have properties.",
+            ))),
+        }
+    }
+
+    /// Extracts the values bound by the names of `pattern` from `value`, in order.
+    fn destructure(
+        &mut self,
+        pattern: &Pattern,
+        value: &Object,
+    ) -> Result<Vec<Object>, RuntimeException> {
+        match (pattern, value) {
+            (Pattern::List { names, .. }, Object::List(list)) => {
+                let list = list.borrow();
+                if list.len() != names.len() {
+                    return Err(RuntimeException::Error(RuntimeError::new(
+                        names[0].clone(),
+                        RuntimeErrorKind::DestructureMismatch {
+                            expected: names.len(),
+                            found: list.len(),
+                        },
+                        &format!(
+                            "Can't destructure {} elements into {} variables.",
+                            list.len(),
+                            names.len()
+                        ),
+                    )));
+                }
+                Ok(list.clone())
+            }
+            (Pattern::List { bracket, .. }, _) => Err(RuntimeException::Error(RuntimeError::new(
+                bracket.clone(),
+                RuntimeErrorKind::TypeMismatch {
+                    expected: "list",
+                    found: value.type_name(),
+                },
+                "Only lists can be destructured into [...].",
+            ))),
+            (Pattern::Fields { names, .. }, Object::Map(map)) => names
+                .iter()
+                .map(|name| {
+                    map.borrow()
+                        .get(&Object::String(name.atom()))
+                        .cloned()
+                        .ok_or_else(|| {
+                            RuntimeException::Error(RuntimeError::new(
+                                name.clone(),
+                                RuntimeErrorKind::UndefinedKey,
+                                &format!("Undefined key {name}."),
+                            ))
+                        })
+                })
+                .collect(),
+            (Pattern::Fields { names, .. }, _) => names
+                .iter()
+                .map(|name| self.get_property(value.clone(), name))
+                .collect(),
+        }
+    }
+
+    fn lookup_variable(&mut self, name: &Token, expr: &Expr) -> Result<Object, RuntimeException> {
+        let value = if let Some(distance) = self.locals.get(&expr.to_hash()) {
             unsafe {
                 self.environment
                     .as_ptr()
                     .as_mut()
                     .unwrap()
                     .get_at(*distance, name)
+                    .cloned()
             }
         } else {
-            unsafe { self.global.as_ptr().as_ref().unwrap().get(name) }
+            self.globals().borrow().get(name).cloned()
+        }?;
+
+        match (value, self.uninitialized_read) {
+            // The book's variables declared without an initializer hold `nil`.
+            (Object::Undefined, _) if self.dialect == Dialect::Lox => Ok(Object::Nil),
+            (Object::Undefined, UninitializedRead::Error) => {
+                Err(RuntimeException::Error(RuntimeError::new(
+                    name.to_owned(),
+                    RuntimeErrorKind::UninitializedVariable,
+                    "The variable isn't initialized.",
+                )))
+            }
+            (Object::Undefined, UninitializedRead::Nil) => Ok(Object::Nil),
+            (value, _) => Ok(value),
         }
     }
 }
@@ -106,7 +1257,25 @@ impl ExprVisitor for Interpreter {
                 .borrow_mut()
                 .assign_at(*distance, &expr.name, value.clone())?;
         } else {
-            self.global.borrow_mut().assign(&expr.name, value.clone())?;
+            self.globals()
+                .borrow_mut()
+                .assign(&expr.name, value.clone())?;
+        }
+        Ok(value)
+    }
+
+    fn visit_destructure_expr(&mut self, expr: &DestructureExpr) -> Self::Output {
+        let value = self.evaluate(&expr.value)?;
+        let values = self.destructure(&expr.pattern, &value)?;
+        for (name, item) in expr.pattern.names().iter().zip(values) {
+            let target = Expr::Variable(VariableExpr::new(name.clone()));
+            if let Some(distance) = self.locals.get(&target.to_hash()) {
+                self.environment
+                    .borrow_mut()
+                    .assign_at(*distance, name, item)?;
+            } else {
+                self.globals().borrow_mut().assign(name, item)?;
+            }
         }
         Ok(value)
     }
@@ -114,142 +1283,150 @@ impl ExprVisitor for Interpreter {
     fn visit_binary_expr(&mut self, expr: &BinaryExpr) -> Self::Output {
         let left = self.evaluate(&expr.left)?;
         let right = self.evaluate(&expr.right)?;
-
-        match expr.operator.id {
-            TokenIdentity::Greater => match (left, right) {
-                (Object::Number(left), Object::Number(right)) => Ok(Object::Boolean(left > right)),
-                _ => Ok(Object::Boolean(false)),
-            },
-            TokenIdentity::GreaterEqual => match (left, right) {
-                (Object::Number(left), Object::Number(right)) => Ok(Object::Boolean(left >= right)),
-                _ => Ok(Object::Boolean(false)),
-            },
-            TokenIdentity::Less => match (left, right) {
-                (Object::Number(left), Object::Number(right)) => Ok(Object::Boolean(left < right)),
-                _ => Ok(Object::Boolean(false)),
-            },
-            TokenIdentity::LessEqual => match (left, right) {
-                (Object::Number(left), Object::Number(right)) => Ok(Object::Boolean(left <= right)),
-                _ => Ok(Object::Boolean(false)),
-            },
-            TokenIdentity::BangEqual => Ok(Object::Boolean(left != right)),
-            TokenIdentity::EqualEqual => Ok(Object::Boolean(left == right)),
-            TokenIdentity::Minus => match (left, right) {
-                (Object::Number(left), Object::Number(right)) => Ok(Object::Number(left - right)),
-                _ => Err(RuntimeException::Error(RuntimeError::new(
-                    expr.operator.clone(),
-                    "Only support number operands.",
-                ))),
-            },
-            TokenIdentity::Plus => match (left.clone(), right.clone()) {
-                (Object::Number(left), Object::Number(right)) => Ok(Object::Number(left + right)),
-                (Object::String(left), Object::String(right)) => Ok(Object::String(left + &right)),
-                (Object::String(left), Object::Number(right)) => {
-                    Ok(Object::String(left + &right.to_string()))
-                }
-                _ => Err(RuntimeException::Error(RuntimeError::new(
-                    expr.operator.clone(),
-                    &format!("Invalid operands {left} and {right} for + operator."),
-                ))),
-            },
-            TokenIdentity::Slash => match (left, right) {
-                (Object::Number(_), Object::Number(0.0)) => Err(RuntimeException::Error(
-                    RuntimeError::new(expr.operator.clone(), "Divided by zero."),
-                )),
-                (Object::Number(left), Object::Number(right)) => Ok(Object::Number(left / right)),
-                _ => Err(RuntimeException::Error(RuntimeError::new(
-                    expr.operator.clone(),
-                    "Only support number operands.",
-                ))),
-            },
-            TokenIdentity::Star => match (left, right) {
-                (Object::Number(left), Object::Number(right)) => Ok(Object::Number(left * right)),
-                _ => Err(RuntimeException::Error(RuntimeError::new(
-                    expr.operator.clone(),
-                    "Only support number operands.",
-                ))),
-            },
-            _ => Err(RuntimeException::Error(RuntimeError::new(
-                expr.operator.clone(),
-                "Unsupported operator.",
-            ))),
-        }
+        self.apply_binary(expr, left, right)
     }
 
     fn visit_call_expr(&mut self, expr: &CallExpr) -> Self::Output {
-        let callee = self.evaluate(&expr.callee)?;
-        let mut arguments = Vec::new();
-
-        for argument in &expr.arguments {
-            arguments.push(self.evaluate(argument)?);
-        }
-        match callee {
-            Object::Function(function) => function.call(self, arguments),
-            Object::Class(lox_class) => lox_class.call(self, arguments),
-            _ => Err(RuntimeException::Error(RuntimeError::new(
+        let (callee, arguments) = self.prepare_call(expr)?;
+        if self
+            .max_call_depth
+            .is_some_and(|max_call_depth| self.stats.call_depth >= max_call_depth)
+        {
+            return Err(RuntimeException::Error(RuntimeError::new(
                 expr.paren.clone(),
-                "Can only call functions and classes.",
-            ))),
+                RuntimeErrorKind::StackOverflow,
+                "Stack overflow.",
+            )));
         }
+        self.stats.calls += 1;
+        self.stats.call_depth += 1;
+        self.stats.peak_call_depth = self.stats.peak_call_depth.max(self.stats.call_depth);
+        let result = callee.call(self, arguments);
+        self.stats.call_depth -= 1;
+        result.map_err(|error| match error {
+            RuntimeException::Error(error) => RuntimeException::Error(error.or_at(&expr.paren)),
+            _ => error,
+        })
     }
 
     fn visit_get_expr(&mut self, expr: &GetExpr) -> Self::Output {
         let object = self.evaluate(&expr.object)?;
+        self.get_property(object, &expr.name)
+    }
+
+    fn visit_grouping_expr(&mut self, expr: &GroupingExpr) -> Self::Output {
+        self.evaluate(&expr.expression)
+    }
+
+    fn visit_index_expr(&mut self, expr: &IndexExpr) -> Self::Output {
+        let object = self.evaluate(&expr.object)?.flatten();
+        let index = self.evaluate(&expr.index)?;
         match object {
-            Object::Instance(instance) => instance.borrow().get_getter(&expr.name).map_or(
-                instance.borrow().get(&expr.name),
-                |getter| {
-                    // We bind the the getter to the instance to be able to call `this` keyword
-                    // Check Test3 in class2.lox test
-                    getter
-                        .bind(Object::Instance(instance.clone()))
-                        .call(self, Vec::new())
+            Object::List(list) => {
+                let list = list.borrow();
+                let index = self.index(&expr.bracket, &index, list.len())?;
+                Ok(list[index].clone())
+            }
+            Object::String(string) => {
+                let index = self.index(&expr.bracket, &index, string.chars().count())?;
+                Ok(Object::String(
+                    string.chars().nth(index).unwrap().to_string().into(),
+                ))
+            }
+            Object::Map(map) => map.borrow().get(&index).cloned().ok_or_else(|| {
+                RuntimeException::Error(RuntimeError::new(
+                    expr.bracket.clone(),
+                    RuntimeErrorKind::UndefinedKey,
+                    &format!("Undefined key {index}."),
+                ))
+            }),
+            other => Err(RuntimeException::Error(RuntimeError::new(
+                expr.bracket.clone(),
+                RuntimeErrorKind::TypeMismatch {
+                    expected: "list, string or map",
+                    found: other.type_name(),
                 },
-            ),
-            Object::Class(class) => class.find_method(&expr.name.value.to_string()).map_or(
-                Err(RuntimeException::Error(RuntimeError::new(
-                    expr.name.clone(),
-                    &format!(
-                        "Class {} doesn't have a method named '{}'.",
-                        class.name, expr.name.value
-                    ),
-                ))),
-                |method| Ok(Object::Function(method.to_owned())),
-            ),
-            _ => Err(RuntimeException::Error(RuntimeError::new(
-                expr.name.clone(),
-                "Only instances have properties.",
+                "Only lists, strings and maps can be indexed.",
             ))),
         }
     }
 
-    fn visit_grouping_expr(&mut self, expr: &GroupingExpr) -> Self::Output {
-        self.evaluate(&expr.expression)
+    fn visit_index_set_expr(&mut self, expr: &IndexSetExpr) -> Self::Output {
+        let object = self.evaluate(&expr.object)?;
+        let index = self.evaluate(&expr.index)?;
+        match object {
+            Object::List(list) => {
+                let value = self.evaluate(&expr.value)?;
+                let index = self.index(&expr.bracket, &index, list.borrow().len())?;
+                list.borrow_mut()[index] = value.clone();
+                Ok(value)
+            }
+            Object::Map(map) => {
+                let value = self.evaluate(&expr.value)?;
+                if map.borrow_mut().insert(index.clone(), value.clone()) {
+                    Ok(value)
+                } else {
+                    Err(RuntimeException::Error(RuntimeError::new(
+                        expr.bracket.clone(),
+                        RuntimeErrorKind::UnhashableKey,
+                        &format!("Unhashable map key {index}."),
+                    )))
+                }
+            }
+            other => Err(RuntimeException::Error(RuntimeError::new(
+                expr.bracket.clone(),
+                RuntimeErrorKind::TypeMismatch {
+                    expected: "list or map",
+                    found: other.type_name(),
+                },
+                "Only list and map elements can be assigned.",
+            ))),
+        }
     }
 
-    fn visit_lambda_expr(&mut self, expr: &LambdaExpr) -> Self::Output {
+    fn visit_lambda_expr(&mut self, expr: &Rc<LambdaExpr>) -> Self::Output {
         Ok(Object::Function(Rc::new(LambdaFunction::new(
-            expr.to_owned(),
+            Rc::clone(expr),
+            self.environment.clone(),
         ))))
     }
 
-    fn visit_literal_expr(&self, expr: &LiteralExpr) -> Self::Output {
+    fn visit_list_expr(&mut self, expr: &ListExpr) -> Self::Output {
+        let mut elements = Vec::new();
+        for element in &expr.elements {
+            elements.push(self.evaluate(element)?);
+        }
+        Ok(Object::new_list(elements))
+    }
+
+    fn visit_literal_expr(&mut self, expr: &LiteralExpr) -> Self::Output {
         Ok(expr.value.to_owned())
     }
 
     fn visit_logical_expr(&mut self, expr: &LogicalExpr) -> Self::Output {
         let left = self.evaluate(&expr.left)?;
-
-        if left.is_truthy() && expr.operator.id == TokenIdentity::Or {
-            return Ok(left);
-        }
-        if !left.is_truthy() && expr.operator.id == TokenIdentity::And {
+        if short_circuits(expr, &left) {
             return Ok(left);
         }
-
         self.evaluate(&expr.right)
     }
 
+    fn visit_map_expr(&mut self, expr: &MapExpr) -> Self::Output {
+        let mut map = LoxMap::new();
+        for (key, value) in &expr.entries {
+            let key = self.evaluate(key)?;
+            let value = self.evaluate(value)?;
+            if !map.insert(key.clone(), value) {
+                return Err(RuntimeException::Error(RuntimeError::new(
+                    expr.brace.clone(),
+                    RuntimeErrorKind::UnhashableKey,
+                    &format!("Unhashable map key {key}."),
+                )));
+            }
+        }
+        Ok(Object::new_map(map))
+    }
+
     fn visit_set_expr(&mut self, expr: &SetExpr) -> Self::Output {
         let object = self.evaluate(&expr.object)?;
         match object {
@@ -260,13 +1437,51 @@ impl ExprVisitor for Interpreter {
                     .set(expr.name.clone(), value.clone())?;
                 Ok(value)
             }
-            _ => Err(RuntimeException::Error(RuntimeError::new(
+            Object::Class(class) => {
+                let value = self.evaluate(&expr.value)?;
+                class.set_static(expr.name.atom(), value.clone());
+                Ok(value)
+            }
+            other => Err(RuntimeException::Error(RuntimeError::new(
                 expr.name.clone(),
+                RuntimeErrorKind::TypeMismatch {
+                    expected: "instance",
+                    found: other.type_name(),
+                },
                 "Only instances have properties.",
             ))),
         }
     }
 
+    fn visit_slice_expr(&mut self, expr: &SliceExpr) -> Self::Output {
+        match self.evaluate(&expr.object)?.flatten() {
+            Object::List(list) => {
+                let len = list.borrow().len();
+                let range = self.slice_range(expr, len)?;
+                Ok(Object::new_list(list.borrow()[range].to_vec()))
+            }
+            Object::String(string) => {
+                let range = self.slice_range(expr, string.chars().count())?;
+                Ok(Object::String(
+                    string
+                        .chars()
+                        .skip(range.start)
+                        .take(range.len())
+                        .collect::<String>()
+                        .into(),
+                ))
+            }
+            other => Err(RuntimeException::Error(RuntimeError::new(
+                expr.bracket.clone(),
+                RuntimeErrorKind::TypeMismatch {
+                    expected: "list or string",
+                    found: other.type_name(),
+                },
+                "Only lists and strings can be sliced.",
+            ))),
+        }
+    }
+
     fn visit_super_expr(&mut self, expr: &SuperExpr) -> Self::Output {
         let distance = *self
             .locals
@@ -285,18 +1500,19 @@ impl ExprVisitor for Interpreter {
                 distance - 1,
                 &Token::new(
                     TokenIdentity::This,
-                    TokenValue::String("this".to_string()),
+                    TokenValue::String(Atom::from("this")),
                     0,
                     0,
                 ),
             )?
             .to_owned();
 
-        if let Some(method) = superclass.find_method(&expr.method.value.to_string()) {
+        if let Some(method) = superclass.find_method(&expr.method.atom()) {
             Ok(Object::Function(Rc::new(method.bind(object))))
         } else {
             Err(RuntimeException::Error(RuntimeError::new(
                 expr.method.clone(),
+                RuntimeErrorKind::UndefinedProperty,
                 "Undefined property.",
             )))
         }
@@ -304,7 +1520,6 @@ impl ExprVisitor for Interpreter {
 
     fn visit_this_expr(&mut self, expr: &ThisExpr) -> Self::Output {
         self.lookup_variable(&expr.keyword, &Expr::This(expr.to_owned()))
-            .map(|r| r.to_owned())
     }
 
     fn visit_ternary_expr(&mut self, expr: &TernaryExpr) -> Self::Output {
@@ -318,16 +1533,189 @@ impl ExprVisitor for Interpreter {
 
     fn visit_unary_expr(&mut self, expr: &UnaryExpr) -> Self::Output {
         let right = self.evaluate(&expr.right)?;
-        Ok(match expr.operator.id {
-            TokenIdentity::Bang => (!right.is_truthy()).into(),
-            TokenIdentity::Minus => Object::Number(-right.maybe_to_number().unwrap()),
-            _ => Object::Nil,
-        })
+        apply_unary(expr, right)
     }
 
     fn visit_variable_expr(&mut self, expr: &VariableExpr) -> Self::Output {
         self.lookup_variable(&expr.name, &Expr::Variable(expr.to_owned()))
-            .map(|r| r.to_owned())
+    }
+}
+
+/// A step of [`Interpreter::evaluate`]. The operators wait for the values of their operands
+/// on the stack of values.
+enum Work<'a> {
+    Evaluate(&'a Expr),
+    Binary(&'a BinaryExpr),
+    /// Evaluates the right operand, unless the left one decides the result.
+    Logical(&'a LogicalExpr),
+    /// Evaluates one of the branches, depending on the condition.
+    Ternary(&'a TernaryExpr),
+    Unary(&'a UnaryExpr),
+}
+
+impl Work<'_> {
+    fn is_operator(expr: &Expr) -> bool {
+        matches!(
+            expr,
+            Expr::Binary(_)
+                | Expr::Grouping(_)
+                | Expr::Logical(_)
+                | Expr::Ternary(_)
+                | Expr::Unary(_)
+        )
+    }
+
+    /// Whether `expr` is an operator with another operator as an operand. Visiting the others
+    /// recurses at most once, which is faster than going through the stacks.
+    fn nests_operators(expr: &Expr) -> bool {
+        match expr {
+            Expr::Binary(expr) => Self::is_operator(&expr.left) || Self::is_operator(&expr.right),
+            Expr::Grouping(expr) => Self::is_operator(&expr.expression),
+            Expr::Logical(expr) => Self::is_operator(&expr.left) || Self::is_operator(&expr.right),
+            Expr::Ternary(expr) => {
+                Self::is_operator(&expr.condition)
+                    || Self::is_operator(&expr.then_branch)
+                    || Self::is_operator(&expr.else_branch)
+            }
+            Expr::Unary(expr) => Self::is_operator(&expr.right),
+            _ => false,
+        }
+    }
+}
+
+/// Whether the left operand of `expr` is its result, without evaluating the right one.
+fn short_circuits(expr: &LogicalExpr, left: &Object) -> bool {
+    match expr.operator.id {
+        TokenIdentity::Or => left.is_truthy(),
+        TokenIdentity::And => !left.is_truthy(),
+        TokenIdentity::QuestionQuestion => !matches!(left, Object::Nil | Object::Undefined),
+        _ => false,
+    }
+}
+
+/// `count` arguments, as in "2 arguments" or "1 argument".
+fn count_arguments(count: usize) -> String {
+    match count {
+        1 => "1 argument".to_string(),
+        _ => format!("{count} arguments"),
+    }
+}
+
+fn apply_unary(expr: &UnaryExpr, right: Object) -> Result<Object, RuntimeException> {
+    Ok(match expr.operator.id {
+        TokenIdentity::Bang => (!right.is_truthy()).into(),
+        TokenIdentity::Minus => match right {
+            Object::Integer(value) => value
+                .checked_neg()
+                .map_or(Object::Number(-(value as f64)), Object::Integer),
+            Object::Number(value) => Object::Number(-value),
+            _ => {
+                return Err(RuntimeException::Error(RuntimeError::new(
+                    expr.operator.clone(),
+                    RuntimeErrorKind::TypeMismatch {
+                        expected: "number",
+                        found: right.type_name(),
+                    },
+                    "Operand must be a number.",
+                )));
+            }
+        },
+        TokenIdentity::Typeof => Object::String(right.type_name().into()),
+        _ => Object::Nil,
+    })
+}
+
+/// `string * count`, or `count * string`: `count` copies of `string` joined together.
+fn repeat(expr: &BinaryExpr, string: &Atom, count: &Object) -> Result<Object, RuntimeException> {
+    let Some(count) = count.maybe_to_integer().filter(|count| *count >= 0) else {
+        return Err(RuntimeException::Error(RuntimeError::new(
+            expr.operator.clone(),
+            RuntimeErrorKind::InvalidArgument,
+            &format!("String repetition count must be a non-negative integer, got {count}."),
+        )));
+    };
+    if string
+        .len()
+        .checked_mul(count as usize)
+        .is_none_or(|len| len > isize::MAX as usize)
+    {
+        return Err(RuntimeException::Error(RuntimeError::new(
+            expr.operator.clone(),
+            RuntimeErrorKind::InvalidArgument,
+            &format!("String repetition count {count} is too large."),
+        )));
+    }
+    Ok(Object::String(string.repeat(count as usize).into()))
+}
+
+/// `+`, `-`, `*` or `/` between two numbers. Integers give an integer when the result is one
+/// and fits in 64 bits, and a float otherwise, as do floats mixed with integers.
+fn arithmetic(operator: TokenIdentity, left: &Object, right: &Object) -> Object {
+    if let (Object::Integer(left), Object::Integer(right)) = (left, right) {
+        let exact = match operator {
+            TokenIdentity::Plus => left.checked_add(*right),
+            TokenIdentity::Minus => left.checked_sub(*right),
+            TokenIdentity::Star => left.checked_mul(*right),
+            _ => left
+                .checked_rem(*right)
+                .filter(|remainder| *remainder == 0)
+                .and_then(|_| left.checked_div(*right)),
+        };
+        if let Some(result) = exact {
+            return Object::Integer(result);
+        }
+    }
+    let (left, right) = (
+        left.maybe_to_number().unwrap(),
+        right.maybe_to_number().unwrap(),
+    );
+    Object::Number(match operator {
+        TokenIdentity::Plus => left + right,
+        TokenIdentity::Minus => left - right,
+        TokenIdentity::Star => left * right,
+        _ => left / right,
+    })
+}
+
+/// `<`, `<=`, `>` or `>=`, between two numbers or two strings. Strings compare by their
+/// characters, in code point order.
+fn compare(expr: &BinaryExpr, left: &Object, right: &Object) -> Result<Object, RuntimeException> {
+    let ordering = match (left, right) {
+        (Object::Integer(left), Object::Integer(right)) => Some(left.cmp(right)),
+        (Object::Number(_) | Object::Integer(_), Object::Number(_) | Object::Integer(_)) => {
+            let (left, right) = (left.maybe_to_number(), right.maybe_to_number());
+            left.unwrap().partial_cmp(&right.unwrap())
+        }
+        (Object::String(left), Object::String(right)) => Some(left.as_str().cmp(right.as_str())),
+        _ => {
+            let (expected, found) = match left {
+                Object::Number(_) | Object::Integer(_) | Object::String(_) => {
+                    (left.type_name(), right.type_name())
+                }
+                _ => ("number or string", left.type_name()),
+            };
+            return Err(RuntimeException::Error(RuntimeError::new(
+                expr.operator.clone(),
+                RuntimeErrorKind::TypeMismatch { expected, found },
+                "Operands must be two numbers or two strings.",
+            )));
+        }
+    };
+    Ok(Object::Boolean(ordering.is_some_and(
+        |ordering| match expr.operator.id {
+            TokenIdentity::Greater => ordering.is_gt(),
+            TokenIdentity::GreaterEqual => ordering.is_ge(),
+            TokenIdentity::Less => ordering.is_lt(),
+            _ => ordering.is_le(),
+        },
+    )))
+}
+
+/// The type of the operand that made an arithmetic operator fail.
+fn non_number(left: &Object, right: &Object) -> &'static str {
+    match left {
+        Object::Number(_) | Object::Integer(_) => right.type_name(),
+        _ => left.type_name(),
     }
 }
 
@@ -343,11 +1731,11 @@ impl StmtVisitor for Interpreter {
         )
     }
 
-    fn visit_break_stmt(&self) -> Self::Output {
+    fn visit_break_stmt(&mut self, _stmt: &BreakStmt) -> Self::Output {
         Err(RuntimeException::Break)
     }
 
-    fn visit_continue_stmt(&self) -> Self::Output {
+    fn visit_continue_stmt(&mut self, _stmt: &ContinueStmt) -> Self::Output {
         Err(RuntimeException::Continue)
     }
 
@@ -355,9 +1743,13 @@ impl StmtVisitor for Interpreter {
         let superclass = if let Some(superclass) = &stmt.superclass {
             match self.evaluate(&Expr::Variable(superclass.to_owned()))? {
                 Object::Class(lox_class) => Some(lox_class),
-                _ => {
+                other => {
                     return Err(RuntimeException::Error(RuntimeError::new(
                         superclass.name.clone(),
+                        RuntimeErrorKind::TypeMismatch {
+                            expected: "class",
+                            found: other.type_name(),
+                        },
                         "Superclass must be a class.",
                     )));
                 }
@@ -366,42 +1758,54 @@ impl StmtVisitor for Interpreter {
             None
         };
 
-        if stmt.superclass.is_some() {
-            if let Some(superclass) = superclass.clone() {
-                self.environment = Rc::new(RefCell::new(Environment::new(Some(
-                    self.environment.clone(),
-                ))));
-                self.environment
-                    .borrow_mut()
-                    .define("super", Object::Class(superclass));
-            }
+        if stmt.superclass.is_some()
+            && let Some(superclass) = superclass.clone()
+        {
+            self.environment = Rc::new(RefCell::new(Environment::new(Some(
+                self.environment.clone(),
+            ))));
+            self.environment
+                .borrow_mut()
+                .define("super", Object::Class(superclass));
         }
 
         let mut methods = HashMap::new();
         for method in &stmt.methods {
-            let function = LoxFunction::new(method.clone(), self.environment.clone(), method.kind);
-            methods.insert(method.name.value.to_string(), Rc::new(function));
+            let function =
+                LoxFunction::new(Rc::clone(method), self.environment.clone(), method.kind);
+            methods.insert(method.name.atom(), Rc::new(function));
         }
 
         for method in &stmt.getter_methods {
             let function = LoxFunction::new(
-                method.clone(),
+                Rc::clone(method),
                 self.environment.clone(),
                 FunctionType::GetterMethod,
             );
-            methods.insert(method.name.value.to_string(), Rc::new(function));
+            methods.insert(method.name.atom(), Rc::new(function));
         }
 
+        // Static methods have no `this`, but the resolver still opens a scope for them, so
+        // their closure gets an empty environment on top of the enclosing one.
+        let static_environment = Rc::new(RefCell::new(Environment::new(Some(
+            self.environment.clone(),
+        ))));
         for method in &stmt.static_methods {
             let function = LoxFunction::new(
-                method.clone(),
-                Rc::new(RefCell::new(Environment::new(None))),
+                Rc::clone(method),
+                static_environment.clone(),
                 FunctionType::StaticMethod,
             );
-            methods.insert(method.name.value.to_string(), Rc::new(function));
+            methods.insert(method.name.atom(), Rc::new(function));
         }
 
-        let kclass = LoxClass::new(stmt.name.value.to_string(), superclass.clone(), methods);
+        let kclass = LoxClass::new(
+            stmt.name.value.to_string(),
+            superclass.clone(),
+            methods,
+            Rc::clone(&stmt.fields),
+            self.environment.clone(),
+        );
 
         if superclass.is_some() {
             self.environment = self
@@ -414,47 +1818,119 @@ impl StmtVisitor for Interpreter {
                 .clone();
         }
 
-        self.environment
-            .borrow_mut()
-            .define(&stmt.name.value.to_string(), Object::Class(Rc::new(kclass)));
+        let class = Object::Class(kclass);
+        self.declare(&stmt.name, class.clone())?;
+
+        for block in &stmt.static_blocks {
+            self.execute_block(
+                &block.statements,
+                Rc::new(RefCell::new(Environment::new(Some(
+                    static_environment.clone(),
+                )))),
+            )?;
+        }
+
+        Ok(class)
+    }
 
+    fn visit_destructure_stmt(&mut self, stmt: &DestructureStmt) -> Self::Output {
+        let value = self.evaluate(&stmt.initializer)?;
+        let values = self.destructure(&stmt.pattern, &value)?;
+        for (name, item) in stmt.pattern.names().iter().zip(values) {
+            self.declare(name, item)?;
+            if stmt.is_const {
+                self.environment.borrow_mut().make_const(name.atom());
+            }
+        }
         Ok(Object::Undefined)
     }
 
+    fn visit_export_stmt(&mut self, stmt: &ExportStmt) -> Self::Output {
+        self.execute(&stmt.declaration)
+    }
+
     fn visit_expression_stmt(&mut self, stmt: &ExpressionStmt) -> Self::Output {
         self.evaluate(&stmt.expr)
     }
 
-    fn visit_function_stmt(&mut self, stmt: &FunctionStmt) -> Self::Output {
+    fn visit_for_stmt(&mut self, stmt: &ForStmt) -> Self::Output {
+        let previous = self.environment.clone();
+        self.environment = Rc::new(RefCell::new(Environment::new(Some(previous.clone()))));
+        let result = self.run_for_loop(stmt, &previous);
+        self.environment = previous;
+        result
+    }
+
+    fn visit_for_in_stmt(&mut self, stmt: &ForInStmt) -> Self::Output {
+        // Iterate over a snapshot so the body can freely modify the collection.
+        let items = match self.evaluate(&stmt.iterable)?.flatten() {
+            Object::List(list) => list.borrow().clone(),
+            Object::String(string) => string
+                .chars()
+                .map(|c| Object::String(c.to_string().into()))
+                .collect(),
+            Object::Map(map) => map.borrow().entries().map(|(key, _)| key.clone()).collect(),
+            other => {
+                return Err(RuntimeException::Error(RuntimeError::new(
+                    stmt.keyword.clone(),
+                    RuntimeErrorKind::TypeMismatch {
+                        expected: "list, string or map",
+                        found: other.type_name(),
+                    },
+                    "Can only iterate over lists, strings and maps.",
+                )));
+            }
+        };
+
+        for item in items {
+            // Each iteration gets a fresh binding, so closures capture the current item.
+            let mut environment = Environment::new(Some(self.environment.clone()));
+            environment.define(stmt.name.atom(), item);
+            match self.execute_block(&stmt.body.statements, Rc::new(RefCell::new(environment))) {
+                Ok(_) | Err(RuntimeException::Continue) => continue,
+                Err(RuntimeException::Break) => break,
+                Err(error) => return Err(error),
+            }
+        }
+        Ok(Object::Undefined)
+    }
+
+    fn visit_function_stmt(&mut self, stmt: &Rc<FunctionStmt>) -> Self::Output {
         let lox = LoxFunction::new(
-            stmt.to_owned(),
+            Rc::clone(stmt),
             self.environment.clone(),
             FunctionType::Function,
         );
-        self.environment
-            .borrow_mut()
-            .define(&stmt.name.value.to_string(), Object::Function(Rc::new(lox)));
-        Ok(Object::Undefined)
+        let function = Object::Function(Rc::new(lox));
+        self.declare(&stmt.name, function.clone())?;
+        Ok(function)
     }
 
     fn visit_if_stmt(&mut self, stmt: &IfStmt) -> Self::Output {
         if self.evaluate(&stmt.condition)?.is_truthy() {
             self.visit_block_stmt(&stmt.then_branch)
         } else if let Some(else_branch) = &stmt.else_branch {
-            self.visit_block_stmt(else_branch)
+            self.execute(else_branch)
         } else {
             Ok(Object::Undefined)
         }
     }
 
+    fn visit_import_stmt(&mut self, stmt: &ImportStmt) -> Self::Output {
+        let module = Object::Module(self.import(&stmt.keyword, &stmt.path.value.to_string())?);
+        self.declare(&stmt.name, module.clone())?;
+        Ok(module)
+    }
+
     fn visit_print_stmt(&mut self, stmt: &PrintStmt) -> Self::Output {
         let value = self.evaluate(&stmt.expr)?;
-        writeln!(self.writer.borrow_mut(), "{value}").unwrap();
+        writeln!(self.writer.borrow_mut(), "{value}").map_err(output_error)?;
         Ok(Object::Undefined)
     }
 
     fn visit_return_stmt(&mut self, stmt: &ReturnStmt) -> Self::Output {
         match &stmt.value {
+            Some(value) if self.try_depth == 0 => self.return_tail(value),
             Some(value) => Err(RuntimeException::Return(RuntimeReturn::new(
                 self.evaluate(value)?,
             ))),
@@ -462,16 +1938,49 @@ impl StmtVisitor for Interpreter {
         }
     }
 
-    fn visit_var_stmt(&mut self, stmt: &VarStmt) -> Self::Output {
-        if let Some(initializer) = &stmt.initializer {
-            let value = self.evaluate(initializer)?;
-            self.environment
-                .borrow_mut()
-                .define(&stmt.name.value.to_string(), value);
-        } else {
-            self.environment
+    fn visit_switch_stmt(&mut self, stmt: &SwitchStmt) -> Self::Output {
+        let subject = self.evaluate(&stmt.subject)?;
+        for (values, body) in &stmt.cases {
+            for value in values {
+                if self.evaluate(value)? == subject {
+                    return self.visit_block_stmt(body);
+                }
+            }
+        }
+        match &stmt.default {
+            Some(default) => self.visit_block_stmt(default),
+            None => Ok(Object::Undefined),
+        }
+    }
+
+    fn visit_throw_stmt(&mut self, stmt: &ThrowStmt) -> Self::Output {
+        let value = self.evaluate(&stmt.value)?;
+        if let Object::Instance(instance) = &value
+            && self.is_error(&value)
+            && instance.borrow().field("line") == Some(&Object::Nil)
+        {
+            instance
                 .borrow_mut()
-                .define(&stmt.name.value.to_string(), Object::Undefined);
+                .define_field("line", Object::Integer(stmt.keyword.line as i64));
+        }
+        Err(RuntimeException::Throw(value))
+    }
+
+    fn visit_try_stmt(&mut self, stmt: &TryStmt) -> Self::Output {
+        self.try_depth += 1;
+        let result = self.run_try(stmt);
+        self.try_depth -= 1;
+        result
+    }
+
+    fn visit_var_stmt(&mut self, stmt: &VarStmt) -> Self::Output {
+        let value = match &stmt.initializer {
+            Some(initializer) => self.evaluate(initializer)?,
+            None => Object::Undefined,
+        };
+        self.declare(&stmt.name, value)?;
+        if stmt.is_const {
+            self.environment.borrow_mut().make_const(stmt.name.atom());
         }
         Ok(Object::Undefined)
     }
@@ -490,3 +1999,476 @@ impl StmtVisitor for Interpreter {
         Ok(Object::Undefined)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, io, rc::Rc};
+
+    use super::*;
+    use crate::{
+        error::LoxError, parser::Parser, resolver::Resolver, scanner::Scanner, timings::run_timed,
+    };
+
+    fn run_with(source: &str, uninitialized_read: UninitializedRead) -> Result<Object, String> {
+        let tokens = Scanner::new(source).collect::<Vec<Token>>();
+        let statements = Parser::new(tokens).parse().unwrap();
+        let mut interpreter = Interpreter::new(Rc::new(RefCell::new(io::sink())))
+            .with_uninitialized_read(uninitialized_read);
+        Resolver::new(&mut interpreter)
+            .resolve_stmts(&statements)
+            .unwrap();
+        interpreter
+            .interpret(&statements)
+            .map_err(|e| e.to_string())
+    }
+
+    fn run(source: &str) -> Object {
+        run_with(source, UninitializedRead::default()).unwrap()
+    }
+
+    #[test]
+    fn test_declarations_return_their_object() {
+        assert_eq!(run("fun foo() {}").to_string(), "<fn foo>");
+        assert_eq!(run("class Foo {}").to_string(), "Foo");
+        assert_eq!(run("class Foo {} class Bar < Foo {}").to_string(), "Bar");
+    }
+
+    #[test]
+    fn test_closures_share_their_declaration() {
+        let source = "
+            fun make() { return fun () {}; }
+            var a = make();
+            var b = make();
+            class C { m() {} }
+        ";
+        let statements = Parser::new(Scanner::new(source).collect()).parse().unwrap();
+        let mut interpreter = Interpreter::new(Rc::new(RefCell::new(io::sink())));
+        Resolver::new(&mut interpreter)
+            .resolve_stmts(&statements)
+            .unwrap();
+        assert!(interpreter.interpret(&statements).is_ok());
+
+        let Stmt::Function(make) = &statements[0] else {
+            panic!("Expected a function");
+        };
+        assert_eq!(Rc::strong_count(make), 2);
+        let Stmt::Return(ReturnStmt {
+            value: Some(Expr::Lambda(lambda)),
+            ..
+        }) = &make.body.statements[0]
+        else {
+            panic!("Expected a returned lambda");
+        };
+        assert_eq!(Rc::strong_count(lambda), 3);
+        let Stmt::Class(class) = &statements[3] else {
+            panic!("Expected a class");
+        };
+        assert_eq!(Rc::strong_count(&class.methods[0]), 2);
+    }
+
+    #[test]
+    fn test_classes_are_called_as_they_are() {
+        let (mut interpreter, _) = Interpreter::with_capture();
+        let Object::Class(class) = run_timed("class A {}", &mut interpreter).0.ok().unwrap() else {
+            panic!("Expected a class");
+        };
+        let statements = Parser::new(Scanner::new("A();").collect()).parse().unwrap();
+        let Stmt::Expression(ExpressionStmt {
+            expr: Expr::Call(call),
+        }) = &statements[0]
+        else {
+            panic!("Expected a call");
+        };
+        let (callee, _) = interpreter.prepare_call(call).ok().unwrap();
+        assert_eq!(Rc::as_ptr(&callee).cast::<()>(), Rc::as_ptr(&class).cast());
+
+        let Object::Instance(instance) = callee.call(&mut interpreter, Vec::new()).ok().unwrap()
+        else {
+            panic!("Expected an instance");
+        };
+        assert!(Rc::ptr_eq(instance.borrow().class(), &class));
+    }
+
+    #[test]
+    fn test_uninitialized_read() {
+        let source = "var a; a;";
+        assert_eq!(
+            run_with(source, UninitializedRead::Error).unwrap_err(),
+            "[line 1:8] Runtime error at 'a': The variable isn't initialized."
+        );
+        assert_eq!(run_with(source, UninitializedRead::Nil), Ok(Object::Nil));
+
+        let source = "fun f() { var b; return b; } f();";
+        assert!(run_with(source, UninitializedRead::Error).is_err());
+        assert_eq!(run_with(source, UninitializedRead::Nil), Ok(Object::Nil));
+
+        let source = "var c; c = 1; c;";
+        assert_eq!(
+            run_with(source, UninitializedRead::Error),
+            Ok(Object::Number(1.0))
+        );
+    }
+
+    #[test]
+    fn test_labelled_errors() {
+        let error = |source: &str| run_timed(source, &mut Interpreter::with_capture().0).0;
+        let label = |line: usize| format!("input {line}");
+
+        let parsing = error("print(1 +);").unwrap_err();
+        assert_eq!(
+            parsing.labelled(&label).to_string(),
+            "[input 1:10] Parsing error at ')': Unexpected expression"
+        );
+        assert_eq!(parsing.token().map(|token| token.column), Some(10));
+
+        let runtime = error("\nnope;").unwrap_err();
+        assert_eq!(
+            runtime.labelled(&label).to_string(),
+            "[input 2:1] Runtime error at 'nope': Undefined variable."
+        );
+        assert_eq!(
+            runtime.to_string(),
+            "[line 2:1] Runtime error at 'nope': Undefined variable."
+        );
+
+        let thrown = error("throw Error(\"boom\");").unwrap_err();
+        assert_eq!(
+            thrown.labelled(&label).to_string(),
+            "[input 1] Uncaught Error: boom"
+        );
+        assert!(thrown.token().is_none());
+    }
+
+    #[test]
+    fn test_error_kinds() {
+        let kind = |source: &str| match run_timed(source, &mut Interpreter::with_capture().0).0 {
+            Err(LoxError::Runtime(RuntimeException::Error(error))) => error.kind().clone(),
+            Err(LoxError::Resolving(error)) => error.kind().clone(),
+            _ => panic!("{source} should fail at runtime"),
+        };
+
+        assert_eq!(kind("undefined;"), RuntimeErrorKind::UndefinedVariable);
+        assert_eq!(kind("\"a\"();"), RuntimeErrorKind::NotCallable);
+        assert_eq!(
+            kind("fun f(a) {} f();"),
+            RuntimeErrorKind::ArityMismatch {
+                expected: 1,
+                found: 0
+            }
+        );
+        assert_eq!(
+            kind("1 - true;"),
+            RuntimeErrorKind::TypeMismatch {
+                expected: "number",
+                found: "boolean"
+            }
+        );
+        assert_eq!(
+            kind("1 < \"2\";"),
+            RuntimeErrorKind::TypeMismatch {
+                expected: "number",
+                found: "string"
+            }
+        );
+        assert_eq!(
+            kind("nil >= 1;"),
+            RuntimeErrorKind::TypeMismatch {
+                expected: "number or string",
+                found: "nil"
+            }
+        );
+        assert_eq!(kind("1 / 0;"), RuntimeErrorKind::DivisionByZero);
+        assert_eq!(kind("\"ab\" * -1;"), RuntimeErrorKind::InvalidArgument);
+        assert_eq!(
+            kind("[1, 2][-3];"),
+            RuntimeErrorKind::IndexOutOfRange {
+                index: -3,
+                length: 2
+            }
+        );
+        assert_eq!(
+            kind("var m = {1: 2}; m[3];"),
+            RuntimeErrorKind::UndefinedKey
+        );
+        assert_eq!(
+            kind("class A {} A().b;"),
+            RuntimeErrorKind::UndefinedProperty
+        );
+        assert_eq!(kind("return 1;"), RuntimeErrorKind::InvalidContext);
+        assert_eq!(kind("range(0.5, 1);"), RuntimeErrorKind::InvalidArgument);
+    }
+
+    #[test]
+    fn test_global_redefinition() {
+        // Runs each source in turn in the same interpreter, as when a file is run again.
+        let run_with = |sources: &[&str], global_redefinition| {
+            let mut interpreter = Interpreter::new(Rc::new(RefCell::new(io::sink())))
+                .with_global_redefinition(global_redefinition);
+            let mut result = Ok(Object::Undefined);
+            for source in sources {
+                result = run_timed(source, &mut interpreter)
+                    .0
+                    .map_err(|e| e.to_string());
+            }
+            result
+        };
+
+        let sources = ["fun f() { return 1; }", "fun f() { return 2; } f();"];
+        assert_eq!(
+            run_with(&sources, GlobalRedefinition::Overwrite),
+            Ok(Object::Number(2.0))
+        );
+        assert_eq!(
+            run_with(&sources, GlobalRedefinition::Warn),
+            Ok(Object::Number(2.0))
+        );
+        assert_eq!(
+            run_with(&sources, GlobalRedefinition::Error).unwrap_err(),
+            "[line 1:5] Runtime error at 'f': Already a global with this name."
+        );
+        assert!(run_with(&["class A {}", "var A;"], GlobalRedefinition::Error).is_err());
+        assert!(run_with(&["var clock = 1;"], GlobalRedefinition::Error).is_err());
+
+        // Only globals are checked.
+        let sources = ["var a = 1;", "{ var a = 2; } fun g(a) { var b; } g(3);"];
+        assert!(run_with(&sources, GlobalRedefinition::Error).is_ok());
+
+        // Functions declared before a shadowing redefinition keep the old value, while those
+        // declared after, and later programs, see the new one.
+        let sources = [
+            "fun f() { return 1; } fun g() { return f(); } fun h() { return f; }",
+            "fun f() { return 2; } fun k() { return f(); }",
+            "[f(), g(), h()(), k()];",
+        ];
+        assert_eq!(
+            run_with(&sources, GlobalRedefinition::Overwrite).map(|value| value.to_string()),
+            Ok("[2, 2, 2, 2]".to_owned())
+        );
+        let diagnostics = CaptureWriter::new();
+        let mut interpreter = Interpreter::new(Rc::new(RefCell::new(io::sink())))
+            .with_global_redefinition(GlobalRedefinition::Warn)
+            .with_diagnostics(diagnostics.buffer());
+        let mut result = Ok(Object::Undefined);
+        for source in sources {
+            result = run_timed(source, &mut interpreter).0;
+        }
+        assert_eq!(result.ok().unwrap().to_string(), "[2, 1, 1, 2]");
+        assert_eq!(
+            diagnostics.into_string(),
+            "[line 1:5] Warning at 'f': Redefining a global; functions declared before keep \
+             the old value.\n"
+        );
+    }
+
+    #[test]
+    fn test_os_natives() {
+        let (interpreter, output) = Interpreter::with_capture();
+        let mut interpreter =
+            interpreter.with_script_args(vec!["--verbose".to_string(), "in.txt".to_string()]);
+        let source = "
+            print(args());
+            print(platform() == \"linux\" or platform() == \"macos\" or platform() == \"windows\");
+            print(env(\"RLOX_SURELY_UNSET_VARIABLE\"));
+            print(env(\"PATH\") == nil);
+        ";
+        assert!(run_timed(source, &mut interpreter).0.is_ok());
+        assert_eq!(
+            output.into_string(),
+            "[--verbose, in.txt]\ntrue\nnil\nfalse\n"
+        );
+    }
+
+    #[test]
+    fn test_define_native() {
+        let (mut interpreter, output) = Interpreter::with_capture();
+        interpreter.define_native("double", 1, |_, args| {
+            Ok(args[0]
+                .maybe_to_number()
+                .map_or(Object::Nil, |number| Object::Number(number * 2.0)))
+        });
+        let source = "print(double(21)); print(double); double(1, 2);";
+        let Err(LoxError::Runtime(RuntimeException::Error(error))) =
+            run_timed(source, &mut interpreter).0
+        else {
+            panic!("Calling double with two arguments should fail");
+        };
+        assert_eq!(output.into_string(), "42\n<native fn double>\n");
+        assert_eq!(
+            error.message(),
+            "<native fn double> expected 1 argument but got 2."
+        );
+    }
+
+    #[test]
+    fn test_evaluate_deeply_nested() {
+        let operator = |id| Token::new(id, TokenValue::Nil, 1, 0);
+        let literal = |value| Expr::Literal(LiteralExpr::new(value));
+        // nil ?? -((nil ?? -((... - 1)) - 1), far deeper than the native stack allows
+        // recursing.
+        let mut expr = literal(Object::Number(0.0));
+        for _ in 0..100_000 {
+            let difference = Expr::Binary(Box::new(BinaryExpr::new(
+                expr,
+                operator(TokenIdentity::Minus),
+                literal(Object::Number(1.0)),
+            )));
+            let negated = Expr::Unary(Box::new(UnaryExpr::new(
+                operator(TokenIdentity::Minus),
+                Expr::Grouping(Box::new(GroupingExpr::new(difference))),
+            )));
+            expr = Expr::Logical(Box::new(LogicalExpr::new(
+                literal(Object::Nil),
+                operator(TokenIdentity::QuestionQuestion),
+                negated,
+            )));
+        }
+
+        let (mut interpreter, _) = Interpreter::with_capture();
+        assert!(matches!(
+            interpreter.evaluate(&expr),
+            Ok(Object::Number(0.0))
+        ));
+
+        // Dropping the tree as is would recurse as deep as it's nested.
+        loop {
+            expr = match expr {
+                Expr::Binary(expr) => expr.left,
+                Expr::Grouping(expr) => expr.expression,
+                Expr::Logical(expr) => expr.right,
+                Expr::Unary(expr) => expr.right,
+                _ => break,
+            };
+        }
+    }
+
+    #[test]
+    fn test_stats() {
+        let (mut interpreter, output) = Interpreter::with_capture();
+        let source = "
+            fun countdown(n) {
+                if (n > 0) countdown(n - 1);
+            }
+            countdown(3);
+            var counters = stats();
+            print(counters[\"calls\"]);
+            print(counters[\"peak_call_depth\"]);
+        ";
+        assert!(run_timed(source, &mut interpreter).0.is_ok());
+        assert_eq!(output.into_string(), "5\n4\n");
+        assert_eq!(interpreter.stats.call_depth, 0);
+        assert!(interpreter.stats.statements >= 10);
+        assert!(interpreter.stats.environments() >= 4);
+    }
+
+    #[test]
+    fn test_integers() {
+        assert!(matches!(run("7 / 2;"), Object::Number(3.5)));
+        assert!(matches!(run("8 / 2;"), Object::Integer(4)));
+        assert!(matches!(run("2 * 1.5;"), Object::Number(3.0)));
+        assert!(matches!(run("-(3 - 5);"), Object::Integer(2)));
+        assert!(matches!(
+            run("9223372036854775807 + 1;"),
+            Object::Number(9223372036854775808.0)
+        ));
+        assert!(matches!(
+            run("-(-9223372036854775807 - 1);"),
+            Object::Number(9223372036854775808.0)
+        ));
+        assert_eq!(run("9007199254740993 + 0;").to_string(), "9007199254740993");
+        assert!(matches!(run("1 == 1.0;"), Object::Boolean(true)));
+    }
+
+    /// An output whose reader went away, like a pipe to a command that already exited.
+    struct ClosedPipe;
+
+    impl io::Write for ClosedPipe {
+        fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+            Err(io::ErrorKind::BrokenPipe.into())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Err(io::ErrorKind::BrokenPipe.into())
+        }
+    }
+
+    #[test]
+    fn test_output_failure() {
+        let kind = |result: Result<Object, LoxError>| match result {
+            Err(LoxError::Runtime(RuntimeException::Error(error))) => error.kind().clone(),
+            _ => panic!("Expected a runtime error"),
+        };
+        let mut interpreter = Interpreter::new(Rc::new(RefCell::new(ClosedPipe)));
+        assert_eq!(
+            kind(run_timed("print(1);", &mut interpreter).0),
+            RuntimeErrorKind::IoFailed
+        );
+
+        // Printing into a buffer succeeds, but flushing it once the program ends doesn't.
+        let writer = Rc::new(RefCell::new(io::BufWriter::new(ClosedPipe)));
+        let mut interpreter = Interpreter::new(writer);
+        assert_eq!(
+            kind(run_timed("print(1);", &mut interpreter).0),
+            RuntimeErrorKind::IoFailed
+        );
+        let error = run_timed("print(1);", &mut interpreter).0.err().unwrap();
+        assert!(error.to_string().contains("Failed to write the output"));
+    }
+
+    #[test]
+    fn test_interpreters_share_prelude_and_natives() {
+        let (mut first, _) = Interpreter::with_capture();
+        let parses = PARSES.get();
+        let (mut second, _) = Interpreter::with_capture();
+        let (_third, _) = Interpreter::with_capture();
+        assert_eq!(PARSES.get(), parses);
+
+        for name in ["clock;", "math;"] {
+            match (
+                run_timed(name, &mut first).0.ok().unwrap(),
+                run_timed(name, &mut second).0.ok().unwrap(),
+            ) {
+                (Object::Function(a), Object::Function(b)) => assert!(Rc::ptr_eq(&a, &b)),
+                (Object::Module(a), Object::Module(b)) => assert!(Rc::ptr_eq(&a, &b)),
+                values => panic!("Expected builtins, got {values:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_load_prelude() {
+        let (mut interpreter, output) = Interpreter::with_capture();
+        assert!(
+            interpreter
+                .load_prelude("fun twice(x) = 2 * x; var greeting = \"hi\";")
+                .is_ok()
+        );
+        let source = "print(twice(21)); fun shout() = greeting + \"!\"; print(shout());";
+        assert!(run_timed(source, &mut interpreter).0.is_ok());
+        assert_eq!(output.into_string(), "42\nhi!\n");
+
+        assert!(matches!(
+            interpreter.load_prelude("fun broken( {"),
+            Err(LoxError::Parsing(_))
+        ));
+    }
+
+    #[cfg(feature = "stdlib")]
+    #[test]
+    fn test_stdlib() {
+        let (mut interpreter, output) = Interpreter::with_capture();
+        let source = "
+            print(max(3, 7) + min(3, 7) + abs(-2) + clamp(12, 0, 10));
+            print(len([1, 2, 3]) + len(\"héllo\") + len({\"a\": 1}));
+            print(sum([1, 2, 3, 4]));
+            print(reduce([\"a\", \"b\"], |a, b| a + b, \"\"));
+            print(any([1, 2], |x| x > 1));
+            print(all([1, 2], |x| x > 1));
+            print(count(range(0, 10), |x| x > 6));
+        ";
+        assert!(run_timed(source, &mut interpreter).0.is_ok());
+        assert_eq!(output.into_string(), "22\n9\n10\nab\ntrue\nfalse\n3\n");
+
+        let mut interpreter = Interpreter::without_features(Rc::new(RefCell::new(io::sink())));
+        assert!(run_timed("max(3, 7);", &mut interpreter).0.is_err());
+    }
+}
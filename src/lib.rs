@@ -1,14 +1,203 @@
+//! A tree-walking interpreter for Lox, following the first part of Crafting Interpreters.
+//!
+//! Embedders should import from [`prelude`]. The other public modules expose the stages of
+//! the interpreter for tooling and are less stable.
+
+mod ast;
 mod builtin_funcs;
 mod class;
+#[cfg(feature = "datetime")]
+mod datetime;
 mod environment;
 mod expr;
 mod function;
+mod gc;
+mod map;
 mod object;
+mod optimizer;
+mod random;
+mod rope;
 mod stmt;
+mod string_methods;
 
+pub mod ast_printer;
+pub mod atom;
+pub mod capture;
 pub mod error;
+pub mod grade;
 pub mod interpreter;
 pub mod parser;
+pub mod prelude;
 pub mod resolver;
 pub mod scanner;
+pub mod timings;
 pub mod token;
+
+use std::{cell::RefCell, fs, io, mem, path::Path, rc::Rc};
+
+use crate::{prelude::*, resolver::TopLevel};
+
+/// An interpreter ready to run programs, hiding the stages each one goes through. The globals
+/// a program defines stay for the programs run after it.
+///
+/// ```
+/// use crafting_interpreters::{capture::CaptureWriter, prelude::*};
+///
+/// let output = CaptureWriter::new();
+/// let mut lox = Lox::new().with_writer(output.buffer());
+/// assert!(lox.run("var greeting = \"Hello\";").is_ok());
+/// assert_eq!(lox.run("print(greeting); 1 + 2;").ok().unwrap(), Object::Integer(3));
+/// assert_eq!(output.into_string(), "Hello\n");
+/// ```
+pub struct Lox {
+    interpreter: Interpreter,
+    /// The top-level scope left by the inputs run with [`run_input`](Self::run_input).
+    top_level: TopLevel,
+    /// The names of those inputs, shared so that a name repeated between them is stored once.
+    atoms: Rc<RefCell<AtomTable>>,
+}
+
+impl Lox {
+    /// An interpreter printing to stdout, whose programs can't read or write files until
+    /// [`with_file_access`](Self::with_file_access) allows them to.
+    pub fn new() -> Self {
+        Self::from(Interpreter::new(Rc::new(RefCell::new(io::stdout()))).with_file_access(false))
+    }
+
+    /// Sets where the programs print to.
+    pub fn with_writer(mut self, writer: Rc<RefCell<impl io::Write + 'static>>) -> Self {
+        self.interpreter.writer = writer;
+        self
+    }
+
+    /// Sets where warnings, such as those about unreachable code, go instead of stderr.
+    pub fn with_diagnostics(mut self, diagnostics: Rc<RefCell<impl io::Write + 'static>>) -> Self {
+        self.interpreter.diagnostics = diagnostics;
+        self
+    }
+
+    /// Sets the grammar of the programs, such as the book's with [`Dialect::Lox`].
+    pub fn with_dialect(mut self, dialect: Dialect) -> Self {
+        self.interpreter.dialect = dialect;
+        self
+    }
+
+    /// Matches keywords regardless of case, so that `IF` and `If` both read as `if`.
+    pub fn with_case_insensitive_keywords(mut self, case_insensitive_keywords: bool) -> Self {
+        self.interpreter.case_insensitive_keywords = case_insensitive_keywords;
+        self
+    }
+
+    /// Reads a name written with a leading backslash, such as `\class`, as an identifier even
+    /// when it's a keyword.
+    pub fn with_raw_identifiers(mut self, raw_identifiers: bool) -> Self {
+        self.interpreter.raw_identifiers = raw_identifiers;
+        self
+    }
+
+    /// Lets the programs read and write files with `readFile` and `writeFile`.
+    pub fn with_file_access(mut self, file_access: bool) -> Self {
+        self.interpreter.file_access = file_access;
+        self
+    }
+
+    /// Limits how deep calls can nest, so that a runaway recursion raises an error instead of
+    /// overflowing the native stack.
+    pub fn with_max_call_depth(mut self, max_call_depth: usize) -> Self {
+        self.interpreter.max_call_depth = Some(max_call_depth);
+        self
+    }
+
+    /// The interpreter, to set the options this type doesn't cover or define natives.
+    pub fn interpreter(&mut self) -> &mut Interpreter {
+        &mut self.interpreter
+    }
+
+    /// Defines a global function implemented by `function`, for the programs to call the
+    /// host with. See [`Interpreter::define_native`].
+    pub fn define_native(
+        &mut self,
+        name: &str,
+        arity: usize,
+        function: impl Fn(&mut Interpreter, Vec<Object>) -> Result<Object, RuntimeException> + 'static,
+    ) {
+        self.interpreter.define_native(name, arity, function);
+    }
+
+    /// Runs `source`, returning the value of its last statement. A call to `exit` ends it
+    /// with a [`RuntimeException::Exit`] error.
+    pub fn run(&mut self, source: &str) -> Result<Object, LoxError> {
+        run_timed(source, &mut self.interpreter).0
+    }
+
+    /// Runs the script at `path`, whose imports are relative to its directory.
+    pub fn run_file(&mut self, path: impl AsRef<Path>) -> Result<Object, LoxError> {
+        let path = path.as_ref();
+        let source = fs::read_to_string(path).map_err(|e| {
+            LoxError::Runtime(RuntimeException::Error(RuntimeError::native(
+                RuntimeErrorKind::IoFailed,
+                &format!("Failed to read '{}': {e}", path.display()),
+            )))
+        })?;
+        let module_dir = path.parent().unwrap_or(Path::new("."));
+        let module_dir = mem::replace(&mut self.interpreter.module_dir, module_dir.into());
+        let result = self.run(&source);
+        self.interpreter.module_dir = module_dir;
+        result
+    }
+
+    /// Runs `source` as the next input of a session, such as the lines typed at a prompt,
+    /// numbering its lines from `first_line`. Its last expression can leave out its `;`. What
+    /// the inputs before it declared can be declared again, while an input that fails to
+    /// resolve leaves nothing behind. Returns the value of the input if it ends with an
+    /// expression, for a REPL to show.
+    pub fn run_input(
+        &mut self,
+        source: &str,
+        first_line: usize,
+    ) -> Result<Option<Object>, LoxError> {
+        let tokens = self
+            .interpreter
+            .scanner(source)
+            .with_atoms(self.atoms.clone())
+            .with_first_line(first_line)
+            .collect();
+        let mut statements = Parser::new(tokens)
+            .with_dialect(self.interpreter.dialect)
+            .with_optional_final_semicolon(true)
+            .parse()
+            .map_err(LoxError::Parsing)?;
+        let mut resolver =
+            Resolver::new(&mut self.interpreter).with_top_level(mem::take(&mut self.top_level));
+        let resolved = resolver.resolve_input(&statements);
+        if resolved.is_ok() {
+            resolver.optimize(&mut statements);
+        }
+        self.top_level = resolver.into_top_level();
+        resolved.map_err(LoxError::Resolving)?;
+        let value = self
+            .interpreter
+            .interpret(&statements)
+            .map_err(LoxError::Runtime)?;
+        Ok(statements
+            .last()
+            .is_some_and(|stmt| stmt.is_expression())
+            .then_some(value))
+    }
+}
+
+impl Default for Lox {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl From<Interpreter> for Lox {
+    fn from(interpreter: Interpreter) -> Self {
+        Self {
+            interpreter,
+            top_level: TopLevel::default(),
+            atoms: Rc::new(RefCell::new(AtomTable::new())),
+        }
+    }
+}
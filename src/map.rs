@@ -0,0 +1,107 @@
+use std::{collections::HashMap, fmt};
+
+use crate::{atom::Atom, object::Object};
+
+/// The hashable subset of objects, used to look up map entries.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum MapKey {
+    Boolean(bool),
+    Number(u64),
+    /// An integer too large to be exactly a float, so equal to no float.
+    Integer(i64),
+    String(Atom),
+    Nil,
+}
+
+impl MapKey {
+    fn new(key: &Object) -> Option<Self> {
+        match key {
+            Object::Boolean(value) => Some(MapKey::Boolean(*value)),
+            // `-0 == 0` in Lox, so both zeroes have to hash to the same key.
+            Object::Number(value) if *value == 0.0 => Some(MapKey::Number(0.0f64.to_bits())),
+            Object::Number(value) => Some(MapKey::Number(value.to_bits())),
+            // Integers equal to a float share its key.
+            Object::Integer(0) => Some(MapKey::Number(0.0f64.to_bits())),
+            Object::Integer(value) if *value as f64 as i64 == *value => {
+                Some(MapKey::Number((*value as f64).to_bits()))
+            }
+            Object::Integer(value) => Some(MapKey::Integer(*value)),
+            Object::String(value) => Some(MapKey::String(value.clone())),
+            Object::Rope(value) => Some(MapKey::String(value.flat().clone())),
+            Object::Nil => Some(MapKey::Nil),
+            _ => None,
+        }
+    }
+}
+
+/// A hash map keyed on booleans, numbers, strings and nil, which keeps its entries in
+/// insertion order so that printing a map is deterministic.
+#[derive(Clone, Debug, Default)]
+pub struct LoxMap {
+    entries: Vec<(Object, Object)>,
+    indices: HashMap<MapKey, usize>,
+}
+
+impl LoxMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_hashable(key: &Object) -> bool {
+        MapKey::new(key).is_some()
+    }
+
+    pub fn get(&self, key: &Object) -> Option<&Object> {
+        let index = self.indices.get(&MapKey::new(key)?)?;
+        Some(&self.entries[*index].1)
+    }
+
+    /// Inserts or replaces the value of `key`. Returns `false` without inserting anything
+    /// when the key isn't hashable.
+    pub fn insert(&mut self, key: Object, value: Object) -> bool {
+        let Some(hash_key) = MapKey::new(&key) else {
+            return false;
+        };
+        if let Some(index) = self.indices.get(&hash_key) {
+            self.entries[*index].1 = value;
+        } else {
+            self.indices.insert(hash_key, self.entries.len());
+            self.entries.push((key, value));
+        }
+        true
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &(Object, Object)> {
+        self.entries.iter()
+    }
+}
+
+impl PartialEq for LoxMap {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len()
+            && self
+                .entries()
+                .all(|(key, value)| other.get(key) == Some(value))
+    }
+}
+
+impl fmt::Display for LoxMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{{")?;
+        for (i, (key, value)) in self.entries().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{key}: {value}")?;
+        }
+        write!(f, "}}")
+    }
+}
@@ -1,37 +1,80 @@
 use std::{
     cell::RefCell,
+    collections::{HashMap, HashSet},
     fmt::{self, Debug},
     rc::Rc,
 };
 
 use crate::{
+    atom::Atom,
     builtin_funcs::LoxCallable,
     class::{LoxClass, LoxInstance},
+    gc,
+    map::LoxMap,
+    rope::Rope,
 };
 
 #[derive(Clone, Debug)]
 pub enum Object {
     Boolean(bool),
+    /// A floating-point number, from a literal with a decimal point or arithmetic that isn't
+    /// exact on integers.
     Number(f64),
-    String(String),
+    /// A whole number, from a literal without a decimal point. Arithmetic on integers stays
+    /// exact as long as it fits in 64 bits.
+    Integer(i64),
+    String(Atom),
+    /// A long string built by `+`, behaving as a string.
+    Rope(Rc<Rope>),
     Function(Rc<dyn LoxCallable>),
     Instance(Rc<RefCell<LoxInstance>>),
     Class(Rc<LoxClass>),
+    List(Rc<RefCell<Vec<Object>>>),
+    Map(Rc<RefCell<LoxMap>>),
+    Module(Rc<LoxModule>),
     Nil,
     Undefined,
 }
 
 impl Object {
+    /// A new list, tracked by the cycle collector.
+    pub fn new_list(elements: Vec<Object>) -> Object {
+        let list = Object::List(Rc::new(RefCell::new(elements)));
+        gc::track(&list);
+        list
+    }
+
+    /// A new map, tracked by the cycle collector.
+    pub fn new_map(map: LoxMap) -> Object {
+        let map = Object::Map(Rc::new(RefCell::new(map)));
+        gc::track(&map);
+        map
+    }
+
     pub fn maybe_to_string(&self) -> Option<String> {
         match self {
-            Object::String(value) => Some(value.clone()),
+            Object::String(value) => Some(value.to_string()),
+            Object::Rope(value) => Some(value.flat().to_string()),
             _ => None,
         }
     }
 
+    /// The value of an integer or a floating-point number, as a float.
     pub fn maybe_to_number(&self) -> Option<f64> {
         match self {
             Object::Number(value) => Some(*value),
+            Object::Integer(value) => Some(*value as f64),
+            _ => None,
+        }
+    }
+
+    /// The value of an integer, or of a float holding a whole number.
+    pub fn maybe_to_integer(&self) -> Option<i64> {
+        match self {
+            Object::Integer(value) => Some(*value),
+            Object::Number(value) if value.fract() == 0.0 && value.abs() < i64::MAX as f64 => {
+                Some(*value as i64)
+            }
             _ => None,
         }
     }
@@ -64,6 +107,62 @@ impl Object {
         }
     }
 
+    pub fn maybe_to_list(&self) -> Option<Rc<RefCell<Vec<Object>>>> {
+        match self {
+            Object::List(value) => Some(value.to_owned()),
+            _ => None,
+        }
+    }
+
+    pub fn maybe_to_map(&self) -> Option<Rc<RefCell<LoxMap>>> {
+        match self {
+            Object::Map(value) => Some(value.to_owned()),
+            _ => None,
+        }
+    }
+
+    /// The name of the object's type, as returned by `typeof`.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Object::Boolean(_) => "boolean",
+            Object::Number(_) | Object::Integer(_) => "number",
+            Object::String(_) | Object::Rope(_) => "string",
+            Object::Function(_) => "function",
+            Object::Instance(_) => "instance",
+            Object::Class(_) => "class",
+            Object::List(_) => "list",
+            Object::Map(_) => "map",
+            Object::Module(_) => "module",
+            Object::Nil | Object::Undefined => "nil",
+        }
+    }
+
+    /// The name of the object's type, or the name of its class for an instance, as returned
+    /// by `type()`.
+    pub fn class_or_type_name(&self) -> String {
+        match self {
+            Object::Instance(instance) => instance.borrow().class().name.clone(),
+            other => other.type_name().to_string(),
+        }
+    }
+
+    /// The object itself, or the string a rope stands for.
+    pub fn flatten(self) -> Object {
+        match self {
+            Object::Rope(rope) => Object::String(rope.flat().clone()),
+            other => other,
+        }
+    }
+
+    /// Whether the value is a native function or module.
+    pub fn is_native(&self) -> bool {
+        match self {
+            Object::Function(function) => function.is_native(),
+            Object::Module(module) => module.is_native,
+            _ => false,
+        }
+    }
+
     pub fn is_truthy(&self) -> bool {
         match self {
             Object::Boolean(value) => *value,
@@ -85,7 +184,22 @@ impl PartialEq for Object {
         match (self, other) {
             (Object::Boolean(a), Object::Boolean(b)) => a == b,
             (Object::Number(a), Object::Number(b)) => a == b,
+            (Object::Integer(a), Object::Integer(b)) => a == b,
+            (Object::Integer(a), Object::Number(b)) | (Object::Number(b), Object::Integer(a)) => {
+                *a as f64 == *b && *b as i64 == *a
+            }
             (Object::String(a), Object::String(b)) => a == b,
+            (Object::Rope(a), Object::Rope(b)) => Rc::ptr_eq(a, b) || a.flat() == b.flat(),
+            (Object::String(a), Object::Rope(b)) | (Object::Rope(b), Object::String(a)) => {
+                a == b.flat()
+            }
+            (Object::List(a), Object::List(b)) => Rc::ptr_eq(a, b) || *a.borrow() == *b.borrow(),
+            (Object::Map(a), Object::Map(b)) => Rc::ptr_eq(a, b) || *a.borrow() == *b.borrow(),
+            // Functions, classes and instances are only equal to themselves.
+            (Object::Function(a), Object::Function(b)) => Rc::ptr_eq(a, b),
+            (Object::Class(a), Object::Class(b)) => Rc::ptr_eq(a, b),
+            (Object::Instance(a), Object::Instance(b)) => Rc::ptr_eq(a, b),
+            (Object::Module(a), Object::Module(b)) => Rc::ptr_eq(a, b),
             (Object::Nil, Object::Nil) => true,
             (Object::Undefined, Object::Undefined) => true,
             _ => false,
@@ -98,12 +212,92 @@ impl fmt::Display for Object {
         match self {
             Object::Boolean(value) => write!(f, "{value}"),
             Object::Number(value) => write!(f, "{value}"),
+            Object::Integer(value) => write!(f, "{value}"),
             Object::String(value) => write!(f, "{value}"),
+            Object::Rope(value) => write!(f, "{}", value.flat()),
             Object::Function(value) => write!(f, "{value}"),
             Object::Instance(value) => write!(f, "{}", value.borrow()),
             Object::Class(value) => write!(f, "{value}"),
+            Object::List(value) => display_once(Rc::as_ptr(value), f, "[...]", |f| {
+                write!(f, "[")?;
+                for (i, element) in value.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{element}")?;
+                }
+                write!(f, "]")
+            }),
+            Object::Map(value) => display_once(Rc::as_ptr(value), f, "{...}", |f| {
+                write!(f, "{}", value.borrow())
+            }),
+            Object::Module(value) => write!(f, "{value}"),
             Object::Nil => write!(f, "nil"),
             Object::Undefined => write!(f, "undefined"),
         }
     }
 }
+
+/// The namespace bound by an `import`, holding the names its module exports, or a native
+/// module such as `math`.
+#[derive(Debug)]
+pub struct LoxModule {
+    pub name: String,
+    exports: HashMap<Atom, Object>,
+    is_native: bool,
+}
+
+impl LoxModule {
+    pub fn new(name: String, exports: HashMap<Atom, Object>) -> Self {
+        Self {
+            name,
+            exports,
+            is_native: false,
+        }
+    }
+
+    /// A module implemented in Rust, which every interpreter starts with.
+    pub fn native(name: String, exports: HashMap<Atom, Object>) -> Self {
+        Self {
+            name,
+            exports,
+            is_native: true,
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Object> {
+        self.exports.get(name)
+    }
+
+    pub fn exports(&self) -> impl Iterator<Item = &Object> {
+        self.exports.values()
+    }
+}
+
+impl fmt::Display for LoxModule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<module {}>", self.name)
+    }
+}
+
+thread_local! {
+    /// The lists and maps being displayed further up the stack.
+    static DISPLAYING: RefCell<HashSet<usize>> = RefCell::new(HashSet::new());
+}
+
+/// Displays a container with `display`, unless the container is already being displayed, in
+/// which case it contains itself and `ellipsis` is written instead of recursing forever.
+fn display_once<T>(
+    container: *const T,
+    f: &mut fmt::Formatter,
+    ellipsis: &str,
+    display: impl FnOnce(&mut fmt::Formatter) -> fmt::Result,
+) -> fmt::Result {
+    let address = container as usize;
+    if !DISPLAYING.with_borrow_mut(|displaying| displaying.insert(address)) {
+        return write!(f, "{ellipsis}");
+    }
+    let result = display(f);
+    DISPLAYING.with_borrow_mut(|displaying| displaying.remove(&address));
+    result
+}
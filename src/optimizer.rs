@@ -0,0 +1,454 @@
+//! Rewrites of resolved programs that spare the interpreter work at run time.
+
+use std::{collections::HashMap, mem, rc::Rc};
+
+use crate::{
+    expr::{Expr, LiteralExpr},
+    function::FunctionType,
+    stmt::{BlockStmt, FunctionStmt, IfStmt, ReturnStmt, Stmt},
+    token::Token,
+};
+
+/// Replaces the variable reads found by the resolver with the literals the variables are
+/// initialized with. `literals` is keyed by the [hash](Expr::to_hash) of the reads.
+pub fn propagate_constants(
+    statements: &mut [Stmt],
+    literals: &HashMap<u64, LiteralExpr>,
+    locals: &mut HashMap<u64, usize>,
+) {
+    if literals.is_empty() {
+        return;
+    }
+    Rewriter::new(locals, |expr: &mut Expr| {
+        if let Expr::Variable(_) = expr
+            && let Some(literal) = literals.get(&expr.to_hash())
+        {
+            *expr = Expr::Literal(literal.clone());
+        }
+    })
+    .stmts(statements);
+}
+
+/// Replaces the calls of the functions found by the resolver with their bodies, when the
+/// arguments are literals or variables. `functions` is keyed by the [hash](Expr::to_hash)
+/// of the reads of the functions' names.
+pub fn inline_calls(
+    statements: &mut [Stmt],
+    functions: &HashMap<u64, Rc<InlineFunction>>,
+    locals: &mut HashMap<u64, usize>,
+) {
+    if functions.is_empty() {
+        return;
+    }
+    Rewriter::new(locals, |expr: &mut Expr| {
+        if let Expr::Call(call) = expr
+            && let Some(function) = functions.get(&call.callee.to_hash())
+            && let Some(body) = function.inline(&call.arguments)
+        {
+            *expr = body;
+        }
+    })
+    .stmts(statements);
+}
+
+/// Drops the statements that can't run: those after a `return`, `break`, `continue` or
+/// `throw` in the same block, and the branches of `if`s whose condition is a literal, such as
+/// a constant that [`propagate_constants`] replaced.
+pub fn prune_dead_code(statements: &mut [Stmt], locals: &mut HashMap<u64, usize>) {
+    Rewriter::new(locals, |_: &mut Expr| {})
+        .with_block_rewrite(prune_block)
+        .stmts(statements);
+    // The top level can't hold jumps, but it can hold `if`s.
+    statements.iter_mut().for_each(fold_if);
+}
+
+fn prune_block(statements: &mut Vec<Stmt>) {
+    if let Some(jump) = statements
+        .iter()
+        .position(|stmt| stmt.jump_keyword().is_some())
+    {
+        statements.truncate(jump + 1);
+    }
+    statements.iter_mut().for_each(fold_if);
+}
+
+/// Replaces an `if` whose condition is a literal with the branch that runs, along `else if`
+/// chains.
+fn fold_if(mut stmt: &mut Stmt) {
+    loop {
+        if let Some(branch) = taken_branch(stmt) {
+            *stmt = branch;
+            continue;
+        }
+        match stmt {
+            Stmt::If(IfStmt {
+                else_branch: Some(else_branch),
+                ..
+            }) => stmt = else_branch,
+            _ => return,
+        }
+    }
+}
+
+/// The branch of an `if` with a literal condition that runs, taken out of it.
+fn taken_branch(stmt: &mut Stmt) -> Option<Stmt> {
+    let Stmt::If(if_stmt) = stmt else {
+        return None;
+    };
+    let Expr::Literal(literal) = &if_stmt.condition else {
+        return None;
+    };
+    let empty = || BlockStmt::new(Vec::new());
+    Some(if literal.value.is_truthy() {
+        Stmt::Block(mem::replace(&mut if_stmt.then_branch, empty()))
+    } else {
+        match if_stmt.else_branch.take() {
+            Some(else_branch) => *else_branch,
+            None => Stmt::Block(empty()),
+        }
+    })
+}
+
+/// A function whose calls can be replaced by its body: a single `return` of an expression
+/// that reads nothing but its parameters, each at least once, and globals.
+#[derive(Debug)]
+pub struct InlineFunction {
+    params: Vec<Token>,
+    body: Expr,
+    /// Whether each parameter is read exactly once, so that its argument can be any
+    /// expression without side effects rather than only a literal or a variable.
+    read_once: Vec<bool>,
+}
+
+impl InlineFunction {
+    /// The inlinable form of `function`, which must have been resolved into `locals`.
+    pub fn of(function: &FunctionStmt, locals: &HashMap<u64, usize>) -> Option<Self> {
+        if function.kind != FunctionType::Function || function.variadic {
+            return None;
+        }
+        let [
+            Stmt::Return(ReturnStmt {
+                value: Some(body), ..
+            }),
+        ] = &function.body.statements[..]
+        else {
+            return None;
+        };
+        let mut reads = vec![0; function.params.len()];
+        if !Self::is_inlinable(body, &function.params, locals, &mut reads) || reads.contains(&0) {
+            return None;
+        }
+        let mut all_reads = vec![0; function.params.len()];
+        Rewriter::new(&mut HashMap::new(), |expr: &mut Expr| {
+            if let Some(param) = param_index(&function.params, expr) {
+                all_reads[param] += 1;
+            }
+        })
+        .expr(&mut body.clone());
+        Some(Self {
+            params: function.params.clone(),
+            body: body.clone(),
+            read_once: all_reads.iter().map(|&reads| reads == 1).collect(),
+        })
+    }
+
+    /// Whether `expr` can be moved to the call site, counting the reads of each parameter
+    /// that always happen. Any other variable must be a global, which reads the same from
+    /// anywhere, and functions declared inside the body would capture the parameters.
+    fn is_inlinable(
+        expr: &Expr,
+        params: &[Token],
+        locals: &HashMap<u64, usize>,
+        reads: &mut [usize],
+    ) -> bool {
+        let sometimes =
+            |expr: &Expr| Self::is_inlinable(expr, params, locals, &mut vec![0; params.len()]);
+        let mut inlinable = |expr: &Expr| Self::is_inlinable(expr, params, locals, reads);
+        match expr {
+            Expr::Literal(_) => true,
+            Expr::Variable(_) => match param_index(params, expr) {
+                Some(param) => {
+                    reads[param] += 1;
+                    true
+                }
+                None => !locals.contains_key(&expr.to_hash()),
+            },
+            Expr::Binary(binary) => inlinable(&binary.left) && inlinable(&binary.right),
+            Expr::Call(call) => {
+                inlinable(&call.callee) && call.arguments.iter().all(&mut inlinable)
+            }
+            Expr::Get(get) => inlinable(&get.object),
+            Expr::Grouping(grouping) => inlinable(&grouping.expression),
+            Expr::Index(index) => inlinable(&index.object) && inlinable(&index.index),
+            Expr::List(list) => list.elements.iter().all(inlinable),
+            Expr::Logical(logical) => inlinable(&logical.left) && sometimes(&logical.right),
+            Expr::Map(map) => map
+                .entries
+                .iter()
+                .all(|(key, value)| inlinable(key) && inlinable(value)),
+            Expr::Slice(slice) => {
+                inlinable(&slice.object)
+                    && [&slice.start, &slice.end]
+                        .into_iter()
+                        .flatten()
+                        .all(&mut inlinable)
+            }
+            Expr::Ternary(ternary) => {
+                inlinable(&ternary.condition)
+                    && sometimes(&ternary.then_branch)
+                    && sometimes(&ternary.else_branch)
+            }
+            Expr::Unary(unary) => inlinable(&unary.right),
+            Expr::Assign(_)
+            | Expr::Destructure(_)
+            | Expr::IndexSet(_)
+            | Expr::Lambda(_)
+            | Expr::Set(_)
+            | Expr::Super(_)
+            | Expr::This(_) => false,
+        }
+    }
+
+    /// The body with `arguments` in place of the parameters, unless evaluating an argument
+    /// more than once or not at all could be told apart from evaluating it once.
+    fn inline(&self, arguments: &[Expr]) -> Option<Expr> {
+        let substitutable = |(argument, read_once): (&Expr, &bool)| match argument {
+            Expr::Literal(_) | Expr::Variable(_) => true,
+            _ => *read_once && is_pure(argument),
+        };
+        if arguments.len() != self.params.len()
+            || !arguments.iter().zip(&self.read_once).all(substitutable)
+        {
+            return None;
+        }
+        let mut body = self.body.clone();
+        // The body holds no assignments, so there are no locals to update.
+        Rewriter::new(&mut HashMap::new(), |expr: &mut Expr| {
+            if let Some(param) = param_index(&self.params, expr) {
+                *expr = arguments[param].clone();
+            }
+        })
+        .expr(&mut body);
+        Some(body)
+    }
+}
+
+/// The parameter `expr` reads, if it's a variable named after one of `params`.
+fn param_index(params: &[Token], expr: &Expr) -> Option<usize> {
+    let Expr::Variable(variable) = expr else {
+        return None;
+    };
+    params
+        .iter()
+        .position(|param| param.value == variable.name.value)
+}
+
+/// Whether `expr` computes a value without side effects, such as `i * 2`.
+fn is_pure(expr: &Expr) -> bool {
+    match expr {
+        Expr::Literal(_) | Expr::Variable(_) => true,
+        Expr::Binary(binary) => is_pure(&binary.left) && is_pure(&binary.right),
+        Expr::Grouping(grouping) => is_pure(&grouping.expression),
+        Expr::Unary(unary) => is_pure(&unary.right),
+        _ => false,
+    }
+}
+
+/// Applies a rewrite to every expression of a program, after its operands.
+struct Rewriter<'a, F> {
+    /// The interpreter's resolved locals. An assignment is resolved by the hash of the whole
+    /// expression, so rewriting its value changes its key.
+    locals: &'a mut HashMap<u64, usize>,
+    rewrite: F,
+    /// Applied to the statements of every block, once they're rewritten.
+    rewrite_block: fn(&mut Vec<Stmt>),
+}
+
+impl<'a, F: FnMut(&mut Expr)> Rewriter<'a, F> {
+    fn new(locals: &'a mut HashMap<u64, usize>, rewrite: F) -> Self {
+        Self {
+            locals,
+            rewrite,
+            rewrite_block: |_| {},
+        }
+    }
+
+    fn with_block_rewrite(mut self, rewrite_block: fn(&mut Vec<Stmt>)) -> Self {
+        self.rewrite_block = rewrite_block;
+        self
+    }
+
+    fn stmts(&mut self, statements: &mut [Stmt]) {
+        for stmt in statements {
+            self.stmt(stmt);
+        }
+    }
+
+    fn block(&mut self, block: &mut BlockStmt) {
+        self.stmts(&mut block.statements);
+        (self.rewrite_block)(&mut block.statements);
+    }
+
+    fn function(&mut self, function: &mut Rc<FunctionStmt>) {
+        self.block(&mut Rc::make_mut(function).body);
+    }
+
+    fn stmt(&mut self, stmt: &mut Stmt) {
+        match stmt {
+            Stmt::Block(block) => self.block(block),
+            Stmt::Break(_) | Stmt::Continue(_) | Stmt::Import(_) => {}
+            Stmt::Class(class) => {
+                for initializer in Rc::make_mut(&mut class.fields)
+                    .iter_mut()
+                    .filter_map(|field| field.initializer.as_mut())
+                {
+                    self.expr(initializer);
+                }
+                for method in class
+                    .methods
+                    .iter_mut()
+                    .chain(&mut class.static_methods)
+                    .chain(&mut class.getter_methods)
+                {
+                    self.function(method);
+                }
+                for block in &mut class.static_blocks {
+                    self.block(block);
+                }
+            }
+            Stmt::Destructure(stmt) => self.expr(&mut stmt.initializer),
+            Stmt::Export(stmt) => self.stmt(&mut stmt.declaration),
+            Stmt::Expression(stmt) => self.expr(&mut stmt.expr),
+            Stmt::For(stmt) => {
+                if let Some(initializer) = &mut stmt.initializer {
+                    self.stmt(initializer);
+                }
+                self.expr(&mut stmt.condition);
+                if let Some(increment) = &mut stmt.increment {
+                    self.expr(increment);
+                }
+                self.block(&mut stmt.body);
+            }
+            Stmt::ForIn(stmt) => {
+                self.expr(&mut stmt.iterable);
+                self.block(&mut stmt.body);
+            }
+            Stmt::Function(function) => self.function(function),
+            Stmt::If(stmt) => {
+                self.expr(&mut stmt.condition);
+                self.block(&mut stmt.then_branch);
+                if let Some(else_branch) = &mut stmt.else_branch {
+                    self.stmt(else_branch);
+                }
+            }
+            Stmt::Print(stmt) => self.expr(&mut stmt.expr),
+            Stmt::Return(stmt) => {
+                if let Some(value) = &mut stmt.value {
+                    self.expr(value);
+                }
+            }
+            Stmt::Switch(stmt) => {
+                self.expr(&mut stmt.subject);
+                for (values, body) in &mut stmt.cases {
+                    for value in values {
+                        self.expr(value);
+                    }
+                    self.block(body);
+                }
+                if let Some(default) = &mut stmt.default {
+                    self.block(default);
+                }
+            }
+            Stmt::Throw(stmt) => self.expr(&mut stmt.value),
+            Stmt::Try(stmt) => {
+                self.block(&mut stmt.body);
+                if let Some((_, body)) = &mut stmt.catch {
+                    self.block(body);
+                }
+                if let Some(finally) = &mut stmt.finally {
+                    self.block(finally);
+                }
+            }
+            Stmt::Var(stmt) => {
+                if let Some(initializer) = &mut stmt.initializer {
+                    self.expr(initializer);
+                }
+            }
+            Stmt::While(stmt) => {
+                self.expr(&mut stmt.condition);
+                self.block(&mut stmt.body);
+            }
+        }
+    }
+
+    fn expr(&mut self, expr: &mut Expr) {
+        let assignment = matches!(expr, Expr::Assign(_)).then(|| expr.to_hash());
+        match expr {
+            Expr::Assign(assign) => self.expr(&mut assign.value),
+            Expr::Binary(binary) => {
+                self.expr(&mut binary.left);
+                self.expr(&mut binary.right);
+            }
+            Expr::Call(call) => {
+                self.expr(&mut call.callee);
+                for argument in &mut call.arguments {
+                    self.expr(argument);
+                }
+            }
+            Expr::Destructure(destructure) => self.expr(&mut destructure.value),
+            Expr::Get(get) => self.expr(&mut get.object),
+            Expr::Grouping(grouping) => self.expr(&mut grouping.expression),
+            Expr::Index(index) => {
+                self.expr(&mut index.object);
+                self.expr(&mut index.index);
+            }
+            Expr::IndexSet(index_set) => {
+                self.expr(&mut index_set.object);
+                self.expr(&mut index_set.index);
+                self.expr(&mut index_set.value);
+            }
+            Expr::Lambda(lambda) => self.block(&mut Rc::make_mut(lambda).body),
+            Expr::List(list) => {
+                for element in &mut list.elements {
+                    self.expr(element);
+                }
+            }
+            Expr::Literal(_) | Expr::Super(_) | Expr::This(_) | Expr::Variable(_) => {}
+            Expr::Logical(logical) => {
+                self.expr(&mut logical.left);
+                self.expr(&mut logical.right);
+            }
+            Expr::Map(map) => {
+                for (key, value) in &mut map.entries {
+                    self.expr(key);
+                    self.expr(value);
+                }
+            }
+            Expr::Set(set) => {
+                self.expr(&mut set.object);
+                self.expr(&mut set.value);
+            }
+            Expr::Slice(slice) => {
+                self.expr(&mut slice.object);
+                for bound in [&mut slice.start, &mut slice.end].into_iter().flatten() {
+                    self.expr(bound);
+                }
+            }
+            Expr::Ternary(ternary) => {
+                self.expr(&mut ternary.condition);
+                self.expr(&mut ternary.then_branch);
+                self.expr(&mut ternary.else_branch);
+            }
+            Expr::Unary(unary) => self.expr(&mut unary.right),
+        }
+        (self.rewrite)(expr);
+        if let Some(before) = assignment {
+            let after = expr.to_hash();
+            if after != before
+                && let Some(&depth) = self.locals.get(&before)
+            {
+                self.locals.insert(after, depth);
+            }
+        }
+    }
+}
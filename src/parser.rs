@@ -1,55 +1,256 @@
+use std::{collections::HashSet, rc::Rc};
+
 use crate::{
-    error::ParsingError,
+    atom::Atom,
+    error::{ParsingError, RuntimeErrorKind},
     expr::{
-        AssignExpr, BinaryExpr, CallExpr, Expr, GetExpr, GroupingExpr, LambdaExpr, LiteralExpr,
-        LogicalExpr, SetExpr, SuperExpr, TernaryExpr, ThisExpr, UnaryExpr, VariableExpr,
+        AssignExpr, BinaryExpr, CallExpr, DestructureExpr, Expr, GetExpr, GroupingExpr, IndexExpr,
+        IndexSetExpr, LambdaExpr, ListExpr, LiteralExpr, LogicalExpr, MapExpr, Pattern, SetExpr,
+        SliceExpr, SuperExpr, TernaryExpr, ThisExpr, UnaryExpr, VariableExpr,
     },
     function::FunctionType,
     object::Object,
     stmt::{
-        BlockStmt, ClassStmt, ExpressionStmt, FunctionStmt, IfStmt, PrintStmt, ReturnStmt, Stmt,
-        VarStmt, WhileStmt,
+        BlockStmt, BreakStmt, ClassStmt, ContinueStmt, DestructureStmt, ExportStmt, ExpressionStmt,
+        ForInStmt, ForStmt, FunctionStmt, IfStmt, ImportStmt, PrintStmt, ReturnStmt, Stmt,
+        SwitchStmt, ThrowStmt, TryStmt, VarStmt, WhileStmt,
     },
     token::{Token, TokenIdentity, TokenValue},
 };
 
+/// The maximum number of parameters a function can declare, and of arguments a call can pass.
+pub const MAX_ARITY: usize = 255;
+
+/// How deeply statements and expressions can nest, such as blocks in blocks or parentheses
+/// in parentheses. Parsing, resolving and running them recurses once per level, so deeper
+/// programs are rejected before they can overflow the stack.
+pub const MAX_NESTING: usize = 128;
+
+/// The body of a function written as a single expression, which returns it. `token` stands
+/// in for the `return` keyword.
+fn implicit_return(token: Token, value: Expr) -> BlockStmt {
+    BlockStmt::new(vec![Stmt::Return(ReturnStmt::new(token, Some(value)))])
+}
+
+/// The grammar programs are written in.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum Dialect {
+    /// The grammar of this crate, which calls `print` like a function and reserves keywords
+    /// for its extensions, such as `break`, `try` and `import`.
+    #[default]
+    Rlox,
+    /// The grammar of the book, so that programs from Crafting Interpreters and its test
+    /// suite run: `print` takes its operand without parentheses and only the book's keywords
+    /// are reserved. The extensions that don't clash with it, such as lambdas, still parse.
+    /// Programs in it also follow the book's semantics: globals can be declared again, and
+    /// variables declared without an initializer hold `nil`.
+    Lox,
+}
+
 pub struct Parser {
     tokens: Vec<Token>,
     current: usize,
+    meta: Vec<(Atom, Atom)>,
+    /// The text the scanner couldn't read, which fails the parse before it starts.
+    scan_errors: Vec<ParsingError>,
+    dialect: Dialect,
+    optional_final_semicolon: bool,
+    /// How many statements and expressions enclose the one being parsed.
+    depth: usize,
+    /// The errors recovered from so far, when parsing with [`Parser::parse_all`].
+    errors: Option<Vec<ParsingError>>,
 }
 
 impl Parser {
     pub fn new(tokens: Vec<Token>) -> Self {
         // We eliminate comments from the token stream
-        let tokens = tokens
+        let mut tokens: Vec<Token> = tokens
             .clone()
             .extract_if(.., |token| token.id != TokenIdentity::Comment)
             .collect();
-        Parser { tokens, current: 0 }
+        let meta = tokens
+            .extract_if(.., |token| token.id == TokenIdentity::Meta)
+            .filter_map(|token| match token.value {
+                TokenValue::Meta(entry) => Some(*entry),
+                _ => None,
+            })
+            .collect();
+        let scan_errors = tokens
+            .extract_if(.., |token| token.id == TokenIdentity::Error)
+            .map(|token| {
+                let message = token.to_string();
+                ParsingError::new(token, &message)
+            })
+            .collect();
+        Parser {
+            tokens,
+            current: 0,
+            meta,
+            scan_errors,
+            dialect: Dialect::default(),
+            optional_final_semicolon: false,
+            depth: 0,
+            errors: None,
+        }
+    }
+
+    /// Parses the grammar of `dialect`. Its tokens should be scanned with the same dialect.
+    pub fn with_dialect(mut self, dialect: Dialect) -> Self {
+        self.dialect = dialect;
+        self
+    }
+
+    /// Lets an expression statement at the very end leave out its `;`, so that the REPL can
+    /// take `1 + 2` as it's typed.
+    pub fn with_optional_final_semicolon(mut self, optional_final_semicolon: bool) -> Self {
+        self.optional_final_semicolon = optional_final_semicolon;
+        self
+    }
+
+    /// The `//! key: value` entries heading the program, in order.
+    pub fn meta(&self) -> &[(Atom, Atom)] {
+        &self.meta
     }
 
     pub fn parse(&mut self) -> Result<Vec<Stmt>, ParsingError> {
+        if !self.scan_errors.is_empty() {
+            return Err(self.scan_errors.remove(0));
+        }
         let mut statements = Vec::new();
         while !self.is_at_end() {
-            statements.push(self.declaration(false)?);
+            statements.extend(self.recoverable_declaration(false)?);
         }
         Ok(statements)
     }
 
+    /// Parses the program like [`Parser::parse`], but skips to the next statement after an
+    /// error to report the errors after it too. Text the scanner couldn't read is reported
+    /// on its own, since the tokens around it would only add errors of their own.
+    pub fn parse_all(&mut self) -> Result<Vec<Stmt>, Vec<ParsingError>> {
+        if !self.scan_errors.is_empty() {
+            return Err(std::mem::take(&mut self.scan_errors));
+        }
+        self.errors = Some(Vec::new());
+        // Every error is recovered from, so parsing itself doesn't fail.
+        let statements = self.parse().unwrap_or_default();
+        match self.errors.take() {
+            Some(errors) if !errors.is_empty() => Err(errors),
+            _ => Ok(statements),
+        }
+    }
+
+    /// A declaration, or `None` when it's an error that [`Parser::parse_all`] recovered from.
+    fn recoverable_declaration(&mut self, in_loop: bool) -> Result<Option<Stmt>, ParsingError> {
+        let start = self.current;
+        match (
+            self.nested(|parser| parser.declaration(in_loop)),
+            &mut self.errors,
+        ) {
+            (Ok(stmt), _) => Ok(Some(stmt)),
+            (Err(error), Some(errors)) => {
+                errors.push(error);
+                if self.current == start {
+                    self.advance();
+                }
+                self.synchronize();
+                Ok(None)
+            }
+            (Err(error), None) => Err(error),
+        }
+    }
+
+    /// Skips the tokens up to the likely start of the next statement: after a `;`, or before
+    /// a keyword starting one or the `}` ending the block.
+    fn synchronize(&mut self) {
+        while !self.is_at_end() {
+            if self.previous().id == TokenIdentity::Semicolon {
+                return;
+            }
+            match self.peek().id {
+                TokenIdentity::Class
+                | TokenIdentity::Fun
+                | TokenIdentity::Var
+                | TokenIdentity::Const
+                | TokenIdentity::For
+                | TokenIdentity::If
+                | TokenIdentity::While
+                | TokenIdentity::Print
+                | TokenIdentity::Return
+                | TokenIdentity::Throw
+                | TokenIdentity::Try
+                | TokenIdentity::Switch
+                | TokenIdentity::Import
+                | TokenIdentity::Export
+                | TokenIdentity::RightBrace => return,
+                _ => {
+                    self.advance();
+                }
+            }
+        }
+    }
+
     fn declaration(&mut self, in_loop: bool) -> Result<Stmt, ParsingError> {
-        if self.match_token(vec![TokenIdentity::Class]) {
+        if self.match_token(vec![TokenIdentity::Export]) {
+            self.export_declaration(in_loop).map(Stmt::Export)
+        } else if self.match_token(vec![TokenIdentity::Import]) {
+            self.import_declaration().map(Stmt::Import)
+        } else if self.match_token(vec![TokenIdentity::Class]) {
             self.class_declaration().map(Stmt::Class)
-        } else if self.match_token(vec![TokenIdentity::Fun])
-            && self.check(TokenIdentity::Identifier)
-        {
-            self.function(FunctionType::Function).map(Stmt::Function)
-        } else if self.match_token(vec![TokenIdentity::Var]) {
-            self.var_declaration().map(Stmt::Var)
+        } else if self.check(TokenIdentity::Fun) && self.check_next(TokenIdentity::Identifier) {
+            self.advance();
+            self.function(FunctionType::Function)
+                .map(|function| Stmt::Function(Rc::new(function)))
+        } else if self.match_token(vec![TokenIdentity::Var, TokenIdentity::Const]) {
+            let is_const = self.previous().id == TokenIdentity::Const;
+            if self.check(TokenIdentity::LeftBracket) || self.check(TokenIdentity::LeftBrace) {
+                self.destructure_declaration(is_const)
+                    .map(Stmt::Destructure)
+            } else if is_const {
+                self.const_declaration().map(Stmt::Var)
+            } else {
+                self.var_declaration().map(Stmt::Var)
+            }
         } else {
             self.statement(in_loop)
         }
     }
 
+    fn export_declaration(&mut self, in_loop: bool) -> Result<ExportStmt, ParsingError> {
+        let keyword = self.previous().to_owned();
+        let is_declaration = self.check(TokenIdentity::Class)
+            || (self.check(TokenIdentity::Fun) && self.check_next(TokenIdentity::Identifier))
+            || self.check(TokenIdentity::Var)
+            || self.check(TokenIdentity::Const);
+        if !is_declaration {
+            return Err(ParsingError::new(
+                self.peek().to_owned(),
+                "Expect a declaration after 'export'.",
+            ));
+        }
+        let declaration = self.declaration(in_loop)?;
+        Ok(ExportStmt::new(keyword, Box::new(declaration)))
+    }
+
+    fn import_declaration(&mut self) -> Result<ImportStmt, ParsingError> {
+        let keyword = self.previous().to_owned();
+        let path = self
+            .consume(TokenIdentity::String, "Expect module path after 'import'.")?
+            .to_owned();
+        // `as` is only a keyword here, so it stays usable as a name elsewhere.
+        if !(self.check(TokenIdentity::Identifier) && self.peek().atom().as_str() == "as") {
+            return Err(ParsingError::new(
+                self.peek().to_owned(),
+                "Expect 'as' after module path.",
+            ));
+        }
+        self.advance();
+        let name = self
+            .consume(TokenIdentity::Identifier, "Expect module name after 'as'.")?
+            .to_owned();
+        self.consume(TokenIdentity::Semicolon, "Expect ';' after import.")?;
+        Ok(ImportStmt::new(keyword, path, name))
+    }
+
     fn class_declaration(&mut self) -> Result<ClassStmt, ParsingError> {
         let name = self
             .consume(TokenIdentity::Identifier, "Expect class name.")?
@@ -61,20 +262,67 @@ impl Parser {
             None
         };
 
+        let mut fields = Vec::new();
         let mut methods = Vec::new();
         let mut static_methods = Vec::new();
         let mut getter_methods = Vec::new();
+        let mut static_blocks = Vec::new();
+
+        // Methods, getters and static methods all share the class' method table.
+        let mut method_names = HashSet::new();
+        let mut field_names = HashSet::new();
 
         self.consume(TokenIdentity::LeftBrace, "Expect '{' before class body.")?;
         while !self.check(TokenIdentity::RightBrace) && !self.is_at_end() {
-            if self.match_token(vec![TokenIdentity::Class]) {
-                static_methods.push(self.function(FunctionType::StaticMethod)?);
+            if self.match_token(vec![TokenIdentity::Var]) {
+                let field = self.var_declaration()?;
+                if !field_names.insert(field.name.value.to_string()) {
+                    return Err(ParsingError::new(
+                        field.name,
+                        &format!("Duplicate field name in class '{}'.", name.value),
+                    ));
+                }
+                fields.push(field);
+                continue;
+            }
+            let is_static = self.match_token(vec![TokenIdentity::Class]);
+            if is_static && self.match_token(vec![TokenIdentity::LeftBrace]) {
+                static_blocks.push(self.block(false)?);
+                continue;
+            }
+            // `memo` is only a modifier when another name follows, so it stays usable as a
+            // method name.
+            let is_memo = !is_static
+                && self.check(TokenIdentity::Identifier)
+                && self.peek().atom().as_str() == "memo"
+                && self.check_next(TokenIdentity::Identifier);
+            if is_memo {
+                self.advance();
+            }
+            if self.check(TokenIdentity::Identifier)
+                && !method_names.insert(self.peek().value.to_string())
+            {
+                return Err(ParsingError::new(
+                    self.peek().to_owned(),
+                    &format!("Duplicate method name in class '{}'.", name.value),
+                ));
+            }
+
+            if is_static {
+                static_methods.push(Rc::new(self.function(FunctionType::StaticMethod)?));
             } else {
-                let method = self.function(FunctionType::Method)?;
+                let mut method = self.function(FunctionType::Method)?;
+                if is_memo && method.kind != FunctionType::GetterMethod {
+                    return Err(ParsingError::new(
+                        method.name,
+                        "Only getters can be memoized.",
+                    ));
+                }
+                method.memo = is_memo;
                 if method.kind == FunctionType::GetterMethod {
-                    getter_methods.push(method);
+                    getter_methods.push(Rc::new(method));
                 } else {
-                    methods.push(method);
+                    methods.push(Rc::new(method));
                 }
             }
         }
@@ -83,9 +331,11 @@ impl Parser {
         Ok(ClassStmt::new(
             name,
             superclass,
+            Rc::new(fields),
             methods,
             static_methods,
             getter_methods,
+            static_blocks,
         ))
     }
 
@@ -102,7 +352,63 @@ impl Parser {
             TokenIdentity::Semicolon,
             "Expect ';' after variable declaration.",
         )?;
-        Ok(VarStmt::new(name, initializer))
+        Ok(VarStmt::new(name, initializer, false))
+    }
+
+    fn const_declaration(&mut self) -> Result<VarStmt, ParsingError> {
+        let name = self
+            .consume(TokenIdentity::Identifier, "Expect constant name.")?
+            .to_owned();
+        self.consume(TokenIdentity::Equal, "Expect '=' after constant name.")?;
+        let initializer = self.expression()?;
+        self.consume(
+            TokenIdentity::Semicolon,
+            "Expect ';' after constant declaration.",
+        )?;
+        Ok(VarStmt::new(name, Some(initializer), true))
+    }
+
+    fn destructure_declaration(&mut self, is_const: bool) -> Result<DestructureStmt, ParsingError> {
+        let pattern = self.pattern()?;
+        self.consume(
+            TokenIdentity::Equal,
+            "Expect '=' after destructuring pattern.",
+        )?;
+        let initializer = self.expression()?;
+        self.consume(
+            TokenIdentity::Semicolon,
+            "Expect ';' after variable declaration.",
+        )?;
+        Ok(DestructureStmt::new(pattern, initializer, is_const))
+    }
+
+    /// Parses the `[a, b]` or `{x, y}` pattern of a destructuring declaration.
+    fn pattern(&mut self) -> Result<Pattern, ParsingError> {
+        let open = self.advance().to_owned();
+        let mut names = Vec::new();
+        loop {
+            names.push(
+                self.consume(
+                    TokenIdentity::Identifier,
+                    "Expect variable name in pattern.",
+                )?
+                .to_owned(),
+            );
+            if !self.match_token(vec![TokenIdentity::Comma]) {
+                break;
+            }
+        }
+
+        if open.id == TokenIdentity::LeftBracket {
+            self.consume(TokenIdentity::RightBracket, "Expect ']' after pattern.")?;
+            Ok(Pattern::List {
+                bracket: open,
+                names,
+            })
+        } else {
+            self.consume(TokenIdentity::RightBrace, "Expect '}' after pattern.")?;
+            Ok(Pattern::Fields { brace: open, names })
+        }
     }
 
     fn while_statement(&mut self) -> Result<Stmt, ParsingError> {
@@ -113,8 +419,7 @@ impl Parser {
             "Expect ')' after while condition.",
         )?;
 
-        self.consume(TokenIdentity::LeftBrace, "Expect '{' before while body.")?;
-        let body = self.block(true)?;
+        let body = self.body(true)?;
 
         Ok(Stmt::While(WhileStmt::new(condition, body)))
     }
@@ -130,6 +435,12 @@ impl Parser {
             self.while_statement()
         } else if self.match_token(vec![TokenIdentity::If]) {
             self.if_statement(in_loop)
+        } else if self.match_token(vec![TokenIdentity::Switch]) {
+            self.switch_statement(in_loop)
+        } else if self.match_token(vec![TokenIdentity::Throw]) {
+            self.throw_statement()
+        } else if self.match_token(vec![TokenIdentity::Try]) {
+            self.try_statement(in_loop)
         } else if self.match_token(vec![TokenIdentity::LeftBrace]) {
             Ok(Stmt::Block(self.block(in_loop)?))
         } else if self.match_token(vec![TokenIdentity::Break]) {
@@ -154,17 +465,25 @@ impl Parser {
     }
 
     fn break_statement(&mut self) -> Result<Stmt, ParsingError> {
+        let keyword = self.previous().to_owned();
         self.consume(TokenIdentity::Semicolon, "Expect ';' after break.")?;
-        Ok(Stmt::Break)
+        Ok(Stmt::Break(BreakStmt::new(keyword)))
     }
 
     fn continue_statement(&mut self) -> Result<Stmt, ParsingError> {
+        let keyword = self.previous().to_owned();
         self.consume(TokenIdentity::Semicolon, "Expect ';' after continue.")?;
-        Ok(Stmt::Continue)
+        Ok(Stmt::Continue(ContinueStmt::new(keyword)))
     }
 
     fn for_statement(&mut self) -> Result<Stmt, ParsingError> {
         self.consume(TokenIdentity::LeftParen, "Expect '(' after 'for'.")?;
+        if self.check_next(TokenIdentity::In)
+            || (self.check(TokenIdentity::Var) && self.check_at(2, TokenIdentity::In))
+        {
+            return self.for_in_statement();
+        }
+
         let initializer = if self.match_token(vec![TokenIdentity::Semicolon]) {
             None
         } else if self.match_token(vec![TokenIdentity::Var]) {
@@ -173,35 +492,45 @@ impl Parser {
             Some(self.expression_statement()?)
         };
 
-        let condition = if self.match_token(vec![TokenIdentity::Semicolon]) {
+        let condition = if self.check(TokenIdentity::Semicolon) {
             None
         } else {
             Some(self.expression()?)
         };
         self.consume(TokenIdentity::Semicolon, "Expect ';' after for condition.")?;
 
-        let increment = if self.match_token(vec![TokenIdentity::RightParen]) {
+        let increment = if self.check(TokenIdentity::RightParen) {
             None
         } else {
             Some(self.expression()?)
         };
         self.consume(TokenIdentity::RightParen, "Expect ')' after for clauses.")?;
 
-        self.consume(TokenIdentity::LeftBrace, "Expect '{' before for body.")?;
-        let mut body = self.block(true)?;
-
-        if let Some(increment) = increment {
-            body.statements
-                .push(Stmt::Expression(ExpressionStmt::new(increment)));
-        }
+        let body = self.body(true)?;
 
         let condition = condition.unwrap_or(Expr::Literal(LiteralExpr::new(Object::Boolean(true))));
-        let mut stmt = Stmt::While(WhileStmt::new(condition, body));
+        Ok(Stmt::For(ForStmt::new(
+            initializer.map(Box::new),
+            condition,
+            increment,
+            body,
+        )))
+    }
 
-        if let Some(initializer) = initializer {
-            stmt = Stmt::Block(BlockStmt::new(vec![initializer, stmt]));
-        }
-        Ok(stmt)
+    fn for_in_statement(&mut self) -> Result<Stmt, ParsingError> {
+        self.match_token(vec![TokenIdentity::Var]);
+        let name = self
+            .consume(TokenIdentity::Identifier, "Expect loop variable name.")?
+            .to_owned();
+        let keyword = self
+            .consume(TokenIdentity::In, "Expect 'in' after loop variable.")?
+            .to_owned();
+        let iterable = self.expression()?;
+        self.consume(TokenIdentity::RightParen, "Expect ')' after for-in clause.")?;
+
+        let body = self.body(true)?;
+
+        Ok(Stmt::ForIn(ForInStmt::new(name, keyword, iterable, body)))
     }
 
     fn if_statement(&mut self, in_loop: bool) -> Result<Stmt, ParsingError> {
@@ -209,18 +538,122 @@ impl Parser {
         let condition = self.expression()?;
         self.consume(TokenIdentity::RightParen, "Expect ')' after if condition.")?;
 
-        self.consume(TokenIdentity::LeftBrace, "Expect '{' before if body.")?;
-        let then_branch = self.block(in_loop)?;
+        let then_branch = self.body(in_loop)?;
         let else_branch = if self.match_token(vec![TokenIdentity::Else]) {
-            self.consume(TokenIdentity::LeftBrace, "Expect '{' before else body.")?;
-            Some(self.block(in_loop)?)
+            // Chain `else if` directly instead of nesting each `if` in its own block.
+            if self.match_token(vec![TokenIdentity::If]) {
+                Some(Box::new(self.if_statement(in_loop)?))
+            } else {
+                Some(Box::new(Stmt::Block(self.body(in_loop)?)))
+            }
         } else {
             None
         };
         Ok(Stmt::If(IfStmt::new(condition, then_branch, else_branch)))
     }
 
+    fn switch_statement(&mut self, in_loop: bool) -> Result<Stmt, ParsingError> {
+        self.consume(TokenIdentity::LeftParen, "Expect '(' after 'switch'.")?;
+        let subject = self.expression()?;
+        self.consume(TokenIdentity::RightParen, "Expect ')' after switch value.")?;
+        self.consume(TokenIdentity::LeftBrace, "Expect '{' before switch body.")?;
+
+        let mut cases = Vec::new();
+        let mut default = None;
+        while !self.check(TokenIdentity::RightBrace) && !self.is_at_end() {
+            if default.is_some() {
+                return Err(ParsingError::new(
+                    self.peek().to_owned(),
+                    "The default case must be the last one.",
+                ));
+            }
+
+            if self.match_token(vec![TokenIdentity::Case]) {
+                let mut values = vec![self.expression()?];
+                while self.match_token(vec![TokenIdentity::Comma]) {
+                    values.push(self.expression()?);
+                }
+                self.consume(TokenIdentity::Colon, "Expect ':' after case values.")?;
+                self.consume(TokenIdentity::LeftBrace, "Expect '{' before case body.")?;
+                cases.push((values, self.block(in_loop)?));
+            } else if self.match_token(vec![TokenIdentity::Default]) {
+                self.consume(TokenIdentity::Colon, "Expect ':' after 'default'.")?;
+                self.consume(TokenIdentity::LeftBrace, "Expect '{' before default body.")?;
+                default = Some(self.block(in_loop)?);
+            } else {
+                return Err(ParsingError::new(
+                    self.peek().to_owned(),
+                    "Expect 'case' or 'default' in switch body.",
+                ));
+            }
+        }
+        self.consume(TokenIdentity::RightBrace, "Expect '}' after switch body.")?;
+
+        Ok(Stmt::Switch(SwitchStmt::new(subject, cases, default)))
+    }
+
+    fn throw_statement(&mut self) -> Result<Stmt, ParsingError> {
+        let keyword = self.previous().to_owned();
+        let value = self.expression()?;
+        self.consume(TokenIdentity::Semicolon, "Expect ';' after thrown value.")?;
+        Ok(Stmt::Throw(ThrowStmt::new(keyword, value)))
+    }
+
+    fn try_statement(&mut self, in_loop: bool) -> Result<Stmt, ParsingError> {
+        self.consume(TokenIdentity::LeftBrace, "Expect '{' after 'try'.")?;
+        let body = self.block(in_loop)?;
+
+        let mut kinds = Vec::new();
+        let catch = if self.match_token(vec![TokenIdentity::Catch]) {
+            self.consume(TokenIdentity::LeftParen, "Expect '(' after 'catch'.")?;
+            let name = self
+                .consume(TokenIdentity::Identifier, "Expect exception name.")?
+                .to_owned();
+            if self.match_token(vec![TokenIdentity::Colon]) {
+                loop {
+                    let kind = self
+                        .consume(TokenIdentity::Identifier, "Expect error kind.")?
+                        .to_owned();
+                    if !RuntimeErrorKind::NAMES.contains(&kind.atom().as_str()) {
+                        return Err(ParsingError::new(kind, "Unknown error kind."));
+                    }
+                    kinds.push(kind);
+                    if !self.match_token(vec![TokenIdentity::Pipe]) {
+                        break;
+                    }
+                }
+            }
+            self.consume(
+                TokenIdentity::RightParen,
+                "Expect ')' after exception name.",
+            )?;
+            self.consume(TokenIdentity::LeftBrace, "Expect '{' before catch body.")?;
+            Some((name, self.block(in_loop)?))
+        } else {
+            None
+        };
+        let finally = if self.match_token(vec![TokenIdentity::Finally]) {
+            self.consume(TokenIdentity::LeftBrace, "Expect '{' after 'finally'.")?;
+            Some(self.block(in_loop)?)
+        } else {
+            None
+        };
+        if catch.is_none() && finally.is_none() {
+            return Err(ParsingError::new(
+                self.peek().to_owned(),
+                "Expect 'catch' or 'finally' after try block.",
+            ));
+        }
+
+        Ok(Stmt::Try(TryStmt::new(body, catch, kinds, finally)))
+    }
+
     fn print_statement(&mut self) -> Result<Stmt, ParsingError> {
+        if self.dialect == Dialect::Lox {
+            let value = self.expression()?;
+            self.consume(TokenIdentity::Semicolon, "Expect ';' after value.")?;
+            return Ok(Stmt::Print(PrintStmt::new(value)));
+        }
         self.consume(TokenIdentity::LeftParen, "Expect '(' after 'print'.")?;
         let value = self.expression()?;
         self.consume(TokenIdentity::RightParen, "Expect ')' after arguments.")?;
@@ -245,9 +678,10 @@ impl Parser {
     fn expression_statement(&mut self) -> Result<Stmt, ParsingError> {
         let expression = self.expression()?;
 
-        // The semicolon isn't at the end of lambda expression.
+        // A lambda written with a block doesn't need a semicolon after it.
         if let Expr::Lambda(_) = expression {
-        } else {
+            self.match_token(vec![TokenIdentity::Semicolon]);
+        } else if !(self.optional_final_semicolon && self.is_at_end()) {
             self.consume(TokenIdentity::Semicolon, "Expect ';' after expression.")?;
         }
 
@@ -259,45 +693,130 @@ impl Parser {
             .consume(TokenIdentity::Identifier, &format!("Expect {kind} name."))?
             .to_owned();
         let mut parameters = Vec::new();
+        let mut variadic = false;
         if kind == FunctionType::Method && self.check(TokenIdentity::LeftBrace) {
             // Getter methods don't have parameters.
             kind = FunctionType::GetterMethod;
         } else {
-            if name.value == TokenValue::String("init".to_string()) {
+            if name.atom().as_str() == "init" {
                 kind = FunctionType::Initializer;
             }
             self.consume(
                 TokenIdentity::LeftParen,
                 &format!("Expect '(' after {kind} name."),
             )?;
-            if !self.check(TokenIdentity::RightParen) {
-                loop {
-                    if parameters.len() >= 255 {
-                        return Err(ParsingError::new(
-                            self.peek().to_owned(),
-                            "Can't have more than 255 parameters.",
-                        ));
-                    }
-                    parameters.push(
-                        self.consume(TokenIdentity::Identifier, "Expect parameter name.")?
-                            .to_owned(),
-                    );
+            (parameters, variadic) = self.parameters()?;
+        }
 
-                    if !self.match_token(vec![TokenIdentity::Comma]) {
-                        break;
-                    }
+        // `fun square(x) = x * x;` is short for a body returning the expression. Initializers
+        // can't return a value, so theirs only evaluates it, as in `init(x) = this.x = x;`.
+        let body = if self.match_token(vec![TokenIdentity::Equal]) {
+            let equal = self.previous().to_owned();
+            let value = self.expression()?;
+            self.consume(
+                TokenIdentity::Semicolon,
+                &format!("Expect ';' after {kind} body."),
+            )?;
+            if kind == FunctionType::Initializer {
+                BlockStmt::new(vec![Stmt::Expression(ExpressionStmt::new(value))])
+            } else {
+                implicit_return(equal, value)
+            }
+        } else {
+            self.consume(
+                TokenIdentity::LeftBrace,
+                &format!("Expect '{{' before {kind} body."),
+            )?;
+            self.block(false)?
+        };
+
+        Ok(FunctionStmt::new(
+            name.to_owned(),
+            parameters,
+            variadic,
+            body,
+            kind,
+            false,
+        ))
+    }
+
+    /// Parses the parameter list of a function or lambda, including the closing ')'. Also
+    /// returns whether the last parameter is a rest parameter, written `...name`.
+    fn parameters(&mut self) -> Result<(Vec<Token>, bool), ParsingError> {
+        self.parameter_list(TokenIdentity::RightParen, "Expect ')' after parameters.")
+    }
+
+    /// Parses parameters up to and including `end`.
+    fn parameter_list(
+        &mut self,
+        end: TokenIdentity,
+        message: &str,
+    ) -> Result<(Vec<Token>, bool), ParsingError> {
+        let mut names = HashSet::new();
+        let mut variadic = false;
+        let parameters = self.comma_separated("parameters", end, |parser| {
+            if variadic {
+                return Err(ParsingError::new(
+                    parser.peek().to_owned(),
+                    "Rest parameter must be last.",
+                ));
+            }
+            variadic = parser.match_token(vec![TokenIdentity::DotDotDot]);
+            let param = parser.consume(TokenIdentity::Identifier, "Expect parameter name.")?;
+            if !names.insert(param.value.to_string()) {
+                return Err(ParsingError::new(
+                    param.to_owned(),
+                    "Duplicate parameter name.",
+                ));
+            }
+            Ok(param.to_owned())
+        })?;
+        self.consume(end, message)?;
+        Ok((parameters, variadic))
+    }
+
+    /// Parses a comma-separated list of up to `MAX_ARITY` elements, stopping before `end`.
+    fn comma_separated<T>(
+        &mut self,
+        kind: &str,
+        end: TokenIdentity,
+        mut element: impl FnMut(&mut Self) -> Result<T, ParsingError>,
+    ) -> Result<Vec<T>, ParsingError> {
+        let mut elements = Vec::new();
+        if !self.check(end) {
+            loop {
+                if elements.len() >= MAX_ARITY {
+                    return Err(ParsingError::new(
+                        self.peek().to_owned(),
+                        &format!("Can't have more than {MAX_ARITY} {kind}."),
+                    ));
+                }
+                elements.push(element(self)?);
+                if !self.match_token(vec![TokenIdentity::Comma]) {
+                    break;
                 }
             }
-            self.consume(TokenIdentity::RightParen, "Expect ')' after parameters.")?;
         }
+        Ok(elements)
+    }
 
-        self.consume(
-            TokenIdentity::LeftBrace,
-            &format!("Expect '{{' before {kind} body."),
-        )?;
-        let body = self.block(false)?;
-
-        Ok(FunctionStmt::new(name.to_owned(), parameters, body, kind))
+    /// Parses the body of a control flow statement: either a block or a single statement,
+    /// which is wrapped into a block of its own.
+    fn body(&mut self, in_loop: bool) -> Result<BlockStmt, ParsingError> {
+        if self.match_token(vec![TokenIdentity::LeftBrace]) {
+            self.block(in_loop)
+        } else if self.check(TokenIdentity::Var)
+            || self.check(TokenIdentity::Const)
+            || self.check(TokenIdentity::Class)
+        {
+            Err(ParsingError::new(
+                self.peek().to_owned(),
+                "Expect '{' before a declaration in a body.",
+            ))
+        } else {
+            let statement = self.nested(|parser| parser.statement(in_loop))?;
+            Ok(BlockStmt::new(vec![statement]))
+        }
     }
 
     fn block(&mut self, in_loop: bool) -> Result<BlockStmt, ParsingError> {
@@ -310,7 +829,7 @@ impl Parser {
 
         let mut statements = Vec::new();
         while !self.check(TokenIdentity::RightBrace) && !self.is_at_end() {
-            statements.push(self.declaration(in_loop)?);
+            statements.extend(self.recoverable_declaration(in_loop)?);
         }
         self.consume(TokenIdentity::RightBrace, "Expect '}' after block.")?;
         // self.consume(TokenIdentity::Semicolon, "Expect ';' after block.")?;
@@ -319,43 +838,7 @@ impl Parser {
     }
 
     fn expression(&mut self) -> Result<Expr, ParsingError> {
-        self.lambda()
-    }
-
-    fn lambda(&mut self) -> Result<Expr, ParsingError> {
-        if self.previous().id == TokenIdentity::Fun || self.match_token(vec![TokenIdentity::Fun]) {
-            self.consume(
-                TokenIdentity::LeftParen,
-                "Expect '(' after 'fun' for lambda.",
-            )?;
-            let mut parameters = Vec::new();
-            if !self.check(TokenIdentity::RightParen) {
-                loop {
-                    if parameters.len() >= 255 {
-                        return Err(ParsingError::new(
-                            self.peek().to_owned(),
-                            "Can't have more than 255 parameters.",
-                        ));
-                    }
-                    parameters.push(
-                        self.consume(TokenIdentity::Identifier, "Expect parameter name.")?
-                            .to_owned(),
-                    );
-
-                    if !self.match_token(vec![TokenIdentity::Comma]) {
-                        break;
-                    }
-                }
-            }
-            self.consume(TokenIdentity::RightParen, "Expect ')' after parameters.")?;
-
-            self.consume(TokenIdentity::LeftBrace, "Expect '{' before function body.")?;
-            let body = self.block(false)?;
-
-            Ok(Expr::Lambda(Box::new(LambdaExpr::new(parameters, body))))
-        } else {
-            self.ternary()
-        }
+        self.nested(Self::ternary)
     }
 
     fn ternary(&mut self) -> Result<Expr, ParsingError> {
@@ -376,7 +859,7 @@ impl Parser {
     }
 
     fn assignment(&mut self) -> Result<Expr, ParsingError> {
-        let expr = self.or()?;
+        let expr = self.coalesce()?;
 
         if self.match_token(vec![TokenIdentity::Equal]) {
             let equals = self.previous().to_owned();
@@ -384,9 +867,38 @@ impl Parser {
 
             match expr {
                 Expr::Variable(var) => Ok(Expr::Assign(Box::new(AssignExpr::new(var.name, value)))),
-                Expr::Get(get) => Ok(Expr::Set(Box::new(SetExpr::new(
-                    get.object, get.name, value,
+                Expr::Get(mut get) => Ok(Expr::Set(Box::new(SetExpr::new(
+                    get.take_object(),
+                    get.name.clone(),
+                    value,
                 )))),
+                Expr::Index(index) => Ok(Expr::IndexSet(Box::new(IndexSetExpr::new(
+                    index.object,
+                    index.bracket,
+                    index.index,
+                    value,
+                )))),
+                // `[a, b] = pair;` assigns the elements of a list to existing variables.
+                Expr::List(list) if !list.elements.is_empty() => {
+                    let names = list
+                        .elements
+                        .into_iter()
+                        .map(|element| match element {
+                            Expr::Variable(var) => Ok(var.name),
+                            _ => Err(ParsingError::new(
+                                equals.clone(),
+                                "Invalid assignment target.",
+                            )),
+                        })
+                        .collect::<Result<Vec<Token>, ParsingError>>()?;
+                    Ok(Expr::Destructure(Box::new(DestructureExpr::new(
+                        Pattern::List {
+                            bracket: list.bracket,
+                            names,
+                        },
+                        value,
+                    ))))
+                }
                 _ => Err(ParsingError::new(equals, "Invalid assignment target.")),
             }
         } else {
@@ -394,6 +906,17 @@ impl Parser {
         }
     }
 
+    fn coalesce(&mut self) -> Result<Expr, ParsingError> {
+        let mut expr = self.or()?;
+
+        while self.match_token(vec![TokenIdentity::QuestionQuestion]) {
+            let operator = self.previous().to_owned();
+            let right = self.or()?;
+            expr = Expr::Logical(Box::new(LogicalExpr::new(expr, operator, right)));
+        }
+        Ok(expr)
+    }
+
     fn or(&mut self) -> Result<Expr, ParsingError> {
         let mut expr = self.and()?;
 
@@ -419,7 +942,11 @@ impl Parser {
     fn equality(&mut self) -> Result<Expr, ParsingError> {
         let mut expr = self.comparison()?;
 
-        while self.match_token(vec![TokenIdentity::BangEqual, TokenIdentity::EqualEqual]) {
+        while self.match_token(vec![
+            TokenIdentity::BangEqual,
+            TokenIdentity::EqualEqual,
+            TokenIdentity::In,
+        ]) {
             let operator = self.previous().to_owned();
             let right = self.comparison()?;
             expr = Expr::Binary(Box::new(BinaryExpr::new(expr, operator, right)));
@@ -435,6 +962,7 @@ impl Parser {
             TokenIdentity::GreaterEqual,
             TokenIdentity::Less,
             TokenIdentity::LessEqual,
+            TokenIdentity::Is,
         ]) {
             let operator = self.previous().to_owned();
             let right = self.term()?;
@@ -466,9 +994,13 @@ impl Parser {
     }
 
     fn unary(&mut self) -> Result<Expr, ParsingError> {
-        if self.match_token(vec![TokenIdentity::Bang, TokenIdentity::Minus]) {
+        if self.match_token(vec![
+            TokenIdentity::Bang,
+            TokenIdentity::Minus,
+            TokenIdentity::Typeof,
+        ]) {
             let operator = self.previous().to_owned();
-            let right = self.unary()?;
+            let right = self.nested(Self::unary)?;
             Ok(Expr::Unary(Box::new(UnaryExpr::new(operator, right))))
         } else {
             self.call()
@@ -485,6 +1017,8 @@ impl Parser {
                 let name =
                     self.consume(TokenIdentity::Identifier, "Expect property name after '.'.")?;
                 expr = Expr::Get(Box::new(GetExpr::new(expr, name.to_owned())));
+            } else if self.match_token(vec![TokenIdentity::LeftBracket]) {
+                expr = self.finish_index(expr)?;
             } else {
                 break;
             }
@@ -494,33 +1028,122 @@ impl Parser {
     }
 
     fn finish_call(&mut self, callee: Expr) -> Result<Expr, ParsingError> {
-        let mut arguments = Vec::new();
+        let arguments =
+            self.comma_separated("arguments", TokenIdentity::RightParen, Self::expression)?;
+        let paren = self
+            .consume(TokenIdentity::RightParen, "Expect ')' after arguments.")?
+            .to_owned();
+
+        Ok(Expr::Call(Box::new(CallExpr::new(
+            callee, paren, arguments,
+        ))))
+    }
+
+    fn finish_index(&mut self, object: Expr) -> Result<Expr, ParsingError> {
+        let bracket = self.previous().to_owned();
+        let start = if self.check(TokenIdentity::Colon) {
+            None
+        } else {
+            Some(self.expression()?)
+        };
+
+        if self.match_token(vec![TokenIdentity::Colon]) {
+            let end = if self.check(TokenIdentity::RightBracket) {
+                None
+            } else {
+                Some(self.expression()?)
+            };
+            self.consume(TokenIdentity::RightBracket, "Expect ']' after slice.")?;
+            Ok(Expr::Slice(Box::new(SliceExpr::new(
+                object, bracket, start, end,
+            ))))
+        } else {
+            self.consume(TokenIdentity::RightBracket, "Expect ']' after index.")?;
+            // `start` is only missing when a ':' follows it.
+            Ok(Expr::Index(Box::new(IndexExpr::new(
+                object,
+                bracket,
+                start.unwrap(),
+            ))))
+        }
+    }
 
-        if !self.check(TokenIdentity::RightParen) {
+    fn lambda(&mut self) -> Result<Expr, ParsingError> {
+        self.consume(
+            TokenIdentity::LeftParen,
+            "Expect '(' after 'fun' for lambda.",
+        )?;
+        let (parameters, variadic) = self.parameters()?;
+
+        self.consume(TokenIdentity::LeftBrace, "Expect '{' before function body.")?;
+        let body = self.block(false)?;
+
+        Ok(Expr::Lambda(Rc::new(LambdaExpr::new(
+            parameters, variadic, body,
+        ))))
+    }
+
+    /// `|params| expression`, a lambda returning `expression`.
+    fn arrow_lambda(&mut self) -> Result<Expr, ParsingError> {
+        let pipe = self.previous().to_owned();
+        let (parameters, variadic) =
+            self.parameter_list(TokenIdentity::Pipe, "Expect '|' after parameters.")?;
+        let value = self.expression()?;
+        let body = implicit_return(pipe, value);
+
+        Ok(Expr::Lambda(Rc::new(LambdaExpr::new(
+            parameters, variadic, body,
+        ))))
+    }
+
+    fn list(&mut self) -> Result<Expr, ParsingError> {
+        let bracket = self.previous().to_owned();
+        let mut elements = Vec::new();
+
+        if !self.check(TokenIdentity::RightBracket) {
             loop {
-                if arguments.len() >= 255 {
-                    return Err(ParsingError::new(
-                        self.peek().to_owned(),
-                        "Can't have more than 255 arguments.",
-                    ));
-                }
-                arguments.push(self.expression()?);
+                elements.push(self.expression()?);
                 if !self.match_token(vec![TokenIdentity::Comma]) {
                     break;
                 }
             }
         }
+        self.consume(
+            TokenIdentity::RightBracket,
+            "Expect ']' after list elements.",
+        )?;
 
-        let paren = self
-            .consume(TokenIdentity::RightParen, "Expect ')' after arguments.")?
-            .to_owned();
+        Ok(Expr::List(Box::new(ListExpr::new(bracket, elements))))
+    }
 
-        Ok(Expr::Call(Box::new(CallExpr::new(
-            callee, paren, arguments,
-        ))))
+    fn map(&mut self) -> Result<Expr, ParsingError> {
+        let brace = self.previous().to_owned();
+        let mut entries = Vec::new();
+
+        if !self.check(TokenIdentity::RightBrace) {
+            loop {
+                let key = self.expression()?;
+                self.consume(TokenIdentity::Colon, "Expect ':' after map key.")?;
+                let value = self.expression()?;
+                entries.push((key, value));
+                if !self.match_token(vec![TokenIdentity::Comma]) {
+                    break;
+                }
+            }
+        }
+        self.consume(TokenIdentity::RightBrace, "Expect '}' after map entries.")?;
+
+        Ok(Expr::Map(Box::new(MapExpr::new(brace, entries))))
     }
 
     fn primary(&mut self) -> Result<Expr, ParsingError> {
+        // The end of the input is never advanced past, so it's reported as it is.
+        if self.is_at_end() {
+            return Err(ParsingError::new(
+                self.peek().to_owned(),
+                "Unexpected expression",
+            ));
+        }
         let token_type = self.advance().id;
         match token_type {
             TokenIdentity::False => Ok(Expr::Literal(LiteralExpr::new(Object::Boolean(false)))),
@@ -528,6 +1151,9 @@ impl Parser {
             TokenIdentity::Nil => Ok(Expr::Literal(LiteralExpr::new(Object::Nil))),
             TokenIdentity::Number => match self.previous().value {
                 TokenValue::Number(num) => Ok(Expr::Literal(LiteralExpr::new(Object::Number(num)))),
+                TokenValue::Integer(num) => {
+                    Ok(Expr::Literal(LiteralExpr::new(Object::Integer(num))))
+                }
                 _ => panic!("Unexpected object type"),
             },
             TokenIdentity::String => match self.previous().value.clone() {
@@ -550,13 +1176,35 @@ impl Parser {
                 self.consume(TokenIdentity::RightParen, "Expect ')' after expression.")?;
                 Ok(Expr::Grouping(Box::new(GroupingExpr::new(expr))))
             }
+            TokenIdentity::Fun => self.lambda(),
+            TokenIdentity::Pipe => self.arrow_lambda(),
+            TokenIdentity::LeftBracket => self.list(),
+            // A '{' at the start of a statement opens a block, so in expressions it's a map.
+            TokenIdentity::LeftBrace => self.map(),
             _ => Err(ParsingError::new(
-                self.peek().to_owned(),
+                self.previous().to_owned(),
                 "Unexpected expression",
             )),
         }
     }
 
+    /// Parses one level deeper, failing past [`MAX_NESTING`] levels.
+    fn nested<T>(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<T, ParsingError>,
+    ) -> Result<T, ParsingError> {
+        if self.depth == MAX_NESTING {
+            return Err(ParsingError::new(
+                self.peek().to_owned(),
+                "Too much nesting.",
+            ));
+        }
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
     fn consume(&mut self, id: TokenIdentity, message: &str) -> Result<&Token, ParsingError> {
         if self.check(id) {
             return Ok(self.advance());
@@ -582,6 +1230,17 @@ impl Parser {
         self.peek().id == id
     }
 
+    fn check_next(&self, id: TokenIdentity) -> bool {
+        self.check_at(1, id)
+    }
+
+    /// Checks the token `offset` positions after the current one without consuming anything.
+    fn check_at(&self, offset: usize, id: TokenIdentity) -> bool {
+        self.tokens
+            .get(self.current + offset)
+            .is_some_and(|token| token.id == id)
+    }
+
     fn advance(&mut self) -> &Token {
         if !self.is_at_end() {
             self.current += 1;
@@ -601,3 +1260,402 @@ impl Parser {
         &self.tokens[self.current - 1]
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scanner::Scanner;
+
+    fn parse(source: &str) -> Result<Vec<Stmt>, ParsingError> {
+        Parser::new(Scanner::new(source).collect()).parse()
+    }
+
+    fn names(count: usize) -> String {
+        (0..count)
+            .map(|i| {
+                format!(
+                    "p{}{}",
+                    (b'a' + (i / 26) as u8) as char,
+                    (b'a' + (i % 26) as u8) as char
+                )
+            })
+            .collect::<Vec<String>>()
+            .join(", ")
+    }
+
+    #[test]
+    fn test_max_arity() {
+        let params = names(MAX_ARITY);
+        assert!(parse(&format!("fun f({params}) {{}}")).is_ok());
+        assert!(parse(&format!("var f = fun ({params}) {{}};")).is_ok());
+        assert!(parse(&format!("f({params});")).is_ok());
+
+        let params = names(MAX_ARITY + 1);
+        let error = parse(&format!("fun f({params}) {{}}")).unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .ends_with("Can't have more than 255 parameters.")
+        );
+        let error = parse(&format!("var f = fun ({params}) {{}};")).unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .ends_with("Can't have more than 255 parameters.")
+        );
+        let error = parse(&format!("f({params});")).unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .ends_with("Can't have more than 255 arguments.")
+        );
+    }
+
+    #[test]
+    fn test_rest_parameter() {
+        assert!(parse("fun f(a, ...rest) {}").is_ok());
+        assert!(parse("var f = fun (...rest) {};").is_ok());
+
+        let error = parse("fun f(...rest, a) {}").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "[line 1:16] Parsing error at 'a': Rest parameter must be last."
+        );
+    }
+
+    #[test]
+    fn test_try() {
+        assert!(parse("try {} catch (e) {}").is_ok());
+        assert!(parse("try {} finally {}").is_ok());
+        assert!(parse("while (true) { try { break; } catch (e) { continue; } }").is_ok());
+        let statements = parse("try {} catch (e: DivisionByZero | TypeMismatch) {}").unwrap();
+        let Stmt::Try(stmt) = &statements[0] else {
+            panic!("expected a try statement");
+        };
+        assert_eq!(stmt.kinds.len(), 2);
+
+        let error = parse("try {} catch (e: Oops) {}").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "[line 1:18] Parsing error at 'Oops': Unknown error kind."
+        );
+
+        let error = parse("try {} print(1);").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "[line 1:8] Parsing error at 'print': Expect 'catch' or 'finally' after try block."
+        );
+    }
+
+    #[test]
+    fn test_memo_getter() {
+        let statements = parse("class A { memo a { return 1; } memo() {} }").unwrap();
+        let Stmt::Class(class) = &statements[0] else {
+            panic!("Expected a class");
+        };
+        assert!(class.getter_methods[0].memo);
+        assert!(!class.methods[0].memo);
+
+        let error = parse("class A { memo f() {} }").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "[line 1:16] Parsing error at 'f': Only getters can be memoized."
+        );
+    }
+
+    #[test]
+    fn test_expression_body() {
+        let statements = parse("fun square(x) = x * x;").unwrap();
+        let Stmt::Function(function) = &statements[0] else {
+            panic!("Expected a function");
+        };
+        assert!(matches!(
+            function.body.statements[..],
+            [Stmt::Return(ReturnStmt { value: Some(_), .. })]
+        ));
+
+        // An initializer's body is evaluated for its effects instead of returned.
+        let statements = parse("class P { init(x) = this.x = x; }").unwrap();
+        let Stmt::Class(class) = &statements[0] else {
+            panic!("Expected a class");
+        };
+        assert!(matches!(
+            class.methods[0].body.statements[..],
+            [Stmt::Expression(_)]
+        ));
+
+        let error = parse("fun f() = 1\nprint(f());").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "[line 2:1] Parsing error at 'print': Expect ';' after function body."
+        );
+    }
+
+    #[test]
+    fn test_import_export() {
+        let statements =
+            parse("import \"utils.lox\" as utils; export var [a, b] = [1, 2];").unwrap();
+        let Stmt::Import(import) = &statements[0] else {
+            panic!("Expected an import");
+        };
+        assert_eq!(import.name.value.to_string(), "utils");
+        let Stmt::Export(export) = &statements[1] else {
+            panic!("Expected an export");
+        };
+        assert_eq!(export.names().len(), 2);
+
+        let error = parse("export print(1);").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "[line 1:8] Parsing error at 'print': Expect a declaration after 'export'."
+        );
+        let error = parse("import \"utils.lox\" utils;").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "[line 1:20] Parsing error at 'utils': Expect 'as' after module path."
+        );
+    }
+
+    #[test]
+    fn test_duplicate_names() {
+        let error = parse("fun f(a, b, a) {}").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "[line 1:13] Parsing error at 'a': Duplicate parameter name."
+        );
+        let error = parse("var f = fun (a, a) {};").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "[line 1:17] Parsing error at 'a': Duplicate parameter name."
+        );
+
+        let error = parse("class A {\n  foo() {}\n  foo(a) {}\n}").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "[line 3:3] Parsing error at 'foo': Duplicate method name in class 'A'."
+        );
+        let error = parse("class A {\n  foo {}\n  class foo() {}\n}").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "[line 3:9] Parsing error at 'foo': Duplicate method name in class 'A'."
+        );
+        assert!(parse("class A { foo() {} bar() {} class baz() {} }").is_ok());
+    }
+
+    #[test]
+    fn test_class_fields() {
+        let statements = parse("class A { var x = 1; var y; init() {} }").unwrap();
+        let Stmt::Class(class) = &statements[0] else {
+            panic!("Expected a class");
+        };
+        let names: Vec<_> = class
+            .fields
+            .iter()
+            .map(|field| field.name.value.to_string())
+            .collect();
+        assert_eq!(names, ["x", "y"]);
+        assert!(class.fields[1].initializer.is_none());
+        assert_eq!(class.methods.len(), 1);
+        assert!(class.static_blocks.is_empty());
+
+        let statements = parse("class A { class {} class f() {} class { var a = 1; } }").unwrap();
+        let Stmt::Class(class) = &statements[0] else {
+            panic!("Expected a class");
+        };
+        assert_eq!(class.static_blocks.len(), 2);
+        assert_eq!(class.static_methods.len(), 1);
+
+        let error = parse("class A {\n  var x = 1;\n  var x = 2;\n}").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "[line 3:7] Parsing error at 'x': Duplicate field name in class 'A'."
+        );
+    }
+
+    #[test]
+    fn test_empty_bodies() {
+        for source in [
+            "",
+            "{}",
+            "{ {} }",
+            "class A {}",
+            "class A {} class B < A {}",
+            "fun f() { class A {} }",
+            "if (true) {} else {}",
+            "while (false) {}",
+            "for (;;) {}",
+            "for (x in []) {}",
+            "switch (1) { case 1: {} default: {} }",
+            "var f = fun () {};",
+        ] {
+            assert!(parse(source).is_ok(), "{source}");
+        }
+
+        let error = parse("class A {").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "[line 1:10] Parsing error at end: Expect '}' after class body."
+        );
+        let error = parse("if (true) {").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "[line 1:12] Parsing error at end: Expect '}' after block."
+        );
+    }
+
+    #[test]
+    fn test_lox_dialect() {
+        let parse_lox = |source: &str| {
+            let tokens = Scanner::new(source).with_dialect(Dialect::Lox).collect();
+            Parser::new(tokens).with_dialect(Dialect::Lox).parse()
+        };
+        for source in [
+            "print 1 + 2;",
+            "print (1) + 2;",
+            "for (var i = 0; i < 3; i = i + 1) print i;",
+            "var break = 1; fun try(in) { return in; } print try(break);",
+            "class A { method() { print this; } }",
+        ] {
+            assert!(parse_lox(source).is_ok(), "{source}");
+        }
+        let statements = parse_lox("print (1) + 2;").unwrap();
+        assert!(matches!(
+            &statements[..],
+            [Stmt::Print(PrintStmt {
+                expr: Expr::Binary(_)
+            })]
+        ));
+
+        let error = parse_lox("print 1").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "[line 1:8] Parsing error at end: Expect ';' after value."
+        );
+        assert!(parse("print 1;").is_err());
+        assert!(parse("var break = 1;").is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde() {
+        use crate::ast_printer::AstPrinter;
+
+        let source = "
+            var [a, b] = [1, 2.5];
+            class A < B { init(...xs) { super.init(xs); } area { return nil; } }
+            print({\"k\": |x| x ? true : -x}[\"k\"](a)[1:]);
+        ";
+        let statements = parse(source).unwrap();
+        let json = serde_json::to_string(&statements).unwrap();
+        let read = serde_json::from_str::<Vec<Stmt>>(&json).unwrap();
+        assert_eq!(
+            AstPrinter::new().print(&read),
+            AstPrinter::new().print(&statements)
+        );
+
+        let tokens = Scanner::new("print(1);").collect::<Vec<_>>();
+        let json = serde_json::to_string(&tokens[2]).unwrap();
+        assert!(
+            json.contains("\"id\":\"Number\",\"value\":{\"Integer\":1}"),
+            "{json}"
+        );
+        let token = serde_json::from_str::<Token>(&json).unwrap();
+        assert_eq!((token.value, token.column), (TokenValue::Integer(1), 7));
+    }
+
+    #[test]
+    fn test_parse_all() {
+        let source = "
+            var a = 1
+            fun f() {
+                print(a +);
+                return a;
+            }
+            { b = 2 }
+            print(a);
+        ";
+        let parse_all = |source: &str| Parser::new(Scanner::new(source).collect()).parse_all();
+        let errors = parse_all(source).unwrap_err();
+        let messages = errors
+            .iter()
+            .map(ParsingError::to_string)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            messages,
+            [
+                "[line 3:13] Parsing error at 'fun': Expect ';' after variable declaration.",
+                "[line 4:26] Parsing error at ')': Unexpected expression",
+                "[line 7:21] Parsing error at '}': Expect ';' after expression.",
+            ]
+        );
+        assert_eq!(parse_all("print(1); print(2);").unwrap().len(), 2);
+        assert_eq!(parse_all("} }").unwrap_err().len(), 2);
+    }
+
+    #[test]
+    fn test_optional_final_semicolon() {
+        let parse_repl = |source: &str| {
+            Parser::new(Scanner::new(source).collect())
+                .with_optional_final_semicolon(true)
+                .parse()
+        };
+        assert!(parse_repl("1 + 2").is_ok());
+        assert!(parse_repl("var a = 1; a = a + 1").is_ok());
+        assert!(parse_repl("1 + 2 3").is_err());
+        assert!(parse_repl("var a = 1").is_err());
+        assert!(parse_repl("{ 1 + 2 }").is_err());
+        assert!(parse("1 + 2").is_err());
+    }
+
+    #[test]
+    fn test_errors_at_end() {
+        let at_end = |source: &str| {
+            Parser::new(Scanner::new(source).collect())
+                .with_optional_final_semicolon(true)
+                .parse()
+                .is_err_and(|error| error.is_at_end())
+        };
+        assert!(at_end("var a = 1"));
+        assert!(at_end("(1 +"));
+        assert!(at_end("fun f() {\n  print(1);"));
+        assert!(at_end("print(\"open"));
+        assert!(!at_end("var c = ;"));
+        assert!(!at_end("1 % 2"));
+        assert!(!at_end("1 + 2"));
+    }
+
+    #[test]
+    fn test_nested_set_targets() {
+        let target = |source: &str| {
+            let statements = parse(source).unwrap();
+            let Stmt::Expression(ExpressionStmt {
+                expr: Expr::Set(set),
+            }) = &statements[0]
+            else {
+                panic!("Expected a set expression: {source}");
+            };
+            (set.object.clone(), set.name.value.to_string())
+        };
+
+        let (object, name) = target("a.b.c = 1;");
+        assert_eq!(name, "c");
+        assert!(matches!(
+            object,
+            Expr::Get(get) if get.name.value.to_string() == "b"
+                && matches!(get.object, Expr::Variable(_))
+        ));
+        let (object, name) = target("f().x.y = 1;");
+        assert_eq!(name, "y");
+        assert!(matches!(object, Expr::Get(get) if matches!(get.object, Expr::Call(_))));
+        let (object, _) = target("list[0].x = 1;");
+        assert!(matches!(object, Expr::Index(_)));
+
+        for source in ["a.b() = 1;", "a.b + c = 1;", "(a.b) = 1;"] {
+            let error = parse(source).unwrap_err();
+            assert!(
+                error.to_string().ends_with("Invalid assignment target."),
+                "{source}"
+            );
+        }
+    }
+}
@@ -0,0 +1,24 @@
+//! The supported API of the crate. Everything re-exported here follows semver, while the
+//! syntax tree and the runtime internals behind it may change in any release.
+//!
+//! ```
+//! use crafting_interpreters::prelude::*;
+//!
+//! let (mut interpreter, output) = Interpreter::with_capture();
+//! let (result, _) = run_timed("print(1 + 2); 1 + 2;", &mut interpreter);
+//! assert_eq!(result.ok().unwrap(), Object::Integer(3));
+//! assert_eq!(output.into_string(), "3\n");
+//! ```
+
+pub use crate::{
+    Lox,
+    atom::AtomTable,
+    capture::CaptureWriter,
+    error::{LoxError, ParsingError, RuntimeError, RuntimeErrorKind, RuntimeException},
+    interpreter::{GlobalRedefinition, Interpreter, OptLevel, UninitializedRead},
+    object::Object,
+    parser::{Dialect, Parser},
+    resolver::Resolver,
+    scanner::Scanner,
+    timings::{Timings, run_parsed, run_timed},
+};
@@ -0,0 +1,67 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The pseudo-random number generator behind the `random` natives (SplitMix64). It is not
+/// suitable for cryptography, but the same seed always gives the same sequence.
+#[derive(Clone, Debug)]
+pub struct Random {
+    state: u64,
+}
+
+impl Random {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    /// Seeds the generator from the current time.
+    pub fn from_time() -> Self {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Time went backwards");
+        Self::new(now.as_nanos() as u64)
+    }
+
+    pub fn seed(&mut self, seed: u64) {
+        self.state = seed;
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number in `[0, 1)`.
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// An integer from `start` up to, but excluding, `end`. `start` must be less than `end`.
+    pub fn next_in_range(&mut self, start: i64, end: i64) -> i64 {
+        let width = end.abs_diff(start);
+        start.wrapping_add((self.next_u64() % width) as i64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seed() {
+        let mut a = Random::new(42);
+        let mut b = Random::from_time();
+        b.seed(42);
+        for _ in 0..100 {
+            assert_eq!(a.next_u64(), b.next_u64());
+        }
+
+        for _ in 0..1000 {
+            let n = a.next_f64();
+            assert!((0.0..1.0).contains(&n));
+            let n = a.next_in_range(-3, 3);
+            assert!((-3..3).contains(&n));
+        }
+    }
+}
@@ -1,21 +1,53 @@
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
 
 use crate::{
-    error::RuntimeError,
+    atom::Atom,
+    error::{RuntimeError, RuntimeErrorKind},
     expr::{
-        AssignExpr, BinaryExpr, CallExpr, Expr, ExprVisitor, GetExpr, GroupingExpr, LambdaExpr,
-        LiteralExpr, LogicalExpr, SetExpr, SuperExpr, TernaryExpr, ThisExpr, UnaryExpr,
-        VariableExpr,
+        AssignExpr, BinaryExpr, CallExpr, DestructureExpr, Expr, ExprVisitor, GetExpr,
+        GroupingExpr, IndexExpr, IndexSetExpr, LambdaExpr, ListExpr, LiteralExpr, LogicalExpr,
+        MapExpr, SetExpr, SliceExpr, SuperExpr, TernaryExpr, ThisExpr, UnaryExpr, VariableExpr,
     },
     function::FunctionType,
-    interpreter::Interpreter,
+    interpreter::{Interpreter, OptLevel},
+    optimizer::{self, InlineFunction},
+    parser::Dialect,
     stmt::{
-        BlockStmt, ClassStmt, ExpressionStmt, FunctionStmt, IfStmt, PrintStmt, ReturnStmt, Stmt,
-        StmtVisitor, VarStmt, WhileStmt,
+        BlockStmt, BreakStmt, ClassStmt, ContinueStmt, DestructureStmt, ExportStmt, ExpressionStmt,
+        ForInStmt, ForStmt, FunctionStmt, IfStmt, ImportStmt, PrintStmt, ReturnStmt, Stmt,
+        StmtVisitor, SwitchStmt, ThrowStmt, TryStmt, VarStmt, WhileStmt,
     },
     token::Token,
 };
 
+/// The state of a name in a scope.
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Binding {
+    /// Declared, but its initializer hasn't been resolved yet.
+    Declared,
+    Defined,
+    /// Defined by a `const` declaration, so it can't be assigned to.
+    Constant,
+}
+
+/// What a variable is known to hold, as long as it isn't assigned to.
+enum KnownValue {
+    /// A local variable initialized with a literal, which can stand in for its reads.
+    Literal(LiteralExpr),
+    /// A function whose calls can be inlined.
+    Function(Rc<InlineFunction>),
+}
+
+struct Known {
+    value: KnownValue,
+    assigned: bool,
+    /// The [hashes](Expr::to_hash) of the expressions reading the variable.
+    reads: Vec<u64>,
+}
+
 #[derive(Copy, Clone, Debug, PartialEq)]
 enum ClassType {
     None,
@@ -23,11 +55,28 @@ enum ClassType {
     Subclass,
 }
 
+/// The top-level scope of a session, which outlives the resolver of each input: it's handed
+/// to the next one [`with_top_level`](Resolver::with_top_level), and taken back with
+/// [`into_top_level`](Resolver::into_top_level).
+#[derive(Default)]
+pub struct TopLevel(HashMap<Atom, Binding>);
+
 pub struct Resolver<'a> {
     pub interpreter: &'a mut Interpreter,
-    scopes: Vec<HashMap<String, bool>>,
+    scopes: Vec<HashMap<Atom, Binding>>,
     current_function: FunctionType,
     current_class: ClassType,
+    // Whether we're inside a static method, including the functions and lambdas nested in it.
+    // `current_function` alone can't tell, as it's overwritten by nested functions.
+    in_static_method: bool,
+    known: Vec<Known>,
+    // For each scope, its variables with a known value, as indices into `known`.
+    known_scopes: Vec<HashMap<Atom, usize>>,
+    // Whether the program comes in several inputs, resolved one at a time.
+    in_session: bool,
+    // The top-level variables declared by earlier inputs, which the current one may declare
+    // again.
+    redeclarable: HashSet<Atom>,
 }
 
 impl<'a> Resolver<'a> {
@@ -37,17 +86,118 @@ impl<'a> Resolver<'a> {
             scopes: vec![HashMap::new()],
             current_function: FunctionType::default(),
             current_class: ClassType::None,
+            in_static_method: false,
+            known: Vec::new(),
+            known_scopes: vec![HashMap::new()],
+            in_session: false,
+            redeclarable: HashSet::new(),
         }
     }
 
+    /// Resolves in the top-level scope left by the inputs of a session resolved before, with
+    /// [`Resolver::resolve_input`].
+    pub fn with_top_level(mut self, top_level: TopLevel) -> Self {
+        self.scopes[0] = top_level.0;
+        self
+    }
+
+    /// The top-level scope, for the resolver of the session's next input.
+    pub fn into_top_level(mut self) -> TopLevel {
+        TopLevel(self.scopes.swap_remove(0))
+    }
+
     pub fn resolve_stmts(&mut self, statements: &[Stmt]) -> Result<(), RuntimeError> {
         for stmt in statements {
             self.resolve_stmt(stmt)?;
         }
-
+        self.warn_unreachable(statements);
         Ok(())
     }
 
+    /// Resolves a program like [`Resolver::resolve_stmts`], but goes on after an error in a
+    /// top-level statement to report the errors of the statements after it too.
+    pub fn resolve_all(&mut self, statements: &[Stmt]) -> Result<(), Vec<RuntimeError>> {
+        let mut errors = Vec::new();
+        for stmt in statements {
+            if let Err(error) = self.resolve_stmt(stmt) {
+                errors.push(error);
+                self.reset();
+            }
+        }
+        self.warn_unreachable(statements);
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Resolves one input of a session, such as a line of the REPL, in the top-level scope
+    /// left by the inputs before it. The variables they declared can be declared again, as
+    /// the input runs after them, and an input that fails to resolve, so never runs, leaves
+    /// the scope as it found it.
+    pub fn resolve_input(&mut self, statements: &[Stmt]) -> Result<(), RuntimeError> {
+        self.in_session = true;
+        let top_level = self.scopes[0].clone();
+        self.redeclarable = top_level.keys().cloned().collect();
+        let result = self.resolve_stmts(statements);
+        if result.is_err() {
+            self.reset();
+            self.scopes[0] = top_level;
+            self.known.clear();
+        }
+        result
+    }
+
+    /// Warns about the statements of a block following one that always leaves it.
+    fn warn_unreachable(&self, statements: &[Stmt]) {
+        if let Some(keyword) = statements
+            .split_last()
+            .and_then(|(_, before)| before.iter().find_map(Stmt::jump_keyword))
+        {
+            self.interpreter
+                .warn(keyword, "Unreachable code after this statement.");
+        }
+    }
+
+    /// Closes the scopes an error left open, going back to the top level.
+    fn reset(&mut self) {
+        self.scopes.truncate(1);
+        self.known_scopes.truncate(1);
+        self.current_function = FunctionType::default();
+        self.current_class = ClassType::None;
+        self.in_static_method = false;
+    }
+
+    /// Applies the optimizations of the interpreter's [`OptLevel`] to `statements`, which must
+    /// have been resolved by this resolver.
+    pub fn optimize(&mut self, statements: &mut [Stmt]) {
+        let mut literals = HashMap::new();
+        let mut functions = HashMap::new();
+        for known in self.known.drain(..).filter(|known| !known.assigned) {
+            for read in known.reads {
+                match &known.value {
+                    KnownValue::Literal(literal) => {
+                        literals.insert(read, literal.clone());
+                    }
+                    KnownValue::Function(function) => {
+                        functions.insert(read, function.clone());
+                    }
+                }
+            }
+        }
+        let locals = &mut self.interpreter.locals;
+        if self.interpreter.opt_level >= OptLevel::Constants {
+            optimizer::propagate_constants(statements, &literals, locals);
+        }
+        if self.interpreter.opt_level >= OptLevel::Inline {
+            optimizer::inline_calls(statements, &functions, locals);
+        }
+        if self.interpreter.opt_level >= OptLevel::Constants {
+            optimizer::prune_dead_code(statements, locals);
+        }
+    }
+
     fn resolve_stmt(&mut self, stmt: &Stmt) -> Result<(), RuntimeError> {
         StmtVisitor::accept(self, stmt)
     }
@@ -73,21 +223,30 @@ impl<'a> Resolver<'a> {
 
     fn begin_scope(&mut self) {
         self.scopes.push(HashMap::new());
+        self.known_scopes.push(HashMap::new());
     }
 
     fn end_scope(&mut self) {
         self.scopes.pop();
+        self.known_scopes.pop();
     }
 
+    /// Declares `name` in the innermost scope, where it mustn't be declared already. Top-level
+    /// names can be declared again in the book's grammar, as they're globals there.
     fn declare(&mut self, name: &Token) -> Result<(), RuntimeError> {
+        let is_top_level = self.scopes.len() == 1;
+        let is_lox = self.interpreter.dialect == Dialect::Lox;
         if let Some(scope) = self.scopes.last_mut() {
-            if scope.contains_key(&name.value.to_string()) {
+            if scope.contains_key(&name.atom())
+                && !(is_top_level && (is_lox || self.redeclarable.remove(&name.atom())))
+            {
                 return Err(RuntimeError::new(
                     name.to_owned(),
+                    RuntimeErrorKind::Redefinition,
                     "Already a variable with this name in this scope.",
                 ));
             }
-            scope.insert(name.value.to_string(), false);
+            scope.insert(name.atom(), Binding::Declared);
         }
 
         Ok(())
@@ -95,24 +254,86 @@ impl<'a> Resolver<'a> {
 
     fn define(&mut self, name: &Token) {
         if let Some(scope) = self.scopes.last_mut() {
-            scope.insert(name.value.to_string(), true);
+            scope.insert(name.atom(), Binding::Defined);
+        }
+    }
+
+    fn define_const(&mut self, name: &Token) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.atom(), Binding::Constant);
+        }
+    }
+
+    fn check_assignable(&self, name: &Token) -> Result<(), RuntimeError> {
+        if let Some(Binding::Constant) = self
+            .scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(&name.atom()))
+        {
+            return Err(RuntimeError::new(
+                name.clone(),
+                RuntimeErrorKind::ConstantAssignment,
+                "Can't assign to a constant.",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Rejects `keyword` unless it's at the top level of the program, outside any block.
+    fn check_top_level(&self, keyword: &Token, message: &str) -> Result<(), RuntimeError> {
+        if self.scopes.len() > 1 {
+            return Err(RuntimeError::new(
+                keyword.clone(),
+                RuntimeErrorKind::InvalidContext,
+                message,
+            ));
         }
+        Ok(())
     }
 
     fn resolve_local(&mut self, expr: &Expr, name: &Token) {
         for i in (0..self.scopes.len()).rev() {
-            if self.scopes[i].contains_key(&name.value.to_string()) {
+            if self.scopes[i].contains_key(&name.atom()) {
                 self.interpreter.resolve(expr, self.scopes.len() - 1 - i);
+                if let Some(known) = self.known(name) {
+                    match expr {
+                        Expr::Variable(_) => self.known[known].reads.push(expr.to_hash()),
+                        _ => self.known[known].assigned = true,
+                    }
+                }
                 return;
             }
         }
     }
+
+    /// The variable `name` refers to, if its value is known.
+    fn known(&self, name: &Token) -> Option<usize> {
+        let scope = self
+            .scopes
+            .iter()
+            .rposition(|scope| scope.contains_key(&name.atom()))?;
+        self.known_scopes[scope].get(&name.atom()).copied()
+    }
+
+    fn define_known(&mut self, name: &Token, value: KnownValue) {
+        self.known.push(Known {
+            value,
+            assigned: false,
+            reads: Vec::new(),
+        });
+        self.known_scopes
+            .last_mut()
+            .unwrap()
+            .insert(name.atom(), self.known.len() - 1);
+    }
 }
 
 impl<'a> ExprVisitor for Resolver<'a> {
     type Output = Result<(), RuntimeError>;
 
     fn visit_assign_expr(&mut self, expr: &AssignExpr) -> Self::Output {
+        self.check_assignable(&expr.name)?;
         self.resolve_expr(&expr.value)?;
         self.resolve_local(&Expr::Assign(Box::new(expr.to_owned())), &expr.name);
         Ok(())
@@ -133,15 +354,47 @@ impl<'a> ExprVisitor for Resolver<'a> {
         Ok(())
     }
 
+    fn visit_destructure_expr(&mut self, expr: &DestructureExpr) -> Self::Output {
+        for name in expr.pattern.names() {
+            self.check_assignable(name)?;
+        }
+        self.resolve_expr(&expr.value)?;
+        for name in expr.pattern.names() {
+            self.resolve_local(&Expr::Variable(VariableExpr::new(name.clone())), name);
+            if let Some(known) = self.known(name) {
+                self.known[known].assigned = true;
+            }
+        }
+        Ok(())
+    }
+
     fn visit_get_expr(&mut self, expr: &GetExpr) -> Self::Output {
-        self.resolve_expr(&expr.object)
+        // Generated code can chain thousands of properties, as in `a.b.c.d`, so walk down to
+        // the start of the chain in a loop rather than by recursion. Only that start has
+        // anything to resolve.
+        let mut object = &expr.object;
+        while let Expr::Get(get) = object {
+            object = &get.object;
+        }
+        self.resolve_expr(object)
     }
 
     fn visit_grouping_expr(&mut self, expr: &GroupingExpr) -> Self::Output {
         self.resolve_expr(&expr.expression)
     }
 
-    fn visit_lambda_expr(&mut self, expr: &LambdaExpr) -> Self::Output {
+    fn visit_index_expr(&mut self, expr: &IndexExpr) -> Self::Output {
+        self.resolve_expr(&expr.object)?;
+        self.resolve_expr(&expr.index)
+    }
+
+    fn visit_index_set_expr(&mut self, expr: &IndexSetExpr) -> Self::Output {
+        self.resolve_expr(&expr.value)?;
+        self.resolve_expr(&expr.object)?;
+        self.resolve_expr(&expr.index)
+    }
+
+    fn visit_lambda_expr(&mut self, expr: &Rc<LambdaExpr>) -> Self::Output {
         let enclosing_function = self.current_function;
         self.current_function = FunctionType::Function;
         self.begin_scope();
@@ -156,32 +409,70 @@ impl<'a> ExprVisitor for Resolver<'a> {
         Ok(())
     }
 
-    fn visit_literal_expr(&self, _expr: &LiteralExpr) -> Self::Output {
+    fn visit_list_expr(&mut self, expr: &ListExpr) -> Self::Output {
+        for element in &expr.elements {
+            self.resolve_expr(element)?;
+        }
+
+        Ok(())
+    }
+
+    fn visit_literal_expr(&mut self, _expr: &LiteralExpr) -> Self::Output {
         Ok(())
     }
 
     fn visit_logical_expr(&mut self, expr: &LogicalExpr) -> Self::Output {
+        self.resolve_expr(&expr.left)?;
         self.resolve_expr(&expr.right)
     }
 
+    fn visit_map_expr(&mut self, expr: &MapExpr) -> Self::Output {
+        for (key, value) in &expr.entries {
+            self.resolve_expr(key)?;
+            self.resolve_expr(value)?;
+        }
+
+        Ok(())
+    }
+
     fn visit_set_expr(&mut self, expr: &SetExpr) -> Self::Output {
         self.resolve_expr(&expr.value)?;
         self.resolve_expr(&expr.object)
     }
 
+    fn visit_slice_expr(&mut self, expr: &SliceExpr) -> Self::Output {
+        self.resolve_expr(&expr.object)?;
+        if let Some(start) = &expr.start {
+            self.resolve_expr(start)?;
+        }
+        if let Some(end) = &expr.end {
+            self.resolve_expr(end)?;
+        }
+        Ok(())
+    }
+
     fn visit_super_expr(&mut self, expr: &SuperExpr) -> Self::Output {
         if self.current_class == ClassType::None {
             return Err(RuntimeError::new(
                 expr.keyword.clone(),
+                RuntimeErrorKind::InvalidContext,
                 "Can't use 'super' outside of a class.",
             ));
         }
         if self.current_class != ClassType::Subclass {
             return Err(RuntimeError::new(
                 expr.keyword.clone(),
+                RuntimeErrorKind::InvalidContext,
                 "Can't use 'super' in a class with no superclass.",
             ));
         }
+        if self.in_static_method {
+            return Err(RuntimeError::new(
+                expr.keyword.clone(),
+                RuntimeErrorKind::InvalidContext,
+                "Can't use 'super' in a static method.",
+            ));
+        }
 
         self.resolve_local(&Expr::Super(expr.to_owned()), &expr.keyword);
 
@@ -192,9 +483,17 @@ impl<'a> ExprVisitor for Resolver<'a> {
         if self.current_class == ClassType::None {
             return Err(RuntimeError::new(
                 expr.keyword.clone(),
+                RuntimeErrorKind::InvalidContext,
                 "Can't use 'this' outside of a class.",
             ));
         }
+        if self.in_static_method {
+            return Err(RuntimeError::new(
+                expr.keyword.clone(),
+                RuntimeErrorKind::InvalidContext,
+                "Can't use 'this' in a static method.",
+            ));
+        }
         self.resolve_local(&Expr::This(expr.to_owned()), &expr.keyword);
         Ok(())
     }
@@ -210,14 +509,15 @@ impl<'a> ExprVisitor for Resolver<'a> {
     }
 
     fn visit_variable_expr(&mut self, expr: &VariableExpr) -> Self::Output {
-        if let Some(scope) = self.scopes.last() {
-            if let Some(false) = scope.get(&expr.name.value.to_string()) {
-                // TODO: fix block2.lox test
-                return Err(RuntimeError::new(
-                    expr.name.clone(),
-                    "Can't read local variable in its own initializer.",
-                ));
-            }
+        if let Some(scope) = self.scopes.last()
+            && let Some(Binding::Declared) = scope.get(&expr.name.atom())
+        {
+            // TODO: fix block2.lox test
+            return Err(RuntimeError::new(
+                expr.name.clone(),
+                RuntimeErrorKind::InvalidContext,
+                "Can't read local variable in its own initializer.",
+            ));
         }
         self.resolve_local(&Expr::Variable(expr.to_owned()), &expr.name);
         Ok(())
@@ -234,17 +534,20 @@ impl<'a> StmtVisitor for Resolver<'a> {
         Ok(())
     }
 
-    fn visit_break_stmt(&self) -> Self::Output {
+    fn visit_break_stmt(&mut self, _stmt: &BreakStmt) -> Self::Output {
         Ok(())
     }
 
-    fn visit_continue_stmt(&self) -> Self::Output {
+    fn visit_continue_stmt(&mut self, _stmt: &ContinueStmt) -> Self::Output {
         Ok(())
     }
 
     fn visit_class_stmt(&mut self, stmt: &ClassStmt) -> Self::Output {
         let enclosing_class = self.current_class;
+        let enclosing_static_method = self.in_static_method;
         self.current_class = ClassType::Class;
+        // A class declared inside a static method has its own `this`.
+        self.in_static_method = false;
 
         self.declare(&stmt.name)?;
         self.define(&stmt.name);
@@ -253,6 +556,7 @@ impl<'a> StmtVisitor for Resolver<'a> {
             if stmt.name.value == superclass.name.value {
                 return Err(RuntimeError::new(
                     superclass.name.clone(),
+                    RuntimeErrorKind::InvalidContext,
                     "A class cannot inherit from itself.",
                 ));
             }
@@ -264,13 +568,19 @@ impl<'a> StmtVisitor for Resolver<'a> {
             self.begin_scope();
             self.scopes
                 .last_mut()
-                .and_then(|scope| scope.insert("super".to_string(), true));
+                .and_then(|scope| scope.insert("super".into(), Binding::Defined));
         }
 
         self.begin_scope();
         self.scopes
             .last_mut()
-            .and_then(|scope| scope.insert("this".to_string(), true));
+            .and_then(|scope| scope.insert("this".into(), Binding::Defined));
+        // Field defaults are evaluated with `this` bound to the new instance, like methods.
+        for field in stmt.fields.iter() {
+            if let Some(initializer) = &field.initializer {
+                self.resolve_expr(initializer)?;
+            }
+        }
         for method in &stmt.methods {
             self.resolve_function(method)?;
         }
@@ -281,9 +591,17 @@ impl<'a> StmtVisitor for Resolver<'a> {
         self.end_scope();
 
         self.begin_scope();
+        self.in_static_method = true;
         for method in &stmt.static_methods {
             self.resolve_function(method)?;
         }
+        let enclosing_function = self.current_function;
+        self.current_function = FunctionType::None;
+        for block in &stmt.static_blocks {
+            self.visit_block_stmt(block)?;
+        }
+        self.current_function = enclosing_function;
+        self.in_static_method = enclosing_static_method;
         self.end_scope();
 
         if stmt.superclass.is_some() {
@@ -293,25 +611,86 @@ impl<'a> StmtVisitor for Resolver<'a> {
         Ok(())
     }
 
+    fn visit_destructure_stmt(&mut self, stmt: &DestructureStmt) -> Self::Output {
+        for name in stmt.pattern.names() {
+            self.declare(name)?;
+        }
+        self.resolve_expr(&stmt.initializer)?;
+        for name in stmt.pattern.names() {
+            if stmt.is_const {
+                self.define_const(name);
+            } else {
+                self.define(name);
+            }
+        }
+        Ok(())
+    }
+
+    fn visit_export_stmt(&mut self, stmt: &ExportStmt) -> Self::Output {
+        self.check_top_level(&stmt.keyword, "Can only export top-level declarations.")?;
+        self.resolve_stmt(&stmt.declaration)
+    }
+
     fn visit_expression_stmt(&mut self, stmt: &ExpressionStmt) -> Self::Output {
         self.resolve_expr(&stmt.expr)
     }
 
-    fn visit_function_stmt(&mut self, stmt: &FunctionStmt) -> Self::Output {
+    fn visit_for_stmt(&mut self, stmt: &ForStmt) -> Self::Output {
+        // The variables declared by the initializer live in a scope around the body's.
+        self.begin_scope();
+        if let Some(initializer) = &stmt.initializer {
+            self.resolve_stmt(initializer)?;
+        }
+        self.resolve_expr(&stmt.condition)?;
+        if let Some(increment) = &stmt.increment {
+            self.resolve_expr(increment)?;
+        }
+        self.visit_block_stmt(&stmt.body)?;
+        self.end_scope();
+        Ok(())
+    }
+
+    fn visit_for_in_stmt(&mut self, stmt: &ForInStmt) -> Self::Output {
+        self.resolve_expr(&stmt.iterable)?;
+        // The loop variable lives in the same scope as the body's declarations.
+        self.begin_scope();
         self.declare(&stmt.name)?;
         self.define(&stmt.name);
-        self.resolve_function(stmt)
+        self.resolve_stmts(&stmt.body.statements)?;
+        self.end_scope();
+        Ok(())
+    }
+
+    fn visit_function_stmt(&mut self, stmt: &Rc<FunctionStmt>) -> Self::Output {
+        self.declare(&stmt.name)?;
+        self.define(&stmt.name);
+        self.resolve_function(stmt)?;
+        // In a session, a later input can assign to a global function.
+        if self.interpreter.opt_level >= OptLevel::Inline
+            && !(self.in_session && self.scopes.len() == 1)
+            && let Some(function) = InlineFunction::of(stmt, &self.interpreter.locals)
+        {
+            self.define_known(&stmt.name, KnownValue::Function(Rc::new(function)));
+        }
+        Ok(())
     }
 
     fn visit_if_stmt(&mut self, stmt: &IfStmt) -> Self::Output {
         self.resolve_expr(&stmt.condition)?;
         self.visit_block_stmt(&stmt.then_branch)?;
         if let Some(else_branch) = &stmt.else_branch {
-            self.visit_block_stmt(else_branch)?;
+            self.resolve_stmt(else_branch)?;
         }
         Ok(())
     }
 
+    fn visit_import_stmt(&mut self, stmt: &ImportStmt) -> Self::Output {
+        self.check_top_level(&stmt.keyword, "Can only import at the top level.")?;
+        self.declare(&stmt.name)?;
+        self.define(&stmt.name);
+        Ok(())
+    }
+
     fn visit_print_stmt(&mut self, stmt: &PrintStmt) -> Self::Output {
         self.resolve_expr(&stmt.expr)
     }
@@ -320,6 +699,7 @@ impl<'a> StmtVisitor for Resolver<'a> {
         if self.current_function == FunctionType::None {
             return Err(RuntimeError::new(
                 stmt.keyword.clone(),
+                RuntimeErrorKind::InvalidContext,
                 "Cannot return from top-level code.",
             ));
         }
@@ -327,6 +707,7 @@ impl<'a> StmtVisitor for Resolver<'a> {
             if self.current_function == FunctionType::Initializer {
                 return Err(RuntimeError::new(
                     stmt.keyword.clone(),
+                    RuntimeErrorKind::InvalidContext,
                     "Cannot return a value from an initializer.",
                 ));
             }
@@ -335,12 +716,56 @@ impl<'a> StmtVisitor for Resolver<'a> {
         Ok(())
     }
 
+    fn visit_switch_stmt(&mut self, stmt: &SwitchStmt) -> Self::Output {
+        self.resolve_expr(&stmt.subject)?;
+        for (values, body) in &stmt.cases {
+            for value in values {
+                self.resolve_expr(value)?;
+            }
+            self.visit_block_stmt(body)?;
+        }
+        if let Some(default) = &stmt.default {
+            self.visit_block_stmt(default)?;
+        }
+        Ok(())
+    }
+
+    fn visit_throw_stmt(&mut self, stmt: &ThrowStmt) -> Self::Output {
+        self.resolve_expr(&stmt.value)
+    }
+
+    fn visit_try_stmt(&mut self, stmt: &TryStmt) -> Self::Output {
+        self.visit_block_stmt(&stmt.body)?;
+        if let Some((name, body)) = &stmt.catch {
+            // The exception lives in the same scope as the catch body's declarations.
+            self.begin_scope();
+            self.declare(name)?;
+            self.define(name);
+            self.resolve_stmts(&body.statements)?;
+            self.end_scope();
+        }
+        if let Some(finally) = &stmt.finally {
+            self.visit_block_stmt(finally)?;
+        }
+        Ok(())
+    }
+
     fn visit_var_stmt(&mut self, stmt: &VarStmt) -> Self::Output {
         self.declare(&stmt.name)?;
         if let Some(initializer) = &stmt.initializer {
             self.resolve_expr(initializer)?;
         }
-        self.define(&stmt.name);
+        if stmt.is_const {
+            self.define_const(&stmt.name);
+        } else {
+            self.define(&stmt.name);
+        }
+        // Globals can be assigned to by later programs, so only locals are propagated.
+        if self.scopes.len() > 1
+            && let Some(Expr::Literal(value)) = &stmt.initializer
+        {
+            self.define_known(&stmt.name, KnownValue::Literal(value.clone()));
+        }
         Ok(())
     }
 
@@ -349,3 +774,300 @@ impl<'a> StmtVisitor for Resolver<'a> {
         self.visit_block_stmt(&stmt.body)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, io, rc::Rc};
+
+    use super::*;
+    use crate::{parser::Parser, scanner::Scanner};
+
+    fn resolve(source: &str) -> Result<(), String> {
+        let statements = Parser::new(Scanner::new(source).collect()).parse().unwrap();
+        let mut interpreter = Interpreter::new(Rc::new(RefCell::new(io::sink())));
+        Resolver::new(&mut interpreter)
+            .resolve_stmts(&statements)
+            .map_err(|e| e.to_string())
+    }
+
+    fn assert_error(source: &str, message: &str) {
+        let error = resolve(source).unwrap_err();
+        assert!(error.contains(message), "{error}");
+    }
+
+    #[test]
+    fn test_return_in_initializer() {
+        assert!(resolve("class A { init() { return; } }").is_ok());
+        assert!(resolve("class A { init() { var f = fun () { return 1; }; } }").is_ok());
+        assert!(resolve("class A { init() { fun f() { return 1; } } }").is_ok());
+        assert_error(
+            "class A { init() { return 1; } }",
+            "Cannot return a value from an initializer.",
+        );
+        assert_error(
+            "class A { init() { if (true) { return 1; } } }",
+            "Cannot return a value from an initializer.",
+        );
+    }
+
+    #[test]
+    fn test_this_and_super_in_static_method() {
+        assert_error(
+            "class A { class f() { return this; } }",
+            "Can't use 'this' in a static method.",
+        );
+        assert_error(
+            "class A { class f() { return fun () { return this; }; } }",
+            "Can't use 'this' in a static method.",
+        );
+        assert_error(
+            "class A { f() {} } class B < A { class f() { super.f(); } }",
+            "Can't use 'super' in a static method.",
+        );
+        assert!(resolve("class A { class f() { class B { g() { return this; } } } }").is_ok());
+        assert!(resolve("class A { class f() {} g() { return this; } }").is_ok());
+        assert_error(
+            "class A { class { print(this); } }",
+            "Can't use 'this' in a static method.",
+        );
+        assert_error(
+            "fun f() { class A { class { return; } } }",
+            "Cannot return from top-level code.",
+        );
+        assert!(resolve("class A { class { A.count = 0; } }").is_ok());
+    }
+
+    #[test]
+    fn test_const() {
+        assert!(resolve("const a = 1; print(a);").is_ok());
+        assert!(resolve("const a = 1; { var a = 2; a = 3; }").is_ok());
+        assert!(resolve("const a = [1]; a[0] = 2;").is_ok());
+        assert_error("const a = 1; a = 2;", "Can't assign to a constant.");
+        assert_error("{ const a = 1; a = 2; }", "Can't assign to a constant.");
+        assert_error(
+            "const a = 1; fun f() { a = 2; }",
+            "Can't assign to a constant.",
+        );
+        assert_error(
+            "{ const a = 1; var f = fun () { a = 2; }; }",
+            "Can't assign to a constant.",
+        );
+        assert_error(
+            "const [a, b] = [1, 2]; var c; [c, b] = [3, 4];",
+            "Can't assign to a constant.",
+        );
+    }
+
+    /// What each function of `source` returns once optimized at `opt_level`: the value of a
+    /// literal, or the kind of expression.
+    #[test]
+    fn test_resolve_all() {
+        let source = "
+            return 1;
+            class A { init() { fun f() { return this; } return 1; } }
+            fun g() { var x; { var x; var x; } }
+            print(1);
+        ";
+        let statements = Parser::new(Scanner::new(source).collect()).parse().unwrap();
+        let mut interpreter = Interpreter::new(Rc::new(RefCell::new(io::sink())));
+        let errors = Resolver::new(&mut interpreter)
+            .resolve_all(&statements)
+            .unwrap_err();
+        let lines = errors
+            .iter()
+            .filter_map(|error| error.token().map(|token| token.line))
+            .collect::<Vec<_>>();
+        assert_eq!(lines, [2, 3, 4]);
+        assert!(
+            Resolver::new(&mut interpreter)
+                .resolve_all(&statements[3..])
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_resolve_input() {
+        let mut interpreter =
+            Interpreter::new(Rc::new(RefCell::new(io::sink()))).with_opt_level(OptLevel::Inline);
+        let mut resolver = Resolver::new(&mut interpreter);
+        let mut resolve = |source: &str| {
+            let mut statements = Parser::new(Scanner::new(source).collect()).parse().unwrap();
+            let result = resolver.resolve_input(&statements);
+            resolver.optimize(&mut statements);
+            result.map_err(|e| e.to_string())
+        };
+        assert!(resolve("var a = 1; fun f() { return a; }").is_ok());
+        assert!(resolve("var a = 2; fun f() { return 2; } print(f());").is_ok());
+        assert!(resolve("var b; var b;").is_err());
+        // The error leaves neither `b` declared nor the function's scope open.
+        assert!(resolve("var b; fun g() { return this; }").is_err());
+        assert!(resolve("var b = 3; { var c = 4; print(b + c); }").is_ok());
+        assert!(resolve("const d = 1;").is_ok());
+        assert!(resolve("d = 2;").is_err());
+        assert!(resolve("var d = 2; d = 3;").is_ok());
+    }
+
+    #[test]
+    fn test_long_property_chain() {
+        // The program `genlox` writes as `stress_property_chain`, with a longer chain.
+        let length = 20_000;
+        let source = format!(
+            "class Node {{ init(value, next) {{ this.value = value; this.next = next; }} }}
+            var list = nil;
+            for (var i = 0; i < {length}; i = i + 1) list = Node(i, list);
+            print(list{}.value);",
+            ".next".repeat(length - 1)
+        );
+        assert!(resolve(&source).is_ok());
+    }
+
+    fn optimized(source: &str, opt_level: OptLevel) -> Vec<Stmt> {
+        let mut statements = Parser::new(Scanner::new(source).collect()).parse().unwrap();
+        let mut interpreter =
+            Interpreter::new(Rc::new(RefCell::new(io::sink()))).with_opt_level(opt_level);
+        let mut resolver = Resolver::new(&mut interpreter);
+        resolver.resolve_stmts(&statements).unwrap();
+        resolver.optimize(&mut statements);
+        statements
+    }
+
+    fn returned_at(source: &str, opt_level: OptLevel) -> Vec<String> {
+        optimized(source, opt_level)
+            .iter()
+            .filter_map(|stmt| match stmt {
+                Stmt::Function(function) => match function.body.statements.last() {
+                    Some(Stmt::Return(ReturnStmt {
+                        value: Some(value), ..
+                    })) => Some(value.clone()),
+                    _ => None,
+                },
+                _ => None,
+            })
+            .map(|value| match value {
+                Expr::Literal(literal) => literal.value.to_string(),
+                Expr::Variable(_) => "read".to_string(),
+                Expr::Call(_) => "call".to_string(),
+                Expr::Binary(_) => "binary".to_string(),
+                _ => "other".to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_propagate_constants() {
+        let returned = |source: &str| returned_at(source, OptLevel::Constants);
+        assert_eq!(
+            returned("fun f() { var a = 1; return a; } fun g() { const b = \"b\"; return b; }"),
+            ["1", "b"]
+        );
+        assert_eq!(
+            returned("fun f() { var a = 1; var g = fun () { a = 2; }; return a; }"),
+            ["read"]
+        );
+        assert_eq!(
+            returned("fun f() { var a = 1; var b; [a, b] = [2, 3]; return a; }"),
+            ["read"]
+        );
+        assert_eq!(returned("fun f() { var a = [1]; return a; }"), ["read"]);
+        assert_eq!(returned("var a = 1; fun f() { return a; }"), ["read"]);
+        assert_eq!(
+            returned_at("fun f() { var a = 1; return a; }", OptLevel::None),
+            ["read"]
+        );
+    }
+
+    #[test]
+    fn test_inline_calls() {
+        let returned = |source: &str| returned_at(source, OptLevel::Inline);
+        let square = "fun square(x) = x * x;";
+        assert_eq!(
+            returned(&format!("{square} fun f(y) {{ return square(y); }}")),
+            ["binary", "binary"]
+        );
+        assert_eq!(
+            returned("fun inc(x) = x + 1; fun f(y) { return inc(y * 2); }"),
+            ["binary", "binary"]
+        );
+        // Arguments that would be evaluated twice or have side effects.
+        assert_eq!(
+            returned(&format!("{square} fun f(y) {{ return square(y + 1); }}")),
+            ["binary", "call"]
+        );
+        assert_eq!(
+            returned(&format!("{square} fun f(y) {{ return square(y()); }}")),
+            ["binary", "call"]
+        );
+        // Bodies reading more than their parameters and globals.
+        assert_eq!(
+            returned("var k = 2; fun scale(x) = x * k; fun f(y) { return scale(y); }"),
+            ["binary", "call"]
+        );
+        assert_eq!(
+            returned("fun first(a, b) = a; fun f() { return first(1, 2); }"),
+            ["read", "call"]
+        );
+        assert_eq!(
+            returned(&format!(
+                "{square} fun f(y) {{ return square(y); }} square = nil;"
+            )),
+            ["binary", "call"]
+        );
+        assert_eq!(
+            returned_at(
+                &format!("{square} fun f(y) {{ return square(y); }}"),
+                OptLevel::Constants
+            ),
+            ["binary", "call"]
+        );
+    }
+
+    #[test]
+    fn test_prune_dead_code() {
+        let source = "
+            fun f(x) {
+                if (x) return 1;
+                return 2;
+                print(3);
+            }
+            fun g() {
+                const debug = false;
+                if (debug) {
+                    print(4);
+                } else if (true) {
+                    return 5;
+                }
+                return 6;
+            }
+            while (true) {
+                break;
+                print(7);
+            }
+            if (false) {
+                print(8);
+            }
+        ";
+        let lengths = |statements: &[Stmt]| -> Vec<usize> {
+            statements
+                .iter()
+                .map(|stmt| match stmt {
+                    Stmt::Function(function) => function.body.statements.len(),
+                    Stmt::While(stmt) => stmt.body.statements.len(),
+                    Stmt::Block(block) => block.statements.len(),
+                    _ => usize::MAX,
+                })
+                .collect()
+        };
+        assert_eq!(
+            lengths(&optimized(source, OptLevel::None)),
+            [3, 3, 2, usize::MAX]
+        );
+
+        let statements = optimized(source, OptLevel::Constants);
+        assert_eq!(lengths(&statements), [2, 3, 1, 0]);
+        let Stmt::Function(g) = &statements[1] else {
+            panic!("Expected a function");
+        };
+        assert!(matches!(&g.body.statements[1], Stmt::Block(block)
+            if matches!(block.statements[..], [Stmt::Return(_)])));
+    }
+}
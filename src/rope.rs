@@ -0,0 +1,127 @@
+use std::{cell::OnceCell, fmt, mem, rc::Rc};
+
+use crate::{atom::Atom, object::Object};
+
+/// Concatenations producing at least this many bytes build a rope instead of copying both
+/// operands, so that a string grown piece by piece isn't copied in full at every step.
+pub const ROPE_THRESHOLD: usize = 1024;
+
+/// A string built by concatenation. Its pieces are only joined the first time its contents
+/// are needed, and the result is kept for the next time.
+pub struct Rope {
+    pieces: Vec<Strand>,
+    len: usize,
+    flat: OnceCell<Atom>,
+}
+
+/// A piece of a rope.
+#[derive(Clone)]
+pub enum Strand {
+    Flat(Atom),
+    Rope(Rc<Rope>),
+}
+
+impl Strand {
+    /// The strand of a string or a rope.
+    pub fn of(object: &Object) -> Option<Self> {
+        match object {
+            Object::String(string) => Some(Strand::Flat(string.clone())),
+            Object::Rope(rope) => Some(Strand::Rope(rope.clone())),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> &str {
+        match self {
+            Strand::Flat(string) => string,
+            Strand::Rope(rope) => rope.flat(),
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Strand::Flat(string) => string.len(),
+            Strand::Rope(rope) => rope.len,
+        }
+    }
+}
+
+impl Rope {
+    /// Joins `left` and `right`, as a rope if the result is long enough.
+    pub fn concat(left: Strand, right: Strand) -> Object {
+        let len = left.len() + right.len();
+        if len < ROPE_THRESHOLD {
+            return Object::String(format!("{}{}", left.as_str(), right.as_str()).into());
+        }
+        Object::Rope(Rc::new(Rope {
+            pieces: vec![left, right],
+            len,
+            flat: OnceCell::new(),
+        }))
+    }
+
+    /// The joined contents of the rope.
+    pub fn flat(&self) -> &Atom {
+        self.flat.get_or_init(|| {
+            let mut flat = String::with_capacity(self.len);
+            // Ropes grown by appending nest deeply on the left, so walk them with a stack of
+            // our own rather than by recursion.
+            let mut stack: Vec<&Strand> = self.pieces.iter().rev().collect();
+            while let Some(strand) = stack.pop() {
+                match strand {
+                    Strand::Flat(string) => flat.push_str(string),
+                    Strand::Rope(rope) => match rope.flat.get() {
+                        Some(string) => flat.push_str(string),
+                        None => stack.extend(rope.pieces.iter().rev()),
+                    },
+                }
+            }
+            flat.into()
+        })
+    }
+}
+
+impl Drop for Rope {
+    // Dropping a deeply nested rope recursively could overflow the stack.
+    fn drop(&mut self) {
+        let mut pieces = mem::take(&mut self.pieces);
+        while let Some(strand) = pieces.pop() {
+            if let Strand::Rope(rope) = strand
+                && let Ok(mut rope) = Rc::try_unwrap(rope)
+            {
+                pieces.append(&mut rope.pieces);
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Rope {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Rope")
+            .field("len", &self.len)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_concat() {
+        let short = Rope::concat(Strand::Flat("ab".into()), Strand::Flat("cd".into()));
+        assert!(matches!(short, Object::String(_)));
+        assert_eq!(short.to_string(), "abcd");
+
+        let line: Atom = "x".repeat(10).into();
+        let mut log = Object::String("".into());
+        for _ in 0..100_000 {
+            log = Rope::concat(Strand::of(&log).unwrap(), Strand::Flat(line.clone()));
+        }
+        let Object::Rope(rope) = &log else {
+            panic!("Expected a rope");
+        };
+        assert_eq!(rope.flat().len(), 1_000_000);
+        assert_eq!(log, Object::String(rope.flat().clone()));
+    }
+}
@@ -1,29 +1,169 @@
-use std::{iter::Peekable, str::Chars};
+use std::{cell::RefCell, iter::Peekable, rc::Rc, str::Chars};
 
-use crate::token::{Token, TokenIdentity, TokenValue};
+use crate::{
+    atom::AtomTable,
+    parser::Dialect,
+    token::{Span, Token, TokenIdentity, TokenValue},
+};
 
-pub struct Scanner<'a> {
+/// The keywords of the book's Lox. The others are identifiers in [`Dialect::Lox`].
+const LOX_KEYWORDS: [&str; 16] = [
+    "and", "class", "else", "false", "for", "fun", "if", "nil", "or", "print", "return", "super",
+    "this", "true", "var", "while",
+];
+
+/// The characters of a source, along with the byte offset of the next one.
+struct SourceChars<'a> {
     chars: Peekable<Chars<'a>>,
+    offset: usize,
+}
+
+impl SourceChars<'_> {
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        self.offset += c.len_utf8();
+        Some(c)
+    }
+
+    fn next_if(&mut self, func: impl FnOnce(&char) -> bool) -> Option<char> {
+        let c = self.chars.next_if(func)?;
+        self.offset += c.len_utf8();
+        Some(c)
+    }
+
+    fn next_if_eq(&mut self, expected: &char) -> Option<char> {
+        self.next_if(|c| c == expected)
+    }
+
+    /// Whether the upcoming characters are `expected`, without consuming them.
+    fn lookahead(&self, expected: &str) -> bool {
+        let mut chars = self.chars.clone();
+        expected.chars().all(|c| chars.next() == Some(c))
+    }
+}
+
+pub struct Scanner<'a> {
+    chars: SourceChars<'a>,
     line: usize,
     column: usize,
     is_finish: bool,
+    atoms: Rc<RefCell<AtomTable>>,
+    case_insensitive_keywords: bool,
+    raw_identifiers: bool,
+    dialect: Dialect,
+    /// Whether only comments have been scanned so far, so that `//!` lines are metadata.
+    in_header: bool,
 }
 
 impl<'a> Scanner<'a> {
     pub fn new(source: &'a str) -> Self {
         Scanner {
-            chars: source.chars().peekable(),
+            chars: SourceChars {
+                chars: source.chars().peekable(),
+                offset: 0,
+            },
             line: 1,
             column: 1,
             is_finish: false,
+            atoms: Rc::new(RefCell::new(AtomTable::new())),
+            case_insensitive_keywords: false,
+            raw_identifiers: false,
+            dialect: Dialect::default(),
+            in_header: true,
         }
     }
+
+    /// Interns identifiers and string literals into `atoms` instead of a table private to
+    /// this scanner, so that several sources can share it.
+    pub fn with_atoms(mut self, atoms: Rc<RefCell<AtomTable>>) -> Self {
+        self.atoms = atoms;
+        self
+    }
+
+    /// Numbers lines from `line` rather than 1, so that the inputs of a REPL session can go
+    /// on from one another.
+    pub fn with_first_line(mut self, line: usize) -> Self {
+        self.line = line;
+        self
+    }
+
+    /// Matches keywords regardless of case, so that `IF` and `If` both scan as `if`.
+    /// Identifiers keep the case they're written in.
+    pub fn with_case_insensitive_keywords(mut self, case_insensitive_keywords: bool) -> Self {
+        self.case_insensitive_keywords = case_insensitive_keywords;
+        self
+    }
+
+    /// Scans a name written with a leading backslash, such as `\class`, as an identifier even
+    /// when it's a keyword.
+    pub fn with_raw_identifiers(mut self, raw_identifiers: bool) -> Self {
+        self.raw_identifiers = raw_identifiers;
+        self
+    }
+
+    /// Reserves the keywords of `dialect`, so that in [`Dialect::Lox`] names such as `break`
+    /// or `in` are identifiers.
+    pub fn with_dialect(mut self, dialect: Dialect) -> Self {
+        self.dialect = dialect;
+        self
+    }
 }
 
-impl Iterator for Scanner<'_> {
-    type Item = Token;
+/// The message of the error token for a string that runs to the end of the input.
+pub const UNTERMINATED_STRING: &str = "Unterminated string.";
 
-    fn next(&mut self) -> Option<Self::Item> {
+impl Scanner<'_> {
+    /// A token for text that can't be scanned, which the parser reports with `message`.
+    fn error(&self, message: &str, line: usize, column: usize) -> Token {
+        Token::new(
+            TokenIdentity::Error,
+            TokenValue::String(self.atoms.borrow_mut().intern(message)),
+            line,
+            column,
+        )
+    }
+
+    /// The metadata entry in the text of a comment, such as `! name: mytool`, when the comment
+    /// is part of the header.
+    fn meta(&self, text: &str) -> Option<Token> {
+        let (key, value) = text.strip_prefix('!')?.split_once(':')?;
+        let key = key.trim();
+        if !self.in_header || key.is_empty() {
+            return None;
+        }
+        let mut atoms = self.atoms.borrow_mut();
+        Some(Token::new(
+            TokenIdentity::Meta,
+            TokenValue::Meta(Box::new((atoms.intern(key), atoms.intern(value.trim())))),
+            self.line,
+            self.column - 2,
+        ))
+    }
+
+    /// Reads the rest of a name starting with `value`.
+    fn identifier(&mut self, mut value: String) -> String {
+        while let Some(c) = self.chars.next_if(|c| c.is_alphabetic() || *c == '_') {
+            value.push(c);
+        }
+        self.column += value.chars().count();
+        value
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(c) = self
+            .chars
+            .next_if(|c| matches!(c, ' ' | '\r' | '\t' | '\n'))
+        {
+            if c == '\n' {
+                self.line += 1;
+                self.column = 1;
+            } else {
+                self.column += 1;
+            }
+        }
+    }
+
+    fn scan_token(&mut self) -> Option<Token> {
         match self.chars.next() {
             Some(c) => match c {
                 '(' => {
@@ -62,24 +202,63 @@ impl Iterator for Scanner<'_> {
                         self.column - 1,
                     ))
                 }
-                ',' => {
+                '[' => {
                     self.column += 1;
                     Some(Token::new(
-                        TokenIdentity::Comma,
+                        TokenIdentity::LeftBracket,
                         TokenValue::Nil,
                         self.line,
                         self.column - 1,
                     ))
                 }
-                '.' => {
+                ']' => {
+                    self.column += 1;
+                    Some(Token::new(
+                        TokenIdentity::RightBracket,
+                        TokenValue::Nil,
+                        self.line,
+                        self.column - 1,
+                    ))
+                }
+                '|' => {
                     self.column += 1;
                     Some(Token::new(
-                        TokenIdentity::Dot,
+                        TokenIdentity::Pipe,
                         TokenValue::Nil,
                         self.line,
                         self.column - 1,
                     ))
                 }
+                ',' => {
+                    self.column += 1;
+                    Some(Token::new(
+                        TokenIdentity::Comma,
+                        TokenValue::Nil,
+                        self.line,
+                        self.column - 1,
+                    ))
+                }
+                '.' => {
+                    self.column += 1;
+                    if self.chars.lookahead("..") {
+                        self.chars.next();
+                        self.chars.next();
+                        self.column += 2;
+                        Some(Token::new(
+                            TokenIdentity::DotDotDot,
+                            TokenValue::Nil,
+                            self.line,
+                            self.column - 3,
+                        ))
+                    } else {
+                        Some(Token::new(
+                            TokenIdentity::Dot,
+                            TokenValue::Nil,
+                            self.line,
+                            self.column - 1,
+                        ))
+                    }
+                }
                 '-' => {
                     self.column += 1;
                     Some(Token::new(
@@ -127,12 +306,22 @@ impl Iterator for Scanner<'_> {
                 }
                 '?' => {
                     self.column += 1;
-                    Some(Token::new(
-                        TokenIdentity::Question,
-                        TokenValue::Nil,
-                        self.line,
-                        self.column - 1,
-                    ))
+                    if self.chars.next_if_eq(&'?').is_some() {
+                        self.column += 1;
+                        Some(Token::new(
+                            TokenIdentity::QuestionQuestion,
+                            TokenValue::Nil,
+                            self.line,
+                            self.column - 2,
+                        ))
+                    } else {
+                        Some(Token::new(
+                            TokenIdentity::Question,
+                            TokenValue::Nil,
+                            self.line,
+                            self.column - 1,
+                        ))
+                    }
                 }
                 '!' => {
                     self.column += 1;
@@ -218,9 +407,12 @@ impl Iterator for Scanner<'_> {
                         while let Some(c) = self.chars.next_if(|c| *c != '\n') {
                             text.push(c);
                         }
+                        if let Some(meta) = self.meta(&text) {
+                            return Some(meta);
+                        }
                         Some(Token::new(
                             TokenIdentity::Comment,
-                            TokenValue::String(text),
+                            TokenValue::String(text.into()),
                             self.line,
                             self.column - 2,
                         ))
@@ -233,33 +425,29 @@ impl Iterator for Scanner<'_> {
                         ))
                     }
                 }
-                ' ' | '\r' | '\t' => {
-                    self.column += 1;
-                    self.next()
-                }
-                '\n' => {
-                    self.line += 1;
-                    self.column = 1;
-                    self.next()
-                }
                 '"' => {
-                    let column = self.column;
+                    let (line, column) = (self.line, self.column);
                     self.column += 1;
                     let mut value = String::new();
+                    // Strings can span several lines, which moves the position of the tokens
+                    // after them.
                     while let Some(c) = self.chars.next_if(|c| *c != '"') {
+                        if c == '\n' {
+                            self.line += 1;
+                            self.column = 1;
+                        } else {
+                            self.column += 1;
+                        }
                         value.push(c);
                     }
                     if self.chars.next_if_eq(&'"').is_none() {
-                        panic!(
-                            "Unterminated string literal at line {}:{}",
-                            self.line, column
-                        );
+                        return Some(self.error(UNTERMINATED_STRING, line, column));
                     }
-                    self.column += value.len() + 1;
+                    self.column += 1;
                     Some(Token::new(
                         TokenIdentity::String,
-                        TokenValue::String(value),
-                        self.line,
+                        TokenValue::String(self.atoms.borrow_mut().intern(&value)),
+                        line,
                         column,
                     ))
                 }
@@ -283,24 +471,53 @@ impl Iterator for Scanner<'_> {
                             }
                         }
                         self.column += value.len();
+                        // Literals without a decimal point are integers, unless they
+                        // don't fit in one.
+                        // Digits of other scripts, such as '٣', are numeric but don't parse.
+                        let number = match (value.parse(), value.parse()) {
+                            (Ok(integer), _) if !value.contains('.') => {
+                                TokenValue::Integer(integer)
+                            }
+                            (_, Ok(number)) => TokenValue::Number(number),
+                            _ => {
+                                let message = format!("Invalid number '{value}'.");
+                                return Some(self.error(&message, self.line, column));
+                            }
+                        };
+                        Some(Token::new(TokenIdentity::Number, number, self.line, column))
+                    } else if c == '\\'
+                        && self.raw_identifiers
+                        && self.chars.chars.peek().is_some_and(|c| c.is_alphabetic())
+                    {
+                        let column = self.column;
+                        let value = self.identifier(String::new());
+                        self.column += 1;
                         Some(Token::new(
-                            TokenIdentity::Number,
-                            TokenValue::Number(
-                                value.parse().unwrap_or_else(|_| {
-                                    panic!("Can't parse '{value}' into a number")
-                                }),
-                            ),
+                            TokenIdentity::Identifier,
+                            TokenValue::String(self.atoms.borrow_mut().intern(&value)),
                             self.line,
                             column,
                         ))
                     } else if c.is_alphabetic() {
                         let column = self.column;
-                        let mut value = String::from(c);
-                        while let Some(c) = self.chars.next_if(|c| c.is_alphabetic() || *c == '_') {
-                            value.push(c);
-                        }
-                        self.column += value.len();
-                        match value.as_str() {
+                        let value = self.identifier(String::from(c));
+                        let keyword = if self.case_insensitive_keywords {
+                            value.to_lowercase()
+                        } else {
+                            value.clone()
+                        };
+                        match keyword.as_str() {
+                            keyword
+                                if self.dialect == Dialect::Lox
+                                    && !LOX_KEYWORDS.contains(&keyword) =>
+                            {
+                                Some(Token::new(
+                                    TokenIdentity::Identifier,
+                                    TokenValue::String(self.atoms.borrow_mut().intern(&value)),
+                                    self.line,
+                                    column,
+                                ))
+                            }
                             "and" => Some(Token::new(
                                 TokenIdentity::And,
                                 TokenValue::Nil,
@@ -313,6 +530,18 @@ impl Iterator for Scanner<'_> {
                                 self.line,
                                 column,
                             )),
+                            "case" => Some(Token::new(
+                                TokenIdentity::Case,
+                                TokenValue::Nil,
+                                self.line,
+                                column,
+                            )),
+                            "catch" => Some(Token::new(
+                                TokenIdentity::Catch,
+                                TokenValue::Nil,
+                                self.line,
+                                column,
+                            )),
                             "continue" => Some(Token::new(
                                 TokenIdentity::Continue,
                                 TokenValue::Nil,
@@ -325,18 +554,42 @@ impl Iterator for Scanner<'_> {
                                 self.line,
                                 column,
                             )),
+                            "const" => Some(Token::new(
+                                TokenIdentity::Const,
+                                TokenValue::Nil,
+                                self.line,
+                                column,
+                            )),
+                            "default" => Some(Token::new(
+                                TokenIdentity::Default,
+                                TokenValue::Nil,
+                                self.line,
+                                column,
+                            )),
                             "else" => Some(Token::new(
                                 TokenIdentity::Else,
                                 TokenValue::Nil,
                                 self.line,
                                 column,
                             )),
+                            "export" => Some(Token::new(
+                                TokenIdentity::Export,
+                                TokenValue::Nil,
+                                self.line,
+                                column,
+                            )),
                             "false" => Some(Token::new(
                                 TokenIdentity::False,
                                 TokenValue::Bool(false),
                                 self.line,
                                 column,
                             )),
+                            "finally" => Some(Token::new(
+                                TokenIdentity::Finally,
+                                TokenValue::Nil,
+                                self.line,
+                                column,
+                            )),
                             "for" => Some(Token::new(
                                 TokenIdentity::For,
                                 TokenValue::Nil,
@@ -355,6 +608,24 @@ impl Iterator for Scanner<'_> {
                                 self.line,
                                 column,
                             )),
+                            "import" => Some(Token::new(
+                                TokenIdentity::Import,
+                                TokenValue::Nil,
+                                self.line,
+                                column,
+                            )),
+                            "in" => Some(Token::new(
+                                TokenIdentity::In,
+                                TokenValue::Nil,
+                                self.line,
+                                column,
+                            )),
+                            "is" => Some(Token::new(
+                                TokenIdentity::Is,
+                                TokenValue::Nil,
+                                self.line,
+                                column,
+                            )),
                             "nil" => Some(Token::new(
                                 TokenIdentity::Nil,
                                 TokenValue::Nil,
@@ -381,13 +652,25 @@ impl Iterator for Scanner<'_> {
                             )),
                             "super" => Some(Token::new(
                                 TokenIdentity::Super,
-                                TokenValue::String("super".to_string()),
+                                TokenValue::String(self.atoms.borrow_mut().intern("super")),
+                                self.line,
+                                column,
+                            )),
+                            "switch" => Some(Token::new(
+                                TokenIdentity::Switch,
+                                TokenValue::Nil,
                                 self.line,
                                 column,
                             )),
                             "this" => Some(Token::new(
                                 TokenIdentity::This,
-                                TokenValue::String("this".to_string()),
+                                TokenValue::String(self.atoms.borrow_mut().intern("this")),
+                                self.line,
+                                column,
+                            )),
+                            "throw" => Some(Token::new(
+                                TokenIdentity::Throw,
+                                TokenValue::Nil,
                                 self.line,
                                 column,
                             )),
@@ -397,6 +680,18 @@ impl Iterator for Scanner<'_> {
                                 self.line,
                                 column,
                             )),
+                            "try" => Some(Token::new(
+                                TokenIdentity::Try,
+                                TokenValue::Nil,
+                                self.line,
+                                column,
+                            )),
+                            "typeof" => Some(Token::new(
+                                TokenIdentity::Typeof,
+                                TokenValue::Nil,
+                                self.line,
+                                column,
+                            )),
                             "var" => Some(Token::new(
                                 TokenIdentity::Var,
                                 TokenValue::Nil,
@@ -411,16 +706,16 @@ impl Iterator for Scanner<'_> {
                             )),
                             _ => Some(Token::new(
                                 TokenIdentity::Identifier,
-                                TokenValue::String(value),
+                                TokenValue::String(self.atoms.borrow_mut().intern(&value)),
                                 self.line,
                                 column,
                             )),
                         }
                     } else {
-                        panic!(
-                            "Unexpected character at line {}:{}: {}",
-                            self.line, self.column, c
-                        );
+                        let column = self.column;
+                        self.column += 1;
+                        let message = format!("Unexpected character '{c}'.");
+                        Some(self.error(&message, self.line, column))
                     }
                 }
             },
@@ -441,6 +736,21 @@ impl Iterator for Scanner<'_> {
     }
 }
 
+impl Iterator for Scanner<'_> {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.skip_whitespace();
+        let start = self.chars.offset;
+        let mut token = self.scan_token()?;
+        token.span = Span::new(start, self.chars.offset);
+        if !matches!(token.id, TokenIdentity::Comment | TokenIdentity::Meta) {
+            self.in_header = false;
+        }
+        Some(token)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -463,6 +773,167 @@ mod tests {
         assert_eq!(tokens[9].id, TokenIdentity::Eof);
     }
 
+    #[test]
+    fn test_keyword_options() {
+        let ids = |scanner: Scanner| scanner.map(|token| token.id).collect::<Vec<_>>();
+
+        assert_eq!(
+            ids(Scanner::new("IF While")),
+            [
+                TokenIdentity::Identifier,
+                TokenIdentity::Identifier,
+                TokenIdentity::Eof
+            ]
+        );
+        let tokens: Vec<Token> = Scanner::new("IF While")
+            .with_case_insensitive_keywords(true)
+            .collect();
+        assert_eq!(tokens[0].id, TokenIdentity::If);
+        assert_eq!(tokens[1].id, TokenIdentity::While);
+
+        let tokens: Vec<Token> = Scanner::new("obj.\\class = 1;")
+            .with_raw_identifiers(true)
+            .collect();
+        assert_eq!(tokens[2].id, TokenIdentity::Identifier);
+        assert_eq!(tokens[2].value.to_string(), "class");
+        assert_eq!(tokens[2].column, 5);
+        assert_eq!(tokens[3].column, 12);
+
+        assert_eq!(
+            ids(Scanner::new("break print").with_dialect(Dialect::Lox)),
+            [
+                TokenIdentity::Identifier,
+                TokenIdentity::Print,
+                TokenIdentity::Eof
+            ]
+        );
+    }
+
+    #[test]
+    fn test_brackets() {
+        let input = "[1, 2]";
+        let scanner = Scanner::new(input);
+        let tokens: Vec<Token> = scanner.into_iter().collect();
+        assert_eq!(tokens.len(), 6);
+        assert_eq!(tokens[0].id, TokenIdentity::LeftBracket);
+        assert_eq!(tokens[1].id, TokenIdentity::Number);
+        assert_eq!(tokens[2].id, TokenIdentity::Comma);
+        assert_eq!(tokens[3].id, TokenIdentity::Number);
+        assert_eq!(tokens[4].id, TokenIdentity::RightBracket);
+        assert_eq!(tokens[4].column, 6);
+        assert_eq!(tokens[5].id, TokenIdentity::Eof);
+    }
+
+    #[test]
+    fn test_numbers() {
+        let values = |input: &str| {
+            Scanner::new(input)
+                .filter(|token| token.id == TokenIdentity::Number)
+                .map(|token| token.value)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            values("1 2.5 3. 99999999999999999999"),
+            [
+                TokenValue::Integer(1),
+                TokenValue::Number(2.5),
+                TokenValue::Number(3.0),
+                TokenValue::Number(1e20),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_meta_header() {
+        let input = "// About\n//! name: tool\n//!version:0.1\n//! plain\nx;\n//! late: no";
+        let tokens: Vec<Token> = Scanner::new(input).collect();
+        let ids: Vec<_> = tokens.iter().map(|token| token.id).collect();
+        assert_eq!(
+            ids,
+            [
+                TokenIdentity::Comment,
+                TokenIdentity::Meta,
+                TokenIdentity::Meta,
+                TokenIdentity::Comment,
+                TokenIdentity::Identifier,
+                TokenIdentity::Semicolon,
+                TokenIdentity::Comment,
+                TokenIdentity::Eof,
+            ]
+        );
+        assert_eq!(
+            tokens[1].value,
+            TokenValue::Meta(Box::new(("name".into(), "tool".into())))
+        );
+        assert_eq!(tokens[2].value.to_string(), "version: 0.1");
+        assert_eq!((tokens[2].line, tokens[2].column), (3, 1));
+    }
+
+    #[test]
+    fn test_first_line() {
+        let tokens: Vec<Token> = Scanner::new("a;\nb;").with_first_line(12).collect();
+        assert_eq!((tokens[0].line, tokens[0].column), (12, 1));
+        assert_eq!((tokens[2].line, tokens[2].column), (13, 1));
+    }
+
+    #[test]
+    fn test_multiline_string() {
+        let input = "var s = \"one\ntwo\"; é;";
+        let tokens: Vec<Token> = Scanner::new(input).collect();
+        assert_eq!(tokens[3].id, TokenIdentity::String);
+        assert_eq!(tokens[3].value.to_string(), "one\ntwo");
+        assert_eq!((tokens[3].line, tokens[3].column), (1, 9));
+        assert_eq!(tokens[3].span.text(input), "\"one\ntwo\"");
+        // Positions after the string account for its newline.
+        assert_eq!((tokens[4].line, tokens[4].column), (2, 5));
+        assert_eq!(tokens[4].span.text(input), ";");
+        // Columns count characters, spans count bytes.
+        assert_eq!((tokens[5].line, tokens[5].column), (2, 7));
+        assert_eq!(tokens[5].span.text(input), "é");
+        assert_eq!((tokens[6].line, tokens[6].column), (2, 8));
+    }
+
+    #[test]
+    fn test_atoms() {
+        let atoms = Rc::new(RefCell::new(AtomTable::new()));
+        let tokens: Vec<Token> = Scanner::new("foo \"foo\" bar foo")
+            .with_atoms(atoms.clone())
+            .collect();
+        assert_eq!(tokens[0].atom(), tokens[3].atom());
+        assert_eq!(tokens[1].atom(), tokens[0].atom());
+        assert_eq!(atoms.borrow().len(), 2);
+
+        // A second source reuses the atoms of the first one.
+        Scanner::new("bar baz")
+            .with_atoms(atoms.clone())
+            .for_each(drop);
+        assert_eq!(atoms.borrow().len(), 3);
+    }
+
+    #[test]
+    fn test_errors() {
+        let tokens: Vec<Token> = Scanner::new("a % b;\n٣;\n\"open").collect();
+        let errors: Vec<(usize, usize, String)> = tokens
+            .iter()
+            .filter(|token| token.id == TokenIdentity::Error)
+            .map(|token| (token.line, token.column, token.to_string()))
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                (1, 3, "Unexpected character '%'.".to_string()),
+                (2, 1, "Invalid number '٣'.".to_string()),
+                (3, 1, UNTERMINATED_STRING.to_string()),
+            ]
+        );
+        // Scanning goes on after an error.
+        assert_eq!(
+            (tokens[2].id, tokens[2].column),
+            (TokenIdentity::Identifier, 5)
+        );
+        assert_eq!(tokens.last().unwrap().id, TokenIdentity::Eof);
+    }
+
     // #[test]
     // fn test_2lines() {
     //     let input = r#"// The comment
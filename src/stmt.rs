@@ -1,178 +1,133 @@
+use std::rc::Rc;
+
 use crate::{
-    expr::{Expr, VariableExpr},
+    ast::ast,
+    expr::{Expr, Pattern, VariableExpr},
     function::FunctionType,
     token::Token,
 };
 
-pub trait StmtVisitor {
-    type Output;
-
-    fn visit_block_stmt(&mut self, stmt: &BlockStmt) -> Self::Output;
-    fn visit_break_stmt(&self) -> Self::Output;
-    fn visit_continue_stmt(&self) -> Self::Output;
-    fn visit_class_stmt(&mut self, stmt: &ClassStmt) -> Self::Output;
-    fn visit_expression_stmt(&mut self, stmt: &ExpressionStmt) -> Self::Output;
-    fn visit_function_stmt(&mut self, stmt: &FunctionStmt) -> Self::Output;
-    fn visit_if_stmt(&mut self, stmt: &IfStmt) -> Self::Output;
-    fn visit_print_stmt(&mut self, stmt: &PrintStmt) -> Self::Output;
-    fn visit_return_stmt(&mut self, stmt: &ReturnStmt) -> Self::Output;
-    fn visit_var_stmt(&mut self, stmt: &VarStmt) -> Self::Output;
-    fn visit_while_stmt(&mut self, stmt: &WhileStmt) -> Self::Output;
-
-    fn accept(&mut self, stmt: &Stmt) -> Self::Output {
-        match stmt {
-            Stmt::Block(stmt) => self.visit_block_stmt(stmt),
-            Stmt::Break => self.visit_break_stmt(),
-            Stmt::Continue => self.visit_continue_stmt(),
-            Stmt::Class(stmt) => self.visit_class_stmt(stmt),
-            Stmt::Expression(stmt) => self.visit_expression_stmt(stmt),
-            Stmt::Function(stmt) => self.visit_function_stmt(stmt),
-            Stmt::If(stmt) => self.visit_if_stmt(stmt),
-            Stmt::Print(stmt) => self.visit_print_stmt(stmt),
-            Stmt::Return(stmt) => self.visit_return_stmt(stmt),
-            Stmt::Var(stmt) => self.visit_var_stmt(stmt),
-            Stmt::While(stmt) => self.visit_while_stmt(stmt),
-        }
+ast! {
+    enum Stmt, visited by StmtVisitor with stmt;
+    boxed {}
+    shared {
+        Function(FunctionStmt) => visit_function_stmt {
+            name: Token,
+            params: Vec<Token>,
+            /// Whether the last parameter collects the remaining arguments into a list.
+            variadic: bool,
+            body: BlockStmt,
+            kind: FunctionType,
+            /// Set on getters declared with `memo`, whose result is cached per instance until
+            /// one of its fields is written.
+            memo: bool,
+        },
     }
-}
-
-#[derive(Clone, Debug)]
-pub enum Stmt {
-    Block(BlockStmt),
-    Break,
-    Continue,
-    Class(ClassStmt),
-    Expression(ExpressionStmt),
-    Function(FunctionStmt),
-    If(IfStmt),
-    Print(PrintStmt),
-    Return(ReturnStmt),
-    Var(VarStmt),
-    While(WhileStmt),
-}
-
-#[derive(Clone, Debug)]
-pub struct BlockStmt {
-    pub statements: Vec<Stmt>,
-}
-
-impl BlockStmt {
-    pub fn new(statements: Vec<Stmt>) -> Self {
-        Self { statements }
+    inline {
+        Block(BlockStmt) => visit_block_stmt { statements: Vec<Stmt> },
+        Break(BreakStmt) => visit_break_stmt { keyword: Token },
+        Class(ClassStmt) => visit_class_stmt {
+            name: Token,
+            superclass: Option<VariableExpr>,
+            /// Fields declared with `var` in the class body, set on every new instance.
+            fields: Rc<Vec<VarStmt>>,
+            methods: Vec<Rc<FunctionStmt>>,
+            static_methods: Vec<Rc<FunctionStmt>>,
+            getter_methods: Vec<Rc<FunctionStmt>>,
+            /// `class { ... }` blocks, run in order once the class is defined, like the body
+            /// of a static method.
+            static_blocks: Vec<BlockStmt>,
+        },
+        Continue(ContinueStmt) => visit_continue_stmt { keyword: Token },
+        /// A `var` or `const` declaration of the names in a destructuring pattern.
+        Destructure(DestructureStmt) => visit_destructure_stmt {
+            pattern: Pattern,
+            initializer: Expr,
+            is_const: bool,
+        },
+        /// A top-level declaration that a module exposes to the programs importing it.
+        Export(ExportStmt) => visit_export_stmt { keyword: Token, declaration: Box<Stmt> },
+        Expression(ExpressionStmt) => visit_expression_stmt { expr: Expr },
+        For(ForStmt) => visit_for_stmt {
+            initializer: Option<Box<Stmt>>,
+            condition: Expr,
+            increment: Option<Expr>,
+            body: BlockStmt,
+        },
+        ForIn(ForInStmt) => visit_for_in_stmt {
+            name: Token,
+            keyword: Token,
+            iterable: Expr,
+            body: BlockStmt,
+        },
+        If(IfStmt) => visit_if_stmt {
+            condition: Expr,
+            then_branch: BlockStmt,
+            /// Either a block or, for an `else if`, the next `if` of the chain.
+            else_branch: Option<Box<Stmt>>,
+        },
+        /// `import "path" as name;`, which runs the module at `path` and binds its exports to
+        /// `name`.
+        Import(ImportStmt) => visit_import_stmt { keyword: Token, path: Token, name: Token },
+        Print(PrintStmt) => visit_print_stmt { expr: Expr },
+        Return(ReturnStmt) => visit_return_stmt { keyword: Token, value: Option<Expr> },
+        /// A `switch` without fallthrough: only the body of the first matching case runs.
+        Switch(SwitchStmt) => visit_switch_stmt {
+            subject: Expr,
+            cases: Vec<(Vec<Expr>, BlockStmt)>,
+            default: Option<BlockStmt>,
+        },
+        Throw(ThrowStmt) => visit_throw_stmt { keyword: Token, value: Expr },
+        /// A `try` with a `catch` clause, a `finally` clause, or both.
+        Try(TryStmt) => visit_try_stmt {
+            body: BlockStmt,
+            /// The name bound to the thrown value, and the block run when something is thrown.
+            catch: Option<(Token, BlockStmt)>,
+            /// The kinds of runtime errors the catch is limited to, as in
+            /// `catch (e: DivisionByZero | TypeMismatch)`. Without any, it catches everything.
+            kinds: Vec<Token>,
+            /// Runs however the rest of the statement finishes, including by `return` or
+            /// `break`.
+            finally: Option<BlockStmt>,
+        },
+        Var(VarStmt) => visit_var_stmt {
+            name: Token,
+            initializer: Option<Expr>,
+            /// Declared with `const`, which forbids assigning to the variable afterwards.
+            is_const: bool,
+        },
+        While(WhileStmt) => visit_while_stmt { condition: Expr, body: BlockStmt },
     }
 }
 
-#[derive(Clone, Debug)]
-pub struct ClassStmt {
-    pub name: Token,
-    pub superclass: Option<VariableExpr>,
-    pub methods: Vec<FunctionStmt>,
-    pub static_methods: Vec<FunctionStmt>,
-    pub getter_methods: Vec<FunctionStmt>,
-}
-
-impl ClassStmt {
-    pub fn new(
-        name: Token,
-        superclass: Option<VariableExpr>,
-        methods: Vec<FunctionStmt>,
-        static_methods: Vec<FunctionStmt>,
-        getter_methods: Vec<FunctionStmt>,
-    ) -> Self {
-        Self {
-            name,
-            superclass,
-            methods,
-            static_methods,
-            getter_methods,
-        }
+impl Stmt {
+    /// Whether the statement is a bare expression, whose value the REPL shows.
+    pub fn is_expression(&self) -> bool {
+        matches!(self, Stmt::Expression(_))
     }
-}
 
-#[derive(Clone, Debug)]
-pub struct ExpressionStmt {
-    pub expr: Expr,
-}
-
-impl ExpressionStmt {
-    pub fn new(expr: Expr) -> Self {
-        Self { expr }
-    }
-}
-#[derive(Clone, Debug)]
-pub struct FunctionStmt {
-    pub name: Token,
-    pub params: Vec<Token>,
-    pub body: BlockStmt,
-    pub kind: FunctionType,
-}
-
-impl FunctionStmt {
-    pub fn new(name: Token, params: Vec<Token>, body: BlockStmt, kind: FunctionType) -> Self {
-        Self {
-            name,
-            params,
-            body,
-            kind,
+    /// The keyword of a statement that always leaves the block it's in: a `return`, `break`,
+    /// `continue` or `throw`.
+    pub fn jump_keyword(&self) -> Option<&Token> {
+        match self {
+            Stmt::Break(stmt) => Some(&stmt.keyword),
+            Stmt::Continue(stmt) => Some(&stmt.keyword),
+            Stmt::Return(stmt) => Some(&stmt.keyword),
+            Stmt::Throw(stmt) => Some(&stmt.keyword),
+            _ => None,
         }
     }
 }
-#[derive(Clone, Debug)]
-pub struct IfStmt {
-    pub condition: Expr,
-    pub then_branch: BlockStmt,
-    pub else_branch: Option<BlockStmt>,
-}
 
-impl IfStmt {
-    pub fn new(condition: Expr, then_branch: BlockStmt, else_branch: Option<BlockStmt>) -> Self {
-        Self {
-            condition,
-            then_branch,
-            else_branch,
+impl ExportStmt {
+    /// The names declared by the exported declaration.
+    pub fn names(&self) -> Vec<Token> {
+        match self.declaration.as_ref() {
+            Stmt::Class(stmt) => vec![stmt.name.clone()],
+            Stmt::Function(stmt) => vec![stmt.name.clone()],
+            Stmt::Var(stmt) => vec![stmt.name.clone()],
+            Stmt::Destructure(stmt) => stmt.pattern.names().to_vec(),
+            _ => Vec::new(),
         }
     }
 }
-#[derive(Clone, Debug)]
-pub struct PrintStmt {
-    pub expr: Expr,
-}
-
-impl PrintStmt {
-    pub fn new(expr: Expr) -> Self {
-        Self { expr }
-    }
-}
-#[derive(Clone, Debug)]
-pub struct ReturnStmt {
-    pub keyword: Token,
-    pub value: Option<Expr>,
-}
-
-impl ReturnStmt {
-    pub fn new(keyword: Token, value: Option<Expr>) -> Self {
-        Self { keyword, value }
-    }
-}
-#[derive(Clone, Debug)]
-pub struct VarStmt {
-    pub name: Token,
-    pub initializer: Option<Expr>,
-}
-
-impl VarStmt {
-    pub fn new(name: Token, initializer: Option<Expr>) -> Self {
-        Self { name, initializer }
-    }
-}
-#[derive(Clone, Debug)]
-pub struct WhileStmt {
-    pub condition: Expr,
-    pub body: BlockStmt,
-}
-
-impl WhileStmt {
-    pub fn new(condition: Expr, body: BlockStmt) -> Self {
-        Self { condition, body }
-    }
-}
@@ -0,0 +1,176 @@
+use std::fmt;
+
+use crate::{
+    atom::Atom,
+    builtin_funcs::{LoxCallable, fmt_callable},
+    error::{RuntimeError, RuntimeErrorKind, RuntimeException},
+    interpreter::Interpreter,
+    object::Object,
+};
+
+/// The methods of strings, with their arities.
+const METHODS: [(&str, usize); 10] = [
+    ("charAt", 1),
+    ("contains", 1),
+    ("indexOf", 1),
+    ("len", 0),
+    ("replace", 2),
+    ("split", 1),
+    ("substring", 2),
+    ("toLower", 0),
+    ("toUpper", 0),
+    ("trim", 0),
+];
+
+/// A method of strings, bound to the string it was read from, as in `"abc".len()`. Positions
+/// count characters, like indexing does.
+#[derive(Debug)]
+pub struct StringMethod {
+    receiver: Atom,
+    name: &'static str,
+    arity: usize,
+}
+
+impl StringMethod {
+    pub fn bind(receiver: Atom, name: &str) -> Option<Self> {
+        let (name, arity) = METHODS.into_iter().find(|(method, _)| *method == name)?;
+        Some(Self {
+            receiver,
+            name,
+            arity,
+        })
+    }
+
+    fn string_arg<'a>(
+        &self,
+        args: &'a [Object],
+        position: usize,
+    ) -> Result<&'a str, RuntimeException> {
+        match &args[position] {
+            Object::String(string) => Ok(string),
+            other => Err(RuntimeException::Error(RuntimeError::native(
+                RuntimeErrorKind::TypeMismatch {
+                    expected: "string",
+                    found: other.type_name(),
+                },
+                &format!("{}() expects a string, got {other}.", self.name),
+            ))),
+        }
+    }
+
+    /// A character position of the receiver, up to its length when `inclusive`.
+    fn position_arg(
+        &self,
+        args: &[Object],
+        position: usize,
+        inclusive: bool,
+    ) -> Result<usize, RuntimeException> {
+        let len = self.receiver.chars().count();
+        let requested = match args[position].maybe_to_integer() {
+            Some(requested) => requested,
+            None => {
+                let other = &args[position];
+                return Err(RuntimeException::Error(RuntimeError::native(
+                    RuntimeErrorKind::TypeMismatch {
+                        expected: "integer",
+                        found: other.type_name(),
+                    },
+                    &format!("{}() expects an integer position, got {other}.", self.name),
+                )));
+            }
+        };
+        let end = if inclusive {
+            len as i64
+        } else {
+            len as i64 - 1
+        };
+        if (0..=end).contains(&requested) {
+            Ok(requested as usize)
+        } else {
+            Err(RuntimeException::Error(RuntimeError::native(
+                RuntimeErrorKind::IndexOutOfRange {
+                    index: requested,
+                    length: len,
+                },
+                &format!("Index {requested} out of range for length {len}."),
+            )))
+        }
+    }
+}
+
+impl LoxCallable for StringMethod {
+    fn call(
+        &self,
+        _interpreter: &mut Interpreter,
+        args: Vec<Object>,
+    ) -> Result<Object, RuntimeException> {
+        let string = self.receiver.as_str();
+        let text = |text: String| Ok(Object::String(text.into()));
+        match self.name {
+            "charAt" => {
+                let index = self.position_arg(&args, 0, false)?;
+                text(string.chars().nth(index).unwrap().to_string())
+            }
+            "contains" => Ok(Object::Boolean(string.contains(self.string_arg(&args, 0)?))),
+            "indexOf" => {
+                let needle = self.string_arg(&args, 0)?;
+                let index = string
+                    .find(needle)
+                    .map_or(-1, |byte| string[..byte].chars().count() as i64);
+                Ok(Object::Integer(index))
+            }
+            "len" => Ok(Object::Integer(string.chars().count() as i64)),
+            "replace" => {
+                text(string.replace(self.string_arg(&args, 0)?, self.string_arg(&args, 1)?))
+            }
+            "split" => {
+                let separator = self.string_arg(&args, 0)?;
+                let parts: Vec<Object> = if separator.is_empty() {
+                    string
+                        .chars()
+                        .map(|c| Object::String(c.to_string().into()))
+                        .collect()
+                } else {
+                    string
+                        .split(separator)
+                        .map(|part| Object::String(part.into()))
+                        .collect()
+                };
+                Ok(Object::new_list(parts))
+            }
+            "substring" => {
+                let start = self.position_arg(&args, 0, true)?;
+                let end = self.position_arg(&args, 1, true)?;
+                text(
+                    string
+                        .chars()
+                        .skip(start)
+                        .take(end.saturating_sub(start))
+                        .collect(),
+                )
+            }
+            "toLower" => text(string.to_lowercase()),
+            "toUpper" => text(string.to_uppercase()),
+            "trim" => text(string.trim().to_string()),
+            _ => unreachable!("Unknown string method {}", self.name),
+        }
+    }
+
+    fn arity(&self) -> usize {
+        self.arity
+    }
+
+    fn name(&self) -> Option<String> {
+        Some(self.name.to_string())
+    }
+
+    fn is_native(&self) -> bool {
+        true
+    }
+}
+
+impl fmt::Display for StringMethod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt_callable(self, f)
+    }
+}
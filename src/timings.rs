@@ -0,0 +1,137 @@
+use std::{
+    fmt,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    error::LoxError, interpreter::Interpreter, object::Object, parser::Parser, resolver::Resolver,
+    stmt::Stmt, token::Token,
+};
+
+/// Time spent in each stage of running a program. Stages that didn't run because an earlier
+/// one failed are left at zero.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Timings {
+    pub scan: Duration,
+    pub parse: Duration,
+    pub resolve: Duration,
+    pub execute: Duration,
+}
+
+impl Timings {
+    pub fn total(&self) -> Duration {
+        self.scan + self.parse + self.resolve + self.execute
+    }
+}
+
+impl fmt::Display for Timings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "scan:    {:?}", self.scan)?;
+        writeln!(f, "parse:   {:?}", self.parse)?;
+        writeln!(f, "resolve: {:?}", self.resolve)?;
+        writeln!(f, "execute: {:?}", self.execute)?;
+        write!(f, "total:   {:?}", self.total())
+    }
+}
+
+/// Runs `source` in `interpreter` like the `rlox` binary does, timing each stage.
+pub fn run_timed(
+    source: &str,
+    interpreter: &mut Interpreter,
+) -> (Result<Object, LoxError>, Timings) {
+    let mut timings = Timings::default();
+
+    let start = Instant::now();
+    let tokens = interpreter.scanner(source).collect::<Vec<Token>>();
+    timings.scan = start.elapsed();
+
+    let start = Instant::now();
+    let mut parser = Parser::new(tokens).with_dialect(interpreter.dialect);
+    interpreter.script_meta = parser.meta().to_vec();
+    let statements = parser.parse();
+    timings.parse = start.elapsed();
+    let statements = match statements {
+        Ok(statements) => statements,
+        Err(error) => return (Err(LoxError::Parsing(error)), timings),
+    };
+
+    let (result, run) = run_parsed(statements, interpreter);
+    timings.resolve = run.resolve;
+    timings.execute = run.execute;
+    (result, timings)
+}
+
+/// Resolves and runs `statements` like [`run_timed`] does once it has parsed a program. It
+/// runs programs parsed elsewhere, such as those read from JSON with the `serde` feature.
+pub fn run_parsed(
+    mut statements: Vec<Stmt>,
+    interpreter: &mut Interpreter,
+) -> (Result<Object, LoxError>, Timings) {
+    let mut timings = Timings::default();
+
+    let start = Instant::now();
+    let mut resolver = Resolver::new(interpreter);
+    let resolved = resolver.resolve_stmts(&statements);
+    if resolved.is_ok() {
+        resolver.optimize(&mut statements);
+    }
+    timings.resolve = start.elapsed();
+    if let Err(error) = resolved {
+        return (Err(LoxError::Resolving(error)), timings);
+    }
+
+    let start = Instant::now();
+    let result = interpreter.interpret(&statements);
+    timings.execute = start.elapsed();
+
+    (result.map_err(LoxError::Runtime), timings)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, io, rc::Rc};
+
+    use super::*;
+
+    fn run(source: &str) -> (Result<Object, LoxError>, Timings) {
+        let mut interpreter = Interpreter::new(Rc::new(RefCell::new(io::sink())));
+        run_timed(source, &mut interpreter)
+    }
+
+    #[test]
+    fn test_run_timed() {
+        let (result, timings) = run("var a = 1; a + 1;");
+        assert_eq!(result.ok().unwrap().to_string(), "2");
+        assert_eq!(
+            timings.total(),
+            timings.scan + timings.parse + timings.resolve + timings.execute
+        );
+
+        // Stages after a failing one don't run.
+        let (result, timings) = run("var a = ;");
+        assert!(matches!(result, Err(LoxError::Parsing(_))));
+        assert_eq!(timings.resolve, Duration::ZERO);
+        assert_eq!(timings.execute, Duration::ZERO);
+
+        let (result, timings) = run("return 1;");
+        assert!(matches!(result, Err(LoxError::Resolving(_))));
+        assert_eq!(timings.execute, Duration::ZERO);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_run_parsed_from_json() {
+        use crate::scanner::Scanner;
+
+        let source = "fun add(a, b) = a + b; var xs = [add(1, 2)]; print(xs); xs[0];";
+        let statements = Parser::new(Scanner::new(source).collect()).parse().unwrap();
+        let json = serde_json::to_string(&statements).unwrap();
+
+        let (mut interpreter, output) = Interpreter::with_capture();
+        let statements = serde_json::from_str(&json).unwrap();
+        let (result, timings) = run_parsed(statements, &mut interpreter);
+        assert_eq!(result.ok().unwrap().to_string(), "3");
+        assert_eq!(output.into_string(), "[3]\n");
+        assert_eq!(timings.scan + timings.parse, Duration::ZERO);
+    }
+}
@@ -1,11 +1,18 @@
 use std::fmt;
 
+use crate::atom::Atom;
+
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum TokenValue {
     Nil,
     Bool(bool),
-    String(String),
+    String(Atom),
     Number(f64),
+    Integer(i64),
+    /// An entry of a script's metadata header, as its key and value. Boxed to keep tokens,
+    /// which errors carry around, small.
+    Meta(Box<(Atom, Atom)>),
 }
 
 impl fmt::Display for TokenValue {
@@ -15,16 +22,41 @@ impl fmt::Display for TokenValue {
             TokenValue::Bool(b) => write!(f, "{b}"),
             TokenValue::String(s) => write!(f, "{s}"),
             TokenValue::Number(n) => write!(f, "{n}"),
+            TokenValue::Integer(n) => write!(f, "{n}"),
+            TokenValue::Meta(entry) => write!(f, "{}: {}", entry.0, entry.1),
         }
     }
 }
 
+/// The byte range of a token's raw text in the source it was scanned from.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Span { start, end }
+    }
+
+    /// The raw text of the token, e.g. a string literal with its quotes.
+    pub fn text<'a>(&self, source: &'a str) -> &'a str {
+        &source[self.start..self.end]
+    }
+}
+
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Token {
     pub id: TokenIdentity,
+    /// The cooked value of the token, e.g. a string literal without its quotes.
     pub value: TokenValue,
     pub line: usize,
     pub column: usize,
+    /// Empty for tokens that the parser or the interpreter make up.
+    pub span: Span,
 }
 
 impl Token {
@@ -34,6 +66,15 @@ impl Token {
             value,
             line,
             column,
+            span: Span::default(),
+        }
+    }
+
+    /// The text of an identifier, `this` or `super` token, shared with the atom table.
+    pub fn atom(&self) -> Atom {
+        match &self.value {
+            TokenValue::String(value) => value.clone(),
+            value => Atom::from(value.to_string()),
         }
     }
 }
@@ -45,6 +86,8 @@ impl fmt::Display for Token {
             TokenIdentity::RightParen => ")",
             TokenIdentity::LeftBrace => "{",
             TokenIdentity::RightBrace => "}",
+            TokenIdentity::LeftBracket => "[",
+            TokenIdentity::RightBracket => "]",
             TokenIdentity::Colon => ":",
             TokenIdentity::Comma => ",",
             TokenIdentity::Dot => ".",
@@ -53,7 +96,10 @@ impl fmt::Display for Token {
             TokenIdentity::Semicolon => ";",
             TokenIdentity::Slash => "/",
             TokenIdentity::Star => "*",
+            TokenIdentity::Pipe => "|",
             TokenIdentity::Question => "?",
+            TokenIdentity::QuestionQuestion => "??",
+            TokenIdentity::DotDotDot => "...",
             TokenIdentity::Bang => "!",
             TokenIdentity::BangEqual => "!=",
             TokenIdentity::Equal => "=",
@@ -63,27 +109,42 @@ impl fmt::Display for Token {
             TokenIdentity::Less => "<",
             TokenIdentity::LessEqual => "<=",
             TokenIdentity::Comment => "// Comment",
+            TokenIdentity::Meta => "//! Meta",
             TokenIdentity::Identifier => &self.value.to_string(),
             TokenIdentity::String => &self.value.to_string(),
             TokenIdentity::Number => &self.value.to_string(),
             TokenIdentity::And => "and",
             TokenIdentity::Break => "break",
+            TokenIdentity::Case => "case",
+            TokenIdentity::Catch => "catch",
             TokenIdentity::Continue => "continue",
             TokenIdentity::Class => "class",
+            TokenIdentity::Const => "const",
+            TokenIdentity::Default => "default",
             TokenIdentity::Else => "else",
+            TokenIdentity::Export => "export",
             TokenIdentity::False => "false",
+            TokenIdentity::Finally => "finally",
             TokenIdentity::Fun => "fun",
             TokenIdentity::For => "for",
             TokenIdentity::If => "if",
+            TokenIdentity::Import => "import",
+            TokenIdentity::In => "in",
+            TokenIdentity::Is => "is",
             TokenIdentity::Nil => "nil",
             TokenIdentity::Or => "or",
             TokenIdentity::Print => "print",
             TokenIdentity::Return => "return",
             TokenIdentity::Super => "super",
+            TokenIdentity::Switch => "switch",
             TokenIdentity::This => "this",
+            TokenIdentity::Throw => "throw",
             TokenIdentity::True => "true",
+            TokenIdentity::Try => "try",
+            TokenIdentity::Typeof => "typeof",
             TokenIdentity::Var => "var",
             TokenIdentity::While => "while",
+            TokenIdentity::Error => &self.value.to_string(),
             TokenIdentity::Eof => "eof",
         };
 
@@ -92,12 +153,15 @@ impl fmt::Display for Token {
 }
 
 #[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum TokenIdentity {
     // Single-character tokens.
     LeftParen,
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
     Colon,
     Comma,
     Dot,
@@ -106,7 +170,7 @@ pub enum TokenIdentity {
     Semicolon,
     Slash,
     Star,
-    Question,
+    Pipe,
 
     // One or two character tokens.
     Bang,
@@ -117,9 +181,14 @@ pub enum TokenIdentity {
     GreaterEqual,
     Less,
     LessEqual,
+    Question,
+    QuestionQuestion,
+    DotDotDot,
 
     // Literals.
     Comment,
+    /// A `//! key: value` line of the comments heading a script.
+    Meta,
     Identifier,
     String,
     Number,
@@ -127,22 +196,38 @@ pub enum TokenIdentity {
     // Keywords.
     And,
     Break,
+    Case,
+    Catch,
     Continue,
     Class,
+    Const,
+    Default,
     Else,
+    Export,
     False,
+    Finally,
     Fun,
     For,
     If,
+    Import,
+    In,
+    Is,
     Nil,
     Or,
     Print,
     Return,
     Super,
+    Switch,
     This,
+    Throw,
     True,
+    Try,
+    Typeof,
     Var,
     While,
 
+    /// Text the scanner couldn't read, such as an unterminated string, with the message the
+    /// parser reports for it as its value.
+    Error,
     Eof,
 }
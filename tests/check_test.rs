@@ -0,0 +1,46 @@
+//! Runs `rlox --check` on source the scanner can't read, which should be reported like any
+//! other error rather than crash.
+
+use std::{
+    io::Write,
+    process::{Command, Output, Stdio},
+};
+
+/// Runs `rlox` with `args`, piping `input` to its stdin.
+fn rlox(args: &[&str], input: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rlox"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn test_check_reports_unterminated_string() {
+    let output = rlox(&["--check", "-"], "print(1);\nprint(\"open);\n");
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "[line 2:7] Scanning error: Unterminated string.\n"
+    );
+}
+
+#[test]
+fn test_check_reports_unexpected_character() {
+    let output = rlox(&["--check", "-"], "print(7 % 2);\nprint(1 @ 2);\n");
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "[line 1:9] Scanning error: Unexpected character '%'.\n\
+         [line 2:9] Scanning error: Unexpected character '@'.\n"
+    );
+}
@@ -0,0 +1,61 @@
+//! Runs `rlox --from-ast` on programs parsed to JSON, as other tools would write them.
+#![cfg(feature = "serde")]
+
+use std::{env, fs, path::PathBuf, process::Command};
+
+use crafting_interpreters::prelude::*;
+
+/// A scratch file, removed when dropped even if the test fails.
+struct TempFile(PathBuf);
+
+impl TempFile {
+    fn new(name: &str, contents: &str) -> Self {
+        let path = env::temp_dir().join(format!("{}-{name}", std::process::id()));
+        fs::write(&path, contents).unwrap();
+        Self(path)
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+#[test]
+fn test_run_from_ast() {
+    let source = "
+        class Greeter {
+          init(name) { this.name = name; }
+          greet() = \"hi \" + this.name;
+        }
+        for (var i = 0; i < 2; i = i + 1) print(Greeter(\"lox\").greet() + str(i));
+    ";
+    let statements = Parser::new(Scanner::new(source).collect())
+        .parse()
+        .ok()
+        .unwrap();
+    let file = TempFile::new("program.json", &serde_json::to_string(&statements).unwrap());
+    let output = Command::new(env!("CARGO_BIN_EXE_rlox"))
+        .arg("--from-ast")
+        .arg(&file.0)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "hi lox0\nhi lox1\n"
+    );
+}
+
+#[test]
+fn test_invalid_ast_is_rejected() {
+    let file = TempFile::new("invalid.json", "print(1);");
+    let output = Command::new(env!("CARGO_BIN_EXE_rlox"))
+        .arg("--from-ast")
+        .arg(&file.0)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).starts_with("Invalid AST: "));
+}
@@ -0,0 +1,45 @@
+//! Runs `genlox` and checks that the programs it generates print their expected output.
+
+mod common;
+
+use std::{fs, process::Command};
+
+use common::TempDir;
+use crafting_interpreters::{grade::run_script, interpreter::OptLevel};
+
+#[test]
+fn test_generated_programs_run() {
+    let dir = TempDir::new("genlox");
+    let status = Command::new(env!("CARGO_BIN_EXE_genlox"))
+        .arg(&dir.0)
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let mut programs = 0;
+    for entry in fs::read_dir(&dir.0).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_none_or(|extension| extension != "lox") {
+            continue;
+        }
+        let source = fs::read_to_string(&path).unwrap();
+        let output = run_script(&path, &source, OptLevel::None)
+            .unwrap_or_else(|mismatch| panic!("{}: {mismatch}", path.display()));
+        let expected = fs::read(path.with_extension("output")).unwrap();
+        assert_eq!(output, expected, "{}", path.display());
+        programs += 1;
+    }
+    assert_eq!(programs, 6);
+}
+
+#[test]
+fn test_scale_zero_is_rejected() {
+    let dir = TempDir::new("genlox-zero");
+    let output = Command::new(env!("CARGO_BIN_EXE_genlox"))
+        .arg(&dir.0)
+        .args(["--scale", "0"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--scale"));
+}
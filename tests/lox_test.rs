@@ -0,0 +1,146 @@
+//! Runs programs through the `Lox` facade, as embedders do.
+
+mod common;
+
+use std::{cell::RefCell, fs, io, rc::Rc};
+
+use common::TempDir;
+use crafting_interpreters::prelude::*;
+
+#[test]
+fn test_lox() {
+    let mut lox = Lox::new().with_writer(Rc::new(RefCell::new(io::sink())));
+    assert!(
+        lox.run("fun f(n) { return n < 1 ? 0 : 1 + f(n - 1); }")
+            .is_ok()
+    );
+    assert_eq!(lox.run("f(100);").ok().unwrap(), Object::Integer(100));
+
+    let mut lox = lox.with_max_call_depth(50);
+    let kind = |result: Result<Object, LoxError>| match result {
+        Err(LoxError::Runtime(RuntimeException::Error(error))) => error.kind().clone(),
+        _ => panic!("Expected a runtime error"),
+    };
+    assert_eq!(kind(lox.run("f(100);")), RuntimeErrorKind::StackOverflow);
+    assert_eq!(lox.run("f(10);").ok().unwrap(), Object::Integer(10));
+    assert_eq!(
+        kind(lox.run_file("missing.lox")),
+        RuntimeErrorKind::IoFailed
+    );
+
+    let calls = Rc::new(RefCell::new(Vec::new()));
+    let recorded = calls.clone();
+    lox.define_native("record", 1, move |_, args| {
+        recorded.borrow_mut().push(args[0].to_string());
+        Ok(Object::Nil)
+    });
+    assert!(lox.run("record(f(3)); record(\"done\");").is_ok());
+    assert_eq!(*calls.borrow(), ["3", "done"]);
+
+    let diagnostics = CaptureWriter::new();
+    let mut lox = Lox::new()
+        .with_writer(Rc::new(RefCell::new(io::sink())))
+        .with_diagnostics(diagnostics.buffer());
+    assert!(lox.run("fun g() {\n  return 1;\n  print(2);\n}").is_ok());
+    assert_eq!(
+        diagnostics.into_string(),
+        "[line 2:3] Warning at 'return': Unreachable code after this statement.\n"
+    );
+}
+
+#[test]
+fn test_file_access() {
+    let dir = TempDir::new("lox-file-access");
+    let source = "writeFile(\"note.txt\", \"hi\"); readFile(\"note.txt\");";
+    let mut lox = Lox::new().with_writer(Rc::new(RefCell::new(io::sink())));
+    lox.interpreter().module_dir = dir.0.clone();
+    match lox.run(source) {
+        Err(LoxError::Runtime(RuntimeException::Error(error))) => {
+            assert_eq!(*error.kind(), RuntimeErrorKind::IoFailed);
+            assert_eq!(
+                error.message(),
+                "writeFile() can't access files: file access is off."
+            );
+        }
+        _ => panic!("Expected a runtime error"),
+    }
+    assert!(!dir.0.join("note.txt").exists());
+
+    let mut lox = lox.with_file_access(true);
+    assert_eq!(lox.run(source).ok().unwrap().to_string(), "hi");
+}
+
+#[test]
+fn test_scanner_options() {
+    let source = "VAR \\class = 1; If (\\class == 1) Print(\\class);";
+    let output = CaptureWriter::new();
+    let mut lox = Lox::new()
+        .with_writer(output.buffer())
+        .with_case_insensitive_keywords(true)
+        .with_raw_identifiers(true);
+    assert!(lox.run(source).is_ok());
+
+    // Imported modules are read the same way.
+    let dir = TempDir::new("lox-scanner-options");
+    fs::write(dir.0.join("module.lox"), "EXPORT VAR \\for = 2;").unwrap();
+    let main = dir.0.join("main.lox");
+    fs::write(&main, "IMPORT \"module.lox\" as m; Print(m.\\for);").unwrap();
+    assert!(lox.run_file(main).is_ok());
+    assert_eq!(output.into_string(), "1\n2\n");
+
+    assert!(matches!(
+        Lox::new().run("VAR x = 1;"),
+        Err(LoxError::Parsing(_))
+    ));
+}
+
+#[test]
+fn test_run_input() {
+    let mut lox = Lox::new().with_writer(Rc::new(RefCell::new(io::sink())));
+    assert_eq!(lox.run_input("var a = 1;", 1).ok().unwrap(), None);
+    assert_eq!(
+        lox.run_input("a + 1", 2).ok().unwrap(),
+        Some(Object::Integer(2))
+    );
+    // Later inputs can declare a name again, though not twice in one scope.
+    assert!(lox.run_input("var a = \"one\"; a", 3).is_ok());
+    match lox.run_input("{ var b = 1; var b = 2; }", 4) {
+        Err(error @ LoxError::Resolving(_)) => assert_eq!(
+            error.to_string(),
+            "[line 4:18] Runtime error at 'b': Already a variable with this name in this scope."
+        ),
+        _ => panic!("Expected a resolving error"),
+    }
+    assert!(matches!(
+        lox.run_input("var c = ;", 5),
+        Err(LoxError::Parsing(_))
+    ));
+    assert_eq!(
+        lox.run_input("a", 6).ok().unwrap().unwrap().to_string(),
+        "one"
+    );
+}
+
+#[test]
+fn test_io_natives() {
+    let dir = TempDir::new("rlox-io");
+    let input = io::Cursor::new("Ada\nLovelace");
+    let (interpreter, output) = Interpreter::with_capture();
+    let mut interpreter = interpreter
+        .with_reader(Rc::new(RefCell::new(input)))
+        .with_module_dir(&dir.0);
+
+    let source = "
+        var first = readLine();
+        var last = readLine();
+        writeFile(\"name.txt\", first + \" \" + last);
+        print(readFile(\"name.txt\"));
+        print(readLine());
+    ";
+    assert!(run_timed(source, &mut interpreter).0.is_ok());
+    assert_eq!(output.into_string(), "Ada Lovelace\nnil\n");
+    assert_eq!(
+        fs::read_to_string(dir.0.join("name.txt")).unwrap(),
+        "Ada Lovelace"
+    );
+}
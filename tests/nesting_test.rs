@@ -0,0 +1,58 @@
+//! Runs `rlox` on deeply nested source, which should either run or be rejected with a parsing
+//! error, but never overflow the stack.
+
+use std::{
+    io::Write,
+    process::{Command, Output, Stdio},
+};
+
+/// Runs `rlox` with `args`, piping `input` to its stdin.
+fn rlox(args: &[&str], input: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rlox"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+/// `1` in `depth` nested parentheses.
+fn parenthesized(depth: usize) -> String {
+    format!("{}1{}", "(".repeat(depth), ")".repeat(depth))
+}
+
+#[test]
+fn test_nesting_below_the_limit_runs() {
+    let code = format!("[{}] == [{}]", parenthesized(60), parenthesized(60));
+    let output = rlox(&["-e", &code], "");
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "true\n");
+
+    let code = format!("{{{}}} print(1);", "{".repeat(100) + &"}".repeat(100));
+    let output = rlox(&["-e", &code], "");
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "1\n");
+}
+
+#[test]
+fn test_nesting_past_the_limit_is_rejected() {
+    let code = parenthesized(10_000);
+    let output = rlox(&["-e", &code], "");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Too much nesting."));
+
+    let output = rlox(&["--check", "-"], &format!("print({code});"));
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "[line 1:134] Parsing error at '(': Too much nesting.\n"
+    );
+}
@@ -0,0 +1,61 @@
+//! Runs `rlox` with the scanner options, which are off by default.
+
+use std::{
+    io::Write,
+    process::{Command, Output, Stdio},
+};
+
+/// Runs `rlox` with `args`, piping `input` to its stdin.
+fn rlox(args: &[&str], input: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rlox"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn test_case_insensitive_keywords() {
+    let code = "VAR x = 1; If (x == 1) Print(x);";
+    let output = rlox(&["--case-insensitive-keywords", "-e", code], "");
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "1\n");
+
+    let output = rlox(&["-e", code], "");
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn test_raw_identifiers() {
+    let output = rlox(
+        &["--raw-identifiers", "-e", "var \\class = 2; \\class * 3"],
+        "",
+    );
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "6\n");
+
+    let output = rlox(&["--raw-identifiers", "--tokens", "-"], "\\class;");
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "1:1 Identifier \"\\\\class\"\n1:7 Semicolon \";\"\n1:8 Eof \"\"\n"
+    );
+}
+
+#[test]
+fn test_scripts_read_with_the_options() {
+    let output = rlox(
+        &["--case-insensitive-keywords", "--raw-identifiers", "-"],
+        "CLASS A { \\print() { RETURN \"raw\"; } }\nprint(A().\\print());\n",
+    );
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "raw\n");
+}